@@ -0,0 +1,239 @@
+use crate::config::password::PasswordPolicy;
+use crate::config::signing::SigningKey;
+use crate::config::token::JwtConfig;
+use crate::config::totp::TotpConfig;
+use crate::handlers::rate_limit::RateLimitConfig;
+use crate::repository::DatabaseConfig;
+use crate::services::lockout_service::LockoutConfig;
+use crate::services::mail_service::{MailConfig, MailTransport};
+use crate::services::oauth_service::OAuthConfig;
+use crate::utils::file_util::StorageConfig;
+use crate::utils::mongo_util::MongoConfig;
+use dotenv::dotenv;
+use rocket::fairing::AdHoc;
+use rocket::figment::providers::Env;
+use rocket::figment::Figment;
+use rocket::Config;
+use serde::Deserialize;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Arc;
+
+/// `jwt.secret` as shipped in Rocket.toml, refused in release builds
+const DEFAULT_JWT_SECRET: &str = "my-jwt-secret-to-change-in-prod";
+
+/// Origins allowed to call the API from a browser.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CorsConfig {
+    /// Exact origins such as `https://app.example.com`, or `*` for any
+    pub allowed_origins: Vec<String>,
+}
+
+/// Whether a browser origin may call the API, and with which credentials.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AllowedOrigin<'a> {
+    /// Listed explicitly, so cookies may be sent along
+    Exact(&'a str),
+    /// Only allowed through `*`, which never covers credentials
+    Any,
+}
+
+impl CorsConfig {
+    pub fn allow_origin<'a>(&self, origin: Option<&'a str>) -> Option<AllowedOrigin<'a>> {
+        let origin = origin?;
+        if self.allowed_origins.iter().any(|allowed| allowed == origin) {
+            Some(AllowedOrigin::Exact(origin))
+        } else if self.allowed_origins.iter().any(|allowed| allowed == "*") {
+            Some(AllowedOrigin::Any)
+        } else {
+            None
+        }
+    }
+}
+
+/// Settings of the whole service, extracted from the Rocket figment.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AppConfig {
+    /// Address clients reach the service at, used to build links to it
+    pub public_base_url: String,
+    /// Reverse proxies whose `X-Real-IP` header names the client. Requests
+    /// from anywhere else are attributed to the address they come from.
+    pub trusted_proxies: Vec<IpAddr>,
+    pub database: DatabaseConfig,
+    pub mongo: MongoConfig,
+    pub password: PasswordPolicy,
+    pub jwt: JwtConfig,
+    pub totp: TotpConfig,
+    pub lockout: LockoutConfig,
+    pub mail: MailConfig,
+    pub storage: StorageConfig,
+    pub cors: CorsConfig,
+    pub rate_limit: RateLimitConfig,
+    pub oauth: OAuthConfig,
+}
+
+impl AppConfig {
+    /// The Rocket figment: `Rocket.toml` for the selected profile and
+    /// `ROCKET_` variables, plus the `MONGO_URI` variable that is also read
+    /// from `.env`.
+    pub fn figment() -> Figment {
+        dotenv().ok();
+        Config::figment().merge(
+            Env::raw()
+                .only(&["MONGO_URI"])
+                .map(|_| "mongo.uri".into())
+                .global(),
+        )
+    }
+
+    /// Extracts and validates the config on ignition, managing it as
+    /// `State<AppConfig>`. Launch is aborted with every problem found.
+    pub fn fairing() -> AdHoc {
+        AdHoc::try_on_ignite("Application configuration", |rocket| async move {
+            let mut config = match rocket.figment().extract::<AppConfig>() {
+                Ok(config) => config,
+                Err(errors) => {
+                    for error in errors {
+                        log::error!("Invalid configuration: {}", error);
+                    }
+                    return Err(rocket);
+                }
+            };
+            if let Err(problems) = config.validate() {
+                for problem in problems {
+                    log::error!("Invalid configuration: {}", problem);
+                }
+                return Err(rocket);
+            }
+            match SigningKey::load(&config.jwt.signing) {
+                Ok(key) => config.jwt.signing_key = Some(Arc::new(key)),
+                Err(problem) => {
+                    log::error!("Invalid configuration: {}", problem);
+                    return Err(rocket);
+                }
+            }
+            Ok(rocket.manage(config))
+        })
+    }
+
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut problems = vec![];
+        let mut check = |valid: bool, problem: &str| {
+            if !valid {
+                problems.push(problem.to_owned());
+            }
+        };
+
+        check(
+            is_http_url(&self.public_base_url) && !self.public_base_url.ends_with('/'),
+            "public_base_url must be an http(s) url without a trailing slash",
+        );
+        check(
+            self.mongo.uri.starts_with("mongodb://") || self.mongo.uri.starts_with("mongodb+srv://"),
+            "mongo.uri must be a mongodb:// or mongodb+srv:// connection string",
+        );
+        check(!self.mongo.database.is_empty(), "mongo.database must not be empty");
+        check(self.mongo.max_pool_size > 0, "mongo.max_pool_size must be positive");
+        check(
+            self.mongo.min_pool_size <= self.mongo.max_pool_size,
+            "mongo.min_pool_size must not exceed mongo.max_pool_size",
+        );
+        check(self.password.min_length > 0, "password.min_length must be positive");
+        check(
+            self.password.max_length >= self.password.min_length,
+            "password.max_length must not be less than password.min_length",
+        );
+        check(
+            self.password
+                .breached_passwords
+                .as_ref()
+                .is_none_or(|directory| Path::new(directory).is_dir()),
+            "password.breached_passwords must be an existing directory",
+        );
+        check(!self.jwt.secret.is_empty(), "jwt.secret must not be empty");
+        // The shipped secret is only good enough for trying the service out
+        check(
+            cfg!(debug_assertions)
+                || (self.jwt.secret.len() >= 32 && self.jwt.secret != DEFAULT_JWT_SECRET),
+            "jwt.secret must be changed from the default and be at least 32 bytes long",
+        );
+        check(
+            is_http_url(&self.jwt.issuer) && !self.jwt.issuer.ends_with('/'),
+            "jwt.issuer must be an http(s) url without a trailing slash, as OpenID Connect requires",
+        );
+        check(
+            self.jwt.access_token_lifetime > 0
+                && self.jwt.refresh_token_lifetime > 0
+                && self.jwt.verification_token_lifetime > 0
+                && self.jwt.reset_token_lifetime > 0
+                && self.jwt.mfa_challenge_lifetime > 0,
+            "jwt token lifetimes must be positive",
+        );
+        check(!self.totp.issuer.is_empty(), "totp.issuer must not be empty");
+        check(
+            (0..=10).contains(&self.totp.skew),
+            "totp.skew must be between 0 and 10",
+        );
+        check(
+            (1..=50).contains(&self.totp.recovery_codes),
+            "totp.recovery_codes must be between 1 and 50",
+        );
+        check(
+            self.lockout.free_failures >= 0
+                && self.lockout.backoff_base > 0
+                && self.lockout.backoff_max >= self.lockout.backoff_base,
+            "lockout.backoff_base must be positive and not exceed lockout.backoff_max",
+        );
+        check(
+            self.lockout.max_account_failures > 0 && self.lockout.max_ip_failures > 0,
+            "lockout failure limits must be positive",
+        );
+        check(
+            self.lockout.lockout_duration > 0
+                && self.lockout.lockout_duration <= self.lockout.failure_window,
+            "lockout.lockout_duration must be positive and not exceed lockout.failure_window",
+        );
+        check(
+            is_http_url(&self.mail.verification_url) && is_http_url(&self.mail.reset_url),
+            "mail.verification_url and mail.reset_url must be http(s) urls",
+        );
+        check(
+            !matches!(self.mail.transport, MailTransport::Smtp) || self.mail.smtp_host.is_some(),
+            "mail.smtp_host must be set for the smtp transport",
+        );
+        check(!self.storage.directory.is_empty(), "storage.directory must not be empty");
+        check(
+            self.storage.max_upload_size > 0 && self.storage.max_avatar_size > 0,
+            "storage size limits must be positive",
+        );
+        check(
+            self.cors
+                .allowed_origins
+                .iter()
+                .all(|origin| origin == "*" || (is_http_url(origin) && !origin.ends_with('/'))),
+            "cors.allowed_origins must be `*` or http(s) origins without a trailing slash",
+        );
+
+        check(self.rate_limit.max_buckets > 0, "rate_limit.max_buckets must be positive");
+        check(
+            self.rate_limit.groups.iter().all(|group| {
+                group.prefix.starts_with('/') && group.limit > 0 && group.period > 0
+            }),
+            "rate_limit.groups need a prefix starting with `/` and a positive limit and period",
+        );
+        check(
+            (1..=600).contains(&self.oauth.authorization_code_lifetime),
+            "oauth.authorization_code_lifetime must be between 1 and 600 seconds",
+        );
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
+    }
+}
+
+fn is_http_url(url: &str) -> bool {
+    (url.starts_with("http://") || url.starts_with("https://")) && validator::validate_url(url)
+}
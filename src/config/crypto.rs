@@ -1,36 +1,44 @@
 use argon2::{self, Config};
-use dotenv::dotenv;
 use color_eyre::Result;
 use eyre::eyre;
-use futures::compat::Future01CompatExt;
-use std::sync::Arc;
+use rand::RngCore;
+use std::sync::OnceLock;
 use tracing::instrument;
 
-#[derive(Debug, Clone)]
-pub struct CryptoService {
-    pub key: String,
-}
+#[derive(Debug, Clone, Default)]
+pub struct CryptoService;
 
 impl CryptoService {
     pub fn new() -> Self {
-        dotenv().ok();
-        let key_from_env: String = std::env::var("SECRET_KEY")
-            .expect("Secret key for hashing not set")
-            .into();
-        Self { key: key_from_env }
+        Self
     }
 
+    /// Hashes a password, or a short, low-entropy secret like a recovery
+    /// code, with a salt of its own, so that equal secrets do not get equal
+    /// hashes.
     #[instrument(skip(self, password))]
     pub async fn hash_password(&self, password: String) -> Result<String> {
-        let config = Config::default();
-        argon2::hash_encoded(&password.as_bytes(), &self.key.as_bytes(), &config)
+        let mut salt = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut salt);
+        argon2::hash_encoded(password.as_bytes(), &salt, &Config::default())
             .map_err(|err| eyre!("Hashing error: {:?}", err))
     }
 
+    /// Hash of no one's password, to verify against when there is no
+    /// account, so that the time taken does not tell whether there is one.
+    pub fn dummy_hash(&self) -> &'static str {
+        static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+        DUMMY_HASH.get_or_init(|| {
+            argon2::hash_encoded(b"no-password", b"no-account", &Config::default())
+                .expect("hashing a fixed password")
+        })
+    }
+
+    /// Checks `password` against a hash from `hash_password`, or one from
+    /// before salts were random, which used the former `SECRET_KEY` instead.
     #[instrument(skip(self, password, password_hash))]
     pub async fn verify_password(&self, password: String, password_hash: String) -> Result<bool> {
-        let config = Config::default();
-        argon2::verify_encoded(&password_hash, &password.as_bytes())
+        argon2::verify_encoded(&password_hash, password.as_bytes())
             .map_err(|err| eyre!("Verification Error: {:?}", err))
     }
 }
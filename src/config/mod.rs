@@ -1 +1,6 @@
-pub mod crypto;
\ No newline at end of file
+pub mod app;
+pub mod crypto;
+pub mod password;
+pub mod signing;
+pub mod token;
+pub mod totp;
@@ -0,0 +1,115 @@
+use serde::Deserialize;
+use sha1::{Digest, Sha1};
+use std::borrow::Cow;
+use std::io;
+use std::path::Path;
+use validator::ValidationError;
+
+/// Rules new passwords have to follow.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    /// Upper bound on the password length, which bounds the cost of hashing
+    pub max_length: usize,
+    pub require_lowercase: bool,
+    pub require_uppercase: bool,
+    pub require_digit: bool,
+    /// Require a character that is neither a letter nor a digit
+    pub require_symbol: bool,
+    /// Reject passwords containing the user's name or email
+    pub reject_personal_info: bool,
+    /// Directory of Pwned Passwords range files, named after the first five
+    /// hex digits of the SHA-1 hash (`21BD1.txt`) and listing the remaining
+    /// digits as `SUFFIX:COUNT` lines. Listed passwords are rejected.
+    pub breached_passwords: Option<String>,
+}
+
+impl PasswordPolicy {
+    /// Every rule `password` breaks. `personal_info` holds the name and email
+    /// of the user the password is for.
+    pub fn check(&self, password: &str, personal_info: &[&str]) -> Vec<ValidationError> {
+        let mut failures = vec![];
+        let mut rule = |passes: bool, code: &'static str, message: String| {
+            if !passes {
+                let mut error = ValidationError::new(code);
+                error.message = Some(Cow::Owned(message));
+                failures.push(error);
+            }
+        };
+
+        let length = password.chars().count();
+        rule(
+            length >= self.min_length,
+            "password_too_short",
+            format!("must be at least {} characters long", self.min_length),
+        );
+        rule(
+            length <= self.max_length,
+            "password_too_long",
+            format!("must be at most {} characters long", self.max_length),
+        );
+        rule(
+            !self.require_lowercase || password.chars().any(char::is_lowercase),
+            "password_lowercase",
+            "must contain a lowercase letter".to_owned(),
+        );
+        rule(
+            !self.require_uppercase || password.chars().any(char::is_uppercase),
+            "password_uppercase",
+            "must contain an uppercase letter".to_owned(),
+        );
+        rule(
+            !self.require_digit || password.chars().any(|c| c.is_ascii_digit()),
+            "password_digit",
+            "must contain a digit".to_owned(),
+        );
+        rule(
+            !self.require_symbol || password.chars().any(|c| !c.is_alphanumeric()),
+            "password_symbol",
+            "must contain a symbol".to_owned(),
+        );
+        rule(
+            !self.reject_personal_info || !contains_personal_info(password, personal_info),
+            "password_personal_info",
+            "must not contain your name or email".to_owned(),
+        );
+        failures
+    }
+
+    /// Whether `password` is listed in the breached password files. Without
+    /// a configured directory nothing is listed.
+    pub async fn is_breached(&self, password: &str) -> io::Result<bool> {
+        let directory = match &self.breached_passwords {
+            Some(directory) => directory,
+            None => return Ok(false),
+        };
+        let hash = format!("{:X}", Sha1::digest(password.as_bytes()));
+        let (prefix, suffix) = hash.split_at(5);
+
+        let path = Path::new(directory).join(format!("{}.txt", prefix));
+        let range = match async_std::fs::read_to_string(&path).await {
+            Ok(range) => range,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(err) => return Err(err),
+        };
+        // Padding entries with a count of 0 are not real passwords
+        Ok(range.lines().filter_map(|line| line.split_once(':')).any(|(listed, count)| {
+            listed.eq_ignore_ascii_case(suffix) && count.trim() != "0"
+        }))
+    }
+}
+
+fn contains_personal_info(password: &str, personal_info: &[&str]) -> bool {
+    let password = password.to_lowercase();
+    personal_info
+        .iter()
+        // Emails are matched on the part before the domain as well
+        .flat_map(|info| {
+            let local_part = info.split('@').next().filter(|part| part.len() < info.len());
+            std::iter::once(*info).chain(local_part)
+        })
+        .map(str::to_lowercase)
+        // Very short values such as initials would reject too much
+        .filter(|info| info.chars().count() >= 3)
+        .any(|info| password.contains(&info))
+}
@@ -0,0 +1,222 @@
+use crate::config::signing::{SigningConfig, SigningKey};
+use crate::handlers::error::AuthenticationError;
+use crate::models::oauth::Scope;
+use crate::models::token::{ActionClaims, Claims, IdClaims, TokenPurpose};
+use crate::models::user::User;
+use chrono::Utc;
+use mongodb::bson;
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use rand::RngCore;
+use serde::Deserialize;
+use serde_json::Value;
+use std::sync::Arc;
+
+/// JWS `typ` of access tokens, RFC 9068
+pub const ACCESS_TOKEN_TYPE: &str = "at+jwt";
+/// JWS `typ` of OpenID Connect ID tokens
+pub const ID_TOKEN_TYPE: &str = "JWT";
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct JwtConfig {
+    /// Key of the single-purpose tokens this service hands out and checks
+    /// itself
+    pub secret: String,
+    /// Also the OpenID Connect issuer identifier
+    pub issuer: String,
+    pub signing: SigningConfig,
+    /// Loaded from `signing` when the configuration is
+    #[serde(skip)]
+    pub signing_key: Option<Arc<SigningKey>>,
+    pub access_token_lifetime: i64,
+    pub refresh_token_lifetime: i64,
+    pub verification_token_lifetime: i64,
+    pub reset_token_lifetime: i64,
+    pub mfa_challenge_lifetime: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct TokenService {
+    pub config: JwtConfig,
+}
+
+impl TokenService {
+    pub fn new(config: &JwtConfig) -> Self {
+        Self {
+            config: config.clone(),
+        }
+    }
+
+    pub fn issue_access_token(
+        &self,
+        user: &User,
+        family_id: &str,
+    ) -> Result<String, AuthenticationError> {
+        self.encode_access_token(user, family_id, None)
+    }
+
+    /// An access token for an OAuth client, limited to `scopes`.
+    pub fn issue_client_access_token(
+        &self,
+        user: &User,
+        client_id: &str,
+        scopes: &[Scope],
+    ) -> Result<String, AuthenticationError> {
+        // Client tokens are not refreshed, each grant is its own family
+        let family_id = bson::oid::ObjectId::new().to_hex();
+        self.encode_access_token(user, &family_id, Some((client_id, scopes)))
+    }
+
+    fn encode_access_token(
+        &self,
+        user: &User,
+        family_id: &str,
+        client: Option<(&str, &[Scope])>,
+    ) -> Result<String, AuthenticationError> {
+        let user_id = user
+            .user_id
+            .as_ref()
+            .ok_or_else(|| AuthenticationError::TokenError("User has no id".to_owned()))?;
+        let now = Utc::now().timestamp();
+        let claims = Claims {
+            sub: user_id.to_hex(),
+            exp: now + self.config.access_token_lifetime,
+            iat: now,
+            iss: self.config.issuer.clone(),
+            sid: family_id.to_owned(),
+            ver: user.token_version,
+            user_type: user.user_type.clone(),
+            user_tags: user.user_tags.clone(),
+            scope: client.map(|(_, scopes)| Scope::join(scopes)),
+            client_id: client.map(|(client_id, _)| client_id.to_owned()),
+        };
+
+        self.signing_key()?.sign(ACCESS_TOKEN_TYPE, &claims)
+    }
+
+    pub fn verify_access_token(&self, token: &str) -> Result<Claims, AuthenticationError> {
+        let claims: Value = self.signing_key()?.verify(ACCESS_TOKEN_TYPE, token)?;
+        // Tokens meant for an audience, like ID tokens, are not for us
+        if claims.get("aud").is_some() {
+            return Err(AuthenticationError::TokenError("Token is meant for someone else".to_owned()));
+        }
+        let claims: Claims = serde_json::from_value(claims)
+            .map_err(|_| AuthenticationError::TokenError("Invalid token".to_owned()))?;
+        if claims.iss != self.config.issuer {
+            return Err(AuthenticationError::TokenError("Token was issued by someone else".to_owned()));
+        }
+        if claims.exp <= Utc::now().timestamp() {
+            return Err(AuthenticationError::TokenError("Token has expired".to_owned()));
+        }
+        Ok(claims)
+    }
+
+    /// An OpenID Connect ID token telling `client_id` who signed in.
+    pub fn issue_id_token(
+        &self,
+        user: &User,
+        client_id: &str,
+        nonce: Option<String>,
+    ) -> Result<String, AuthenticationError> {
+        let user_id = user
+            .user_id
+            .as_ref()
+            .ok_or_else(|| AuthenticationError::TokenError("User has no id".to_owned()))?;
+        let now = Utc::now().timestamp();
+        let claims = IdClaims {
+            iss: self.config.issuer.clone(),
+            sub: user_id.to_hex(),
+            aud: client_id.to_owned(),
+            exp: now + self.config.access_token_lifetime,
+            iat: now,
+            nonce,
+            name: format!("{} {}", user.first_name, user.last_name),
+            email: user.email_id.clone(),
+            email_verified: user.email_verified,
+        };
+        self.signing_key()?.sign(ID_TOKEN_TYPE, &claims)
+    }
+
+    pub fn signing_key(&self) -> Result<&SigningKey, AuthenticationError> {
+        self.config
+            .signing_key
+            .as_deref()
+            .ok_or_else(|| AuthenticationError::SigningError("No signing key loaded".to_owned()))
+    }
+
+    pub fn issue_action_token(
+        &self,
+        user: &User,
+        purpose: TokenPurpose,
+        lifetime: i64,
+    ) -> Result<String, AuthenticationError> {
+        let user_id = user
+            .user_id
+            .as_ref()
+            .ok_or_else(|| AuthenticationError::TokenError("User has no id".to_owned()))?;
+        let now = Utc::now().timestamp();
+        let claims = ActionClaims {
+            sub: user_id.to_hex(),
+            exp: now + lifetime,
+            iat: now,
+            iss: self.config.issuer.clone(),
+            pwd: match purpose {
+                TokenPurpose::ResetPassword | TokenPurpose::MfaChallenge => {
+                    user.password.as_deref().map(Self::fingerprint)
+                }
+                _ => None,
+            },
+            purpose,
+        };
+
+        encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(self.config.secret.as_bytes()),
+        )
+        .map_err(|err| AuthenticationError::SigningError(err.to_string()))
+    }
+
+    pub fn verify_action_token(
+        &self,
+        token: &str,
+        purpose: TokenPurpose,
+    ) -> Result<ActionClaims, AuthenticationError> {
+        let validation = Validation {
+            iss: Some(self.config.issuer.clone()),
+            ..Validation::default()
+        };
+        let claims = decode::<ActionClaims>(
+            token,
+            &DecodingKey::from_secret(self.config.secret.as_bytes()),
+            &validation,
+        )
+        .map(|data| data.claims)
+        .map_err(|err| AuthenticationError::TokenError(err.to_string()))?;
+
+        if claims.purpose != purpose {
+            return Err(AuthenticationError::TokenError(
+                "Token was issued for a different purpose".to_owned(),
+            ));
+        }
+        Ok(claims)
+    }
+
+    /// Generates a new opaque refresh token, returning it along with the hash
+    /// that gets persisted.
+    pub fn generate_refresh_token(&self) -> (String, String) {
+        let mut bytes = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let token = base64::encode_config(bytes, base64::URL_SAFE_NO_PAD);
+        let token_hash = Self::hash_refresh_token(&token);
+        (token, token_hash)
+    }
+
+    pub fn hash_refresh_token(token: &str) -> String {
+        blake3::hash(token.as_bytes()).to_hex().to_string()
+    }
+
+    /// Short, non-reversible digest of a password hash to embed in tokens.
+    pub fn fingerprint(password_hash: &str) -> String {
+        blake3::hash(password_hash.as_bytes()).to_hex()[..16].to_owned()
+    }
+}
@@ -0,0 +1,170 @@
+use rand::{Rng, RngCore};
+use ring::hmac;
+use rocket::http::RawStr;
+use serde::Deserialize;
+
+/// Digits of a code, as most authenticator apps expect.
+pub const TOTP_DIGITS: u32 = 6;
+
+/// Seconds each code is valid for.
+pub const TOTP_PERIOD: i64 = 30;
+
+/// Bytes of a generated secret, the length RFC 4226 recommends.
+const SECRET_LENGTH: usize = 20;
+
+/// Characters of a recovery code, not counting the separator.
+const RECOVERY_CODE_LENGTH: usize = 10;
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TotpConfig {
+    /// Name authenticator apps list the account under
+    pub issuer: String,
+    /// Periods before and after the current one whose codes are still
+    /// accepted, to allow for clock drift
+    pub skew: i64,
+    /// One-time recovery codes handed out when two-factor authentication is
+    /// enabled
+    pub recovery_codes: usize,
+}
+
+/// RFC 6238 time-based one-time passwords, using HMAC-SHA1.
+#[derive(Debug, Clone)]
+pub struct TotpService {
+    pub config: TotpConfig,
+}
+
+impl TotpService {
+    pub fn new(config: &TotpConfig) -> Self {
+        Self {
+            config: config.clone(),
+        }
+    }
+
+    /// A new random secret, base32 encoded.
+    pub fn generate_secret() -> String {
+        let mut bytes = [0u8; SECRET_LENGTH];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        base32_encode(&bytes)
+    }
+
+    /// A new random recovery code such as `k7rqm-2xw4p`.
+    pub fn generate_recovery_code() -> String {
+        let mut rng = rand::thread_rng();
+        let mut code: String = (0..RECOVERY_CODE_LENGTH)
+            .map(|_| BASE32_ALPHABET[rng.gen_range(0..32)].to_ascii_lowercase() as char)
+            .collect();
+        code.insert(RECOVERY_CODE_LENGTH / 2, '-');
+        code
+    }
+
+    /// `code` as generated by `generate_recovery_code`, if it has the shape
+    /// of a recovery code once case, spaces and separators are ignored.
+    pub fn normalize_recovery_code(code: &str) -> Option<String> {
+        let code: String = code
+            .chars()
+            .filter(|c| !matches!(c, ' ' | '-'))
+            .map(|c| c.to_ascii_lowercase())
+            .collect();
+        let valid = code.len() == RECOVERY_CODE_LENGTH
+            && code.bytes().all(|byte| BASE32_ALPHABET.contains(&byte.to_ascii_uppercase()));
+        if !valid {
+            return None;
+        }
+        let (first, second) = code.split_at(RECOVERY_CODE_LENGTH / 2);
+        Some(format!("{}-{}", first, second))
+    }
+
+    /// The `otpauth://` URI authenticator apps enrol `secret` from, usually
+    /// shown as a QR code.
+    pub fn provisioning_uri(&self, secret: &str, account: &str) -> String {
+        let issuer = RawStr::new(&self.config.issuer).percent_encode();
+        format!(
+            "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+            issuer,
+            RawStr::new(account).percent_encode(),
+            secret,
+            issuer,
+            TOTP_DIGITS,
+            TOTP_PERIOD
+        )
+    }
+
+    /// The code for `secret` at unix time `time`, `None` if the secret is
+    /// not valid base32.
+    pub fn code(secret: &str, time: i64) -> Option<String> {
+        let key = base32_decode(secret)?;
+        Some(Self::code_for_step(&key, time.div_euclid(TOTP_PERIOD)))
+    }
+
+    /// The time step `code` is valid for at unix time `time`. Steps up to
+    /// `last_step` are skipped, so that a code cannot be used twice.
+    pub fn verify(&self, secret: &str, code: &str, time: i64, last_step: Option<i64>) -> Option<i64> {
+        let current = time.div_euclid(TOTP_PERIOD);
+        (current - self.config.skew..=current + self.config.skew)
+            .filter(|step| last_step.is_none_or(|last_step| *step > last_step))
+            .find(|step| {
+                Self::code(secret, step * TOTP_PERIOD).is_some_and(|expected| {
+                    ring::constant_time::verify_slices_are_equal(expected.as_bytes(), code.as_bytes())
+                        .is_ok()
+                })
+            })
+    }
+
+    fn code_for_step(key: &[u8], step: i64) -> String {
+        let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, key);
+        let digest = hmac::sign(&key, &step.to_be_bytes());
+        let digest = digest.as_ref();
+
+        // Dynamic truncation, RFC 4226 section 5.3
+        let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+        let binary = u32::from_be_bytes([
+            digest[offset] & 0x7f,
+            digest[offset + 1],
+            digest[offset + 2],
+            digest[offset + 3],
+        ]);
+        format!(
+            "{:0width$}",
+            binary % 10_u32.pow(TOTP_DIGITS),
+            width = TOTP_DIGITS as usize
+        )
+    }
+}
+
+/// RFC 4648 base32 without padding, the form secrets are shown in.
+fn base32_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(5) * 8);
+    let (mut buffer, mut bits) = (0u32, 0);
+    for byte in bytes {
+        buffer = (buffer << 8) | *byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    encoded
+}
+
+/// Decodes base32, ignoring case, spaces and padding as users may type them.
+fn base32_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(encoded.len() * 5 / 8);
+    let (mut buffer, mut bits) = (0u32, 0);
+    for c in encoded.chars().filter(|c| *c != ' ' && *c != '=') {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|letter| *letter as char == c.to_ascii_uppercase())?;
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+    (!bytes.is_empty()).then_some(bytes)
+}
@@ -0,0 +1,104 @@
+use crate::handlers::body::JsonBody;
+use crate::handlers::error::AuthenticationError;
+use crate::handlers::guard::{AuthenticatedUser, RequireRole};
+use crate::config::app::AppConfig;
+use crate::config::token::TokenService;
+use crate::models::audit::{AuditExportQuery, AuditQuery};
+use crate::models::oauth::RegisterClient;
+use crate::models::role::{Admin, Permission};
+use crate::models::user::UserListQuery;
+use crate::repository::audit_repository::AuditRepository;
+use crate::repository::login_attempt_repository::LoginAttemptRepository;
+use crate::repository::oauth_repository::OAuthRepository;
+use crate::repository::user_repository::UserRepository;
+use crate::services::audit_service::AuditService;
+use crate::services::oauth_service::OAuthService;
+use crate::services::user_service::UserService;
+use rocket::{http::Status, response::status, State};
+use serde_json::{json, Value};
+
+#[get("/users?<query..>")]
+pub async fn list_users(
+    _admin: RequireRole<Admin>,
+    query: UserListQuery,
+    users: &State<Box<dyn UserRepository>>,
+) -> Result<status::Custom<Value>, AuthenticationError> {
+    let page = UserService::list_users(users.as_ref(), query).await?;
+    let message = json!({"success": true, "message": "Users", "data": page});
+    Ok(status::Custom(Status::Ok, message))
+}
+
+#[post("/users/<user_id>/unlock")]
+pub async fn unlock_user(
+    auth: AuthenticatedUser,
+    user_id: &str,
+    users: &State<Box<dyn UserRepository>>,
+    attempts: &State<Box<dyn LoginAttemptRepository>>,
+) -> Result<status::Custom<Value>, AuthenticationError> {
+    auth.require(Permission::UnlockAccounts)?;
+    let unlocked = UserService::unlock(users.as_ref(), attempts.as_ref(), user_id).await?;
+    let message = json!({"success": true, "message": "User Unlocked", "data": {"unlocked": unlocked}});
+    Ok(status::Custom(Status::Ok, message))
+}
+
+#[get("/audit?<query..>")]
+pub async fn list_audit_events(
+    _admin: RequireRole<Admin>,
+    query: AuditQuery,
+    audit: &State<Box<dyn AuditRepository>>,
+) -> Result<status::Custom<Value>, AuthenticationError> {
+    let page = AuditService::list(audit.as_ref(), query).await?;
+    let message = json!({"success": true, "message": "Audit Events", "data": page});
+    Ok(status::Custom(Status::Ok, message))
+}
+
+#[get("/audit/verify")]
+pub async fn verify_audit_log(
+    _admin: RequireRole<Admin>,
+    audit: &State<Box<dyn AuditRepository>>,
+) -> Result<status::Custom<Value>, AuthenticationError> {
+    let verification = AuditService::verify(audit.as_ref()).await?;
+    let message = json!({"success": true, "message": "Audit Log Verified", "data": verification});
+    Ok(status::Custom(Status::Ok, message))
+}
+
+#[get("/audit/export?<query..>")]
+pub async fn export_audit_log(
+    _admin: RequireRole<Admin>,
+    query: AuditExportQuery,
+    audit: &State<Box<dyn AuditRepository>>,
+    config: &State<AppConfig>,
+) -> Result<status::Custom<Value>, AuthenticationError> {
+    let tokens = TokenService::new(&config.jwt);
+    let export = AuditService::export(audit.as_ref(), tokens.signing_key()?, query).await?;
+    let message = json!({"success": true, "message": "Audit Log Export", "data": export});
+    Ok(status::Custom(Status::Ok, message))
+}
+
+/// Registers an OAuth client. The secret of a confidential client is only
+/// returned here.
+#[post("/oauth/clients", data = "<client>")]
+pub async fn register_oauth_client(
+    _admin: RequireRole<Admin>,
+    client: JsonBody<RegisterClient>,
+    oauth: &State<Box<dyn OAuthRepository>>,
+) -> Result<status::Custom<Value>, AuthenticationError> {
+    let (client, client_secret) =
+        OAuthService::register_client(oauth.as_ref(), client.into_inner()).await?;
+    let message = json!({
+        "success": true,
+        "message": "OAuth Client Registered",
+        "data": {"client": client, "client_secret": client_secret},
+    });
+    Ok(status::Custom(Status::Created, message))
+}
+
+#[get("/oauth/clients")]
+pub async fn list_oauth_clients(
+    _admin: RequireRole<Admin>,
+    oauth: &State<Box<dyn OAuthRepository>>,
+) -> Result<status::Custom<Value>, AuthenticationError> {
+    let clients = OAuthService::list_clients(oauth.as_ref()).await?;
+    let message = json!({"success": true, "message": "OAuth Clients", "data": clients});
+    Ok(status::Custom(Status::Ok, message))
+}
@@ -1,42 +1,43 @@
 use rocket::data::Data;
-use rocket::form::FromForm;
-use rocket::fs::TempFile;
 use rocket::http::{ContentType, Status};
 use rocket::response::status;
+use rocket::State;
 
 use rocket_download_response::DownloadResponse;
-use rocket_multipart_form_data::{
-    MultipartFormData, MultipartFormDataField, MultipartFormDataOptions,
-};
 
-use async_std::io::prelude::*;
 use serde_json::{json, Value};
 use std::{io::ErrorKind, time};
 
-use crate::utils::file_util;
+use crate::config::app::AppConfig;
+use crate::handlers::error::TransmissionError;
+use crate::handlers::guard::{AuthenticatedUser, ClientInfo};
+use crate::models::audit::AuditEventKind;
+use crate::models::role::Permission;
+use crate::repository::audit_repository::AuditRepository;
+use crate::services::audit_service::AuditService;
+use crate::utils::file_util::FileUtil;
 use crate::services::file_service::MultipartHandler;
 
-#[derive(Debug, FromForm)]
-pub struct UploadForm<'a> {
-    somefile: TempFile<'a>,
-}
-
+/// Stores a file for the signed-in user. Uploaded files are private to
+/// the user who uploaded them.
 #[post("/", data = "<form_data>")]
 pub async fn upload_file(
+    auth: AuthenticatedUser,
     content_type: &ContentType,
     form_data: Data<'_>,
-) -> Result<status::Custom<Value>, status::Custom<Value>> {
+    client: ClientInfo,
+    audit: &State<Box<dyn AuditRepository>>,
+    config: &State<AppConfig>,
+) -> Result<status::Custom<Value>, TransmissionError> {
+    auth.require(Permission::TransferFiles)?;
     let initial_time = time::Instant::now();
 
-    let multipart = MultipartHandler::from(content_type, form_data)
-        .await
-        .map_err(|e| {
-            let message =
-                json!({"success": false, "message": format!("Upload Failed with error: {:#?}", e)});
-            return status::Custom(Status::BadRequest, message);
-        })?;
-
-    let file_data = multipart.save_to_file().await.unwrap();
+    let multipart =
+        MultipartHandler::from(content_type, form_data, config.storage.max_upload_size).await?;
+    let owner = auth.user.user_id.as_ref().unwrap();
+    let file_data = multipart.save_to_file(config, owner).await?;
+    let (actor, target) = (AuditService::actor(&auth.user), Some(file_data.name.clone()));
+    AuditService::record(audit.as_ref(), &client, AuditEventKind::FileUploaded, actor, target).await;
 
     let elapsed = initial_time.elapsed();
     let message = json!({"success": true, "message": "Upload Successful", "data": file_data, "elapsed": {"value": elapsed.as_millis() as u32, "unit": "milliseconds"}});
@@ -44,11 +45,44 @@ pub async fn upload_file(
     Ok(status::Custom(Status::Ok, message))
 }
 
+/// Returns a file the signed-in user uploaded.
 #[get("/<filename>")]
-pub async fn download_file(filename: &str) -> Result<DownloadResponse, Status> {
-    let file = format!("{}/{}", file_util::STORAGE_DIRECTORY, filename);
+pub async fn download_file(
+    auth: AuthenticatedUser,
+    filename: &str,
+    client: ClientInfo,
+    audit: &State<Box<dyn AuditRepository>>,
+    config: &State<AppConfig>,
+) -> Result<DownloadResponse, Status> {
+    let owner = auth.user.user_id.as_ref().unwrap();
+    let file = FileUtil::user_file_path(config, owner, filename).ok_or(Status::BadRequest)?;
+    download(auth, file, filename, client, audit.as_ref()).await
+}
+
+#[get("/avatars/<filename>")]
+pub async fn download_avatar(
+    auth: AuthenticatedUser,
+    filename: &str,
+    client: ClientInfo,
+    audit: &State<Box<dyn AuditRepository>>,
+    config: &State<AppConfig>,
+) -> Result<DownloadResponse, Status> {
+    let file = FileUtil::avatar_path(config, filename).ok_or(Status::BadRequest)?;
+    download(auth, file, filename, client, audit.as_ref()).await
+}
+
+async fn download(
+    auth: AuthenticatedUser,
+    file: String,
+    filename: &str,
+    client: ClientInfo,
+    audit: &dyn AuditRepository,
+) -> Result<DownloadResponse, Status> {
+    if !auth.allows(Permission::TransferFiles) {
+        return Err(Status::Forbidden);
+    }
     let path = std::path::Path::new(&file);
-    DownloadResponse::from_file(path, None::<String>, None)
+    let response = DownloadResponse::from_file(path, None::<String>, None)
         .await
         .map_err(|err| {
             if err.kind() == ErrorKind::NotFound {
@@ -56,5 +90,9 @@ pub async fn download_file(filename: &str) -> Result<DownloadResponse, Status> {
             } else {
                 Status::InternalServerError
             }
-        })
+        })?;
+
+    let (actor, target) = (AuditService::actor(&auth.user), Some(filename.to_owned()));
+    AuditService::record(audit, &client, AuditEventKind::FileDownloaded, actor, target).await;
+    Ok(response)
 }
@@ -1,5 +1,18 @@
+// Rocket's route attributes emit `uri!` helper re-exports that are unused
+// until a handler is referenced through `uri!`.
+#[allow(unused_imports)]
 pub mod user_controller;
+#[allow(unused_imports)]
 pub mod file_controller;
+#[allow(unused_imports)]
+pub mod admin_controller;
+#[allow(unused_imports)]
+pub mod oauth_controller;
+#[allow(unused_imports)]
+pub mod oidc_controller;
 
 pub(crate) use user_controller::*;
 pub(crate) use file_controller::*;
+pub(crate) use admin_controller::*;
+pub(crate) use oauth_controller::*;
+pub(crate) use oidc_controller::*;
@@ -0,0 +1,291 @@
+use crate::config::app::AppConfig;
+use crate::handlers::error::{AuthenticationError, OAuthError};
+use crate::handlers::guard::{AuthenticatedUser, ClientCredentials, ClientInfo, ACCESS_TOKEN_COOKIE};
+use crate::models::audit::AuditEventKind;
+use crate::models::oauth::{AuthorizeDecision, AuthorizeRequest, TokenRequest, TokenResponse};
+use crate::models::token::{LoginOutcome, MfaVerifyRequest, SecondFactor};
+use crate::models::user::{LoginUser, User};
+use crate::repository::audit_repository::AuditRepository;
+use crate::repository::login_attempt_repository::LoginAttemptRepository;
+use crate::repository::oauth_repository::OAuthRepository;
+use crate::repository::refresh_token_repository::RefreshTokenRepository;
+use crate::repository::user_repository::UserRepository;
+use crate::services::audit_service::AuditService;
+use crate::services::oauth_service::{AuthorizeRejection, OAuthService};
+use crate::services::user_service::UserService;
+use crate::views::oauth::{authorize_page, error_page, AuthorizeView};
+use crate::views::Page;
+use rocket::form::Form;
+use rocket::http::{Cookie, CookieJar, Header, Status};
+use rocket::request::Request;
+use rocket::response::{self, Redirect, Responder};
+use rocket::serde::json::Json;
+use rocket::State;
+
+/// Private cookie holding the token the consent form has to echo back.
+const CSRF_COOKIE: &str = "oauth_csrf";
+
+/// Either a page for the user or a redirect back to the client.
+pub enum AuthorizeResponse {
+    Page(Page),
+    Redirect(String),
+}
+
+impl<'r> Responder<'r, 'static> for AuthorizeResponse {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        match self {
+            Self::Page(page) => page.respond_to(request),
+            Self::Redirect(location) => Redirect::to(location).respond_to(request),
+        }
+    }
+}
+
+/// A successful token response, which must not be cached.
+#[derive(Responder)]
+pub struct TokenGranted {
+    inner: Json<TokenResponse>,
+    cache_control: Header<'static>,
+}
+
+/// The login and consent page of an authorization request. Requests from
+/// unknown clients or to unregistered redirect URIs get an error page, other
+/// invalid requests are sent back to the client.
+#[get("/authorize?<request..>")]
+pub async fn authorize(
+    request: AuthorizeRequest,
+    auth: Option<AuthenticatedUser>,
+    oauth: &State<Box<dyn OAuthRepository>>,
+    cookies: &CookieJar<'_>,
+) -> AuthorizeResponse {
+    let authorization = match OAuthService::validate_authorization(oauth.as_ref(), &request).await {
+        Ok(authorization) => authorization,
+        Err(rejection) => return rejected(rejection),
+    };
+    let csrf_token = match cookies.get_private(CSRF_COOKIE) {
+        Some(cookie) => cookie.value().to_owned(),
+        None => {
+            let token = OAuthService::random_token();
+            cookies.add_private(Cookie::new(CSRF_COOKIE, token.clone()));
+            token
+        }
+    };
+    let session = session_user(auth);
+    let view = AuthorizeView {
+        client_name: &authorization.client.name,
+        scopes: &authorization.scopes,
+        signed_in_as: session.as_ref().map(|user| user.email_id.as_str()),
+        request: &request,
+        csrf_token: &csrf_token,
+        error: None,
+    };
+    AuthorizeResponse::Page(authorize_page(Status::Ok, view))
+}
+
+/// Handles the consent form: signs the user in if needed and, if they
+/// approved, sends the client an authorization code.
+#[allow(clippy::too_many_arguments)]
+#[post("/authorize", data = "<decision>")]
+pub async fn approve(
+    decision: Form<AuthorizeDecision>,
+    auth: Option<AuthenticatedUser>,
+    client: ClientInfo,
+    oauth: &State<Box<dyn OAuthRepository>>,
+    users: &State<Box<dyn UserRepository>>,
+    refresh_tokens: &State<Box<dyn RefreshTokenRepository>>,
+    attempts: &State<Box<dyn LoginAttemptRepository>>,
+    audit: &State<Box<dyn AuditRepository>>,
+    config: &State<AppConfig>,
+    cookies: &CookieJar<'_>,
+) -> AuthorizeResponse {
+    let decision = decision.into_inner();
+    let authorization =
+        match OAuthService::validate_authorization(oauth.as_ref(), &decision.request).await {
+            Ok(authorization) => authorization,
+            Err(rejection) => return rejected(rejection),
+        };
+    let csrf_valid = cookies.get_private(CSRF_COOKIE).is_some_and(|cookie| {
+        ring::constant_time::verify_slices_are_equal(
+            cookie.value().as_bytes(),
+            decision.csrf_token.as_bytes(),
+        )
+        .is_ok()
+    });
+    if !csrf_valid {
+        return AuthorizeResponse::Page(error_page(Status::Forbidden, "The form has expired, please try again"));
+    }
+    if decision.decision != "approve" {
+        return AuthorizeResponse::Redirect(authorization.redirect(&[("error", "access_denied")]));
+    }
+
+    let user = match session_user(auth) {
+        Some(user) => user,
+        None => {
+            let signed_in = sign_in(
+                &decision,
+                &client,
+                users.as_ref(),
+                refresh_tokens.as_ref(),
+                attempts.as_ref(),
+                audit.as_ref(),
+                config,
+            )
+            .await;
+            match signed_in {
+                Ok((user, access_token)) => {
+                    cookies.add_private(Cookie::new(ACCESS_TOKEN_COOKIE, access_token));
+                    user
+                }
+                Err((status, error)) => {
+                    let view = AuthorizeView {
+                        client_name: &authorization.client.name,
+                        scopes: &authorization.scopes,
+                        signed_in_as: None,
+                        request: &decision.request,
+                        csrf_token: &decision.csrf_token,
+                        error: Some(&error),
+                    };
+                    return AuthorizeResponse::Page(authorize_page(status, view));
+                }
+            }
+        }
+    };
+
+    let scopes = authorization.grantable_scopes(&user);
+    if scopes.is_empty() {
+        let parameters = [
+            ("error", "invalid_scope"),
+            ("error_description", "None of the requested scopes can be granted"),
+        ];
+        return AuthorizeResponse::Redirect(authorization.redirect(&parameters));
+    }
+    match OAuthService::issue_code(oauth.as_ref(), config, &authorization, &user, scopes).await {
+        Ok(code) => {
+            let (actor, target) = (AuditService::actor(&user), Some(authorization.client.client_id.clone()));
+            AuditService::record(audit.as_ref(), &client, AuditEventKind::ClientAuthorized, actor, target).await;
+            AuthorizeResponse::Redirect(authorization.redirect(&[("code", &code)]))
+        }
+        Err(err) => rejected(AuthorizeRejection::Internal(err)),
+    }
+}
+
+/// Exchanges an authorization code for an access token. Confidential
+/// clients authenticate with HTTP Basic or `client_secret` in the form.
+#[post("/token", data = "<request>")]
+pub async fn token(
+    request: Form<TokenRequest>,
+    credentials: ClientCredentials,
+    oauth: &State<Box<dyn OAuthRepository>>,
+    users: &State<Box<dyn UserRepository>>,
+    config: &State<AppConfig>,
+) -> Result<TokenGranted, OAuthError> {
+    let response = OAuthService::exchange_code(
+        oauth.as_ref(),
+        users.as_ref(),
+        config,
+        request.into_inner(),
+        credentials.0,
+    )
+    .await?;
+    Ok(TokenGranted {
+        inner: Json(response),
+        cache_control: Header::new("Cache-Control", "no-store"),
+    })
+}
+
+/// The user signed in to this service. Tokens issued to OAuth clients do
+/// not count as a session.
+fn session_user(auth: Option<AuthenticatedUser>) -> Option<User> {
+    auth.filter(|auth| auth.claims.client_id.is_none())
+        .map(|auth| auth.user)
+}
+
+fn rejected(rejection: AuthorizeRejection) -> AuthorizeResponse {
+    match rejection {
+        AuthorizeRejection::Untrusted(message) => {
+            AuthorizeResponse::Page(error_page(Status::BadRequest, &message))
+        }
+        AuthorizeRejection::Redirect {
+            redirect_uri,
+            state,
+            error,
+            description,
+        } => {
+            let mut parameters = vec![("error", error), ("error_description", description.as_str())];
+            if let Some(state) = &state {
+                parameters.push(("state", state));
+            }
+            AuthorizeResponse::Redirect(OAuthService::redirect_uri(&redirect_uri, &parameters))
+        }
+        AuthorizeRejection::Internal(err) => {
+            log::error!("Authorization request failed with error: {:?}", err);
+            AuthorizeResponse::Page(error_page(
+                Status::InternalServerError,
+                "Something went wrong, please try again",
+            ))
+        }
+    }
+}
+
+/// Signs in with the credentials of the consent form, returning the user and
+/// a session token, or the status and message to show on the form.
+async fn sign_in(
+    decision: &AuthorizeDecision,
+    client: &ClientInfo,
+    users: &dyn UserRepository,
+    refresh_tokens: &dyn RefreshTokenRepository,
+    attempts: &dyn LoginAttemptRepository,
+    audit: &dyn AuditRepository,
+    config: &AppConfig,
+) -> Result<(User, String), (Status, String)> {
+    let (username, password) = match (&decision.username, &decision.password) {
+        (Some(username), Some(password)) => (username.clone(), password.clone()),
+        _ => return Err((Status::BadRequest, "Enter your email and password".to_owned())),
+    };
+    let login = LoginUser {
+        username: username.clone(),
+        password,
+    };
+    let outcome =
+        UserService::login(users, refresh_tokens, attempts, config, login, client.ip).await;
+    let result = match (outcome, decision.otp.as_deref().filter(|otp| !otp.is_empty())) {
+        (Ok(LoginOutcome::Authenticated(res)), _) => Ok(*res),
+        (Ok(LoginOutcome::MfaRequired(challenge)), Some(otp)) => {
+            let request = MfaVerifyRequest {
+                challenge_token: challenge.challenge_token,
+                code: otp.to_owned(),
+            };
+            let verified =
+                UserService::verify_mfa(users, refresh_tokens, attempts, config, request, client.ip)
+                    .await;
+            if let Ok((res, SecondFactor::RecoveryCode)) = &verified {
+                let (actor, target) = (AuditService::actor(&res.user), Some(username.clone()));
+                AuditService::record(audit, client, AuditEventKind::RecoveryCodeUsed, actor, target).await;
+            }
+            verified.map(|(res, _)| res)
+        }
+        // Asked for again without recording a failure, the password was right
+        (Ok(LoginOutcome::MfaRequired(_)), None) => {
+            return Err((
+                Status::Unauthorized,
+                "Enter the code from your authenticator app".to_owned(),
+            ))
+        }
+        (Err(err), _) => Err(err),
+    };
+    let (kind, actor) = match &result {
+        Ok(res) => (AuditEventKind::SignInSucceeded, AuditService::actor(&res.user)),
+        Err(_) => (AuditEventKind::SignInFailed, None),
+    };
+    AuditService::record(audit, client, kind, actor, Some(username)).await;
+
+    match result {
+        Ok(res) => Ok((res.user, res.access_token)),
+        Err(AuthenticationError::InvalidMfaCode(message)) => Err((Status::Unauthorized, message)),
+        Err(
+            AuthenticationError::ValidationError(_)
+            | AuthenticationError::UserNotFound(_)
+            | AuthenticationError::PasswordMismatch(_),
+        ) => Err((Status::Unauthorized, "Invalid email or password".to_owned())),
+        Err(err) => Err((err.status(), err.message())),
+    }
+}
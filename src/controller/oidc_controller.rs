@@ -0,0 +1,37 @@
+use crate::config::app::AppConfig;
+use crate::handlers::error::AuthenticationError;
+use crate::handlers::guard::AuthenticatedUser;
+use crate::models::oidc::{DiscoveryDocument, Jwks, UserInfo};
+use crate::models::role::Permission;
+use crate::services::oidc_service::OidcService;
+use rocket::serde::json::Json;
+use rocket::State;
+
+// These documents follow the OpenID Connect specifications rather than the
+// `success`/`data` envelope of the rest of the API.
+
+#[get("/openid-configuration")]
+pub fn openid_configuration(
+    config: &State<AppConfig>,
+) -> Result<Json<DiscoveryDocument>, AuthenticationError> {
+    Ok(Json(OidcService::discovery(config)?))
+}
+
+#[get("/jwks.json")]
+pub fn jwks(config: &State<AppConfig>) -> Result<Json<Jwks>, AuthenticationError> {
+    Ok(Json(OidcService::jwks(config)?))
+}
+
+/// Claims about the user an access token was issued for. Tokens of OAuth
+/// clients need the `openid` scope.
+#[get("/userinfo")]
+pub fn userinfo(auth: AuthenticatedUser) -> Result<Json<UserInfo>, AuthenticationError> {
+    auth.require(Permission::ViewIdentity)?;
+    Ok(Json(OidcService::user_info(&auth.user)))
+}
+
+/// `/userinfo` as a POST, which OpenID Connect clients may also use.
+#[post("/userinfo")]
+pub fn userinfo_post(auth: AuthenticatedUser) -> Result<Json<UserInfo>, AuthenticationError> {
+    userinfo(auth)
+}
@@ -1,82 +1,405 @@
-use crate::config::crypto::CryptoService;
+use crate::config::app::AppConfig;
+use crate::handlers::body::{FormBody, JsonBody};
+use crate::handlers::error::{AuthenticationError, TransmissionError};
+use crate::handlers::guard::{AuthenticatedUser, ClientInfo, RequireRole, ACCESS_TOKEN_COOKIE};
+use crate::models::role::{Permission, Support};
+use crate::models::token::{
+    LoginOutcome, MfaCode, MfaVerifyRequest, RefreshRequest, ResendVerification, SecondFactor,
+};
 use crate::models::user::*;
+use crate::models::audit::AuditEventKind;
+use crate::repository::audit_repository::AuditRepository;
+use crate::repository::login_attempt_repository::LoginAttemptRepository;
+use crate::repository::refresh_token_repository::RefreshTokenRepository;
+use crate::repository::user_repository::UserRepository;
+use crate::services::audit_service::AuditService;
+use crate::services::file_service::MultipartHandler;
+use crate::services::mail_service::Mailer;
 use crate::services::user_service::UserService;
-use crate::utils::mongo_util::MongoUtil;
-use rocket::serde::json::Json;
+use chrono::Utc;
 use rocket::{
-    form::{Form, Strict},
-    http::Status,
+    data::Data,
+    form::Strict,
+    http::{ContentType, Cookie, CookieJar, Status},
     response::status,
+    State,
 };
 use serde_json::{json, Value};
+use std::sync::Arc;
 use strum::VariantNames;
 
+#[allow(clippy::too_many_arguments)]
 #[post("/sign-in", data = "<user>")]
 pub async fn sign_in(
-    user: Form<Strict<LoginUser>>,
-) -> Result<status::Custom<Value>, status::Custom<Value>> {
-    let result = UserService::login(user.into_inner().into_inner())
-        .await
-        .map_err(|e| {
-            let message =
-                json!({"success": false, "message": format!("Login Failed with error: {:#?}", e)});
-            return status::Custom(Status::NotImplemented, message);
-        })
-        .and_then(|res| {
-            let message = json!({"success": true, "message": "Login Successful", "data": res});
+    user: FormBody<Strict<LoginUser>>,
+    client: ClientInfo,
+    users: &State<Box<dyn UserRepository>>,
+    refresh_tokens: &State<Box<dyn RefreshTokenRepository>>,
+    attempts: &State<Box<dyn LoginAttemptRepository>>,
+    audit: &State<Box<dyn AuditRepository>>,
+    config: &State<AppConfig>,
+    cookies: &CookieJar<'_>,
+) -> Result<status::Custom<Value>, AuthenticationError> {
+    let user = user.into_inner().into_inner();
+    let username = user.username.clone();
+    let result = UserService::login(
+        users.as_ref(),
+        refresh_tokens.as_ref(),
+        attempts.as_ref(),
+        config,
+        user,
+        client.ip,
+    )
+    .await;
+    let (kind, actor) = match &result {
+        Ok(LoginOutcome::Authenticated(res)) => {
+            (AuditEventKind::SignInSucceeded, AuditService::actor(&res.user))
+        }
+        Err(_) => (AuditEventKind::SignInFailed, None),
+        // Recorded once the second factor is verified
+        Ok(LoginOutcome::MfaRequired(_)) => {
+            let message = json!({"success": true, "message": "Two-Factor Authentication Required", "data": result?});
             return Ok(status::Custom(Status::Ok, message));
-        });
-    result
+        }
+    };
+    AuditService::record(audit.as_ref(), &client, kind, actor, Some(username)).await;
+    let res = result?;
+
+    if let LoginOutcome::Authenticated(tokens) = &res {
+        cookies.add_private(Cookie::new(ACCESS_TOKEN_COOKIE, tokens.access_token.clone()));
+    }
+    let message = json!({"success": true, "message": "Login Successful", "data": res});
+    Ok(status::Custom(Status::Ok, message))
+}
+
+#[allow(clippy::too_many_arguments)]
+#[post("/mfa/verify", data = "<request>")]
+pub async fn verify_mfa(
+    request: FormBody<Strict<MfaVerifyRequest>>,
+    client: ClientInfo,
+    users: &State<Box<dyn UserRepository>>,
+    refresh_tokens: &State<Box<dyn RefreshTokenRepository>>,
+    attempts: &State<Box<dyn LoginAttemptRepository>>,
+    audit: &State<Box<dyn AuditRepository>>,
+    config: &State<AppConfig>,
+    cookies: &CookieJar<'_>,
+) -> Result<status::Custom<Value>, AuthenticationError> {
+    let result = UserService::verify_mfa(
+        users.as_ref(),
+        refresh_tokens.as_ref(),
+        attempts.as_ref(),
+        config,
+        request.into_inner().into_inner(),
+        client.ip,
+    )
+    .await;
+    let (kind, actor, target) = match &result {
+        Ok((res, _)) => (
+            AuditEventKind::SignInSucceeded,
+            AuditService::actor(&res.user),
+            Some(res.user.email_id.clone()),
+        ),
+        Err(_) => (AuditEventKind::SignInFailed, None, None),
+    };
+    if let Ok((res, SecondFactor::RecoveryCode)) = &result {
+        let (actor, target) = (AuditService::actor(&res.user), Some(res.user.email_id.clone()));
+        AuditService::record(audit.as_ref(), &client, AuditEventKind::RecoveryCodeUsed, actor, target).await;
+    }
+    AuditService::record(audit.as_ref(), &client, kind, actor, target).await;
+    let (res, _) = result?;
+
+    cookies.add_private(Cookie::new(ACCESS_TOKEN_COOKIE, res.access_token.clone()));
+    let message = json!({"success": true, "message": "Login Successful", "data": res});
+    Ok(status::Custom(Status::Ok, message))
+}
+
+#[post("/mfa/enroll")]
+pub async fn enroll_mfa(
+    auth: AuthenticatedUser,
+    users: &State<Box<dyn UserRepository>>,
+    config: &State<AppConfig>,
+) -> Result<status::Custom<Value>, AuthenticationError> {
+    auth.require(Permission::ManageCredentials)?;
+    let (secret, otpauth_uri) = UserService::enroll_mfa(users.as_ref(), config, auth.user).await?;
+    let message = json!({"success": true, "message": "Two-Factor Enrolment Started", "data": {"secret": secret, "otpauth_uri": otpauth_uri}});
+    Ok(status::Custom(Status::Ok, message))
+}
+
+#[post("/mfa/confirm", data = "<request>")]
+pub async fn confirm_mfa(
+    auth: AuthenticatedUser,
+    request: FormBody<Strict<MfaCode>>,
+    client: ClientInfo,
+    users: &State<Box<dyn UserRepository>>,
+    audit: &State<Box<dyn AuditRepository>>,
+    config: &State<AppConfig>,
+) -> Result<status::Custom<Value>, AuthenticationError> {
+    auth.require(Permission::ManageCredentials)?;
+    let (user, recovery_codes) = UserService::confirm_mfa(users.as_ref(), config, auth.user, &request.code).await?;
+    let (actor, target) = (AuditService::actor(&user), Some(user.email_id.clone()));
+    AuditService::record(audit.as_ref(), &client, AuditEventKind::MfaEnabled, actor, target).await;
+    let message = json!({"success": true, "message": "Two-Factor Authentication Enabled", "data": {"user": user, "recovery_codes": recovery_codes}});
+    Ok(status::Custom(Status::Ok, message))
+}
+
+#[allow(clippy::too_many_arguments)]
+#[post("/mfa/recovery-codes", data = "<request>")]
+pub async fn regenerate_recovery_codes(
+    auth: AuthenticatedUser,
+    request: FormBody<Strict<MfaCode>>,
+    client: ClientInfo,
+    users: &State<Box<dyn UserRepository>>,
+    attempts: &State<Box<dyn LoginAttemptRepository>>,
+    audit: &State<Box<dyn AuditRepository>>,
+    config: &State<AppConfig>,
+) -> Result<status::Custom<Value>, AuthenticationError> {
+    auth.require(Permission::ManageCredentials)?;
+    let (user, recovery_codes, factor) = UserService::regenerate_recovery_codes(
+        users.as_ref(),
+        attempts.as_ref(),
+        config,
+        auth.user,
+        &request.code,
+        client.ip,
+    )
+    .await?;
+    let (actor, target) = (AuditService::actor(&user), Some(user.email_id.clone()));
+    if factor == SecondFactor::RecoveryCode {
+        AuditService::record(audit.as_ref(), &client, AuditEventKind::RecoveryCodeUsed, actor.clone(), target.clone()).await;
+    }
+    AuditService::record(audit.as_ref(), &client, AuditEventKind::RecoveryCodesRegenerated, actor, target).await;
+    let message = json!({"success": true, "message": "Recovery Codes Regenerated", "data": {"recovery_codes": recovery_codes}});
+    Ok(status::Custom(Status::Ok, message))
+}
+
+#[post("/refresh", data = "<token>")]
+pub async fn refresh(
+    token: FormBody<Strict<RefreshRequest>>,
+    users: &State<Box<dyn UserRepository>>,
+    refresh_tokens: &State<Box<dyn RefreshTokenRepository>>,
+    config: &State<AppConfig>,
+    cookies: &CookieJar<'_>,
+) -> Result<status::Custom<Value>, AuthenticationError> {
+    let res = UserService::refresh(
+        users.as_ref(),
+        refresh_tokens.as_ref(),
+        config,
+        token.into_inner().into_inner(),
+    )
+    .await?;
+
+    cookies.add_private(Cookie::new(ACCESS_TOKEN_COOKIE, res.access_token.clone()));
+    let message = json!({"success": true, "message": "Token Refreshed", "data": res});
+    Ok(status::Custom(Status::Ok, message))
 }
 
 #[post("/sign-up", data = "<user>")]
 pub async fn sign_up(
-    mut user: Form<Strict<RegisterUser>>,
-) -> Result<status::Custom<Value>, status::Custom<Value>> {
-    let hasher = CryptoService::new();
-    let password_hash = hasher.hash_password(user.password.clone()).await.unwrap();
-    user.password = password_hash;
-    println!("password_hash : {:#?}", user.clone());
+    user: FormBody<Strict<RegisterUser>>,
+    client: ClientInfo,
+    users: &State<Box<dyn UserRepository>>,
+    audit: &State<Box<dyn AuditRepository>>,
+    config: &State<AppConfig>,
+    mailer: &State<Arc<dyn Mailer>>,
+) -> Result<status::Custom<Value>, AuthenticationError> {
+    let res = UserService::register(users.as_ref(), config, user.into_inner().into_inner(), mailer.as_ref()).await?;
+    let (actor, target) = (AuditService::actor(&res), Some(res.email_id.clone()));
+    AuditService::record(audit.as_ref(), &client, AuditEventKind::SignUp, actor, target).await;
+    let message = json!({"success": true, "message": "User Registration Successful", "data": res});
+    Ok(status::Custom(Status::Ok, message))
+}
+
+#[get("/verify-email?<token>")]
+pub async fn verify_email(
+    token: &str,
+    users: &State<Box<dyn UserRepository>>,
+    config: &State<AppConfig>,
+) -> Result<status::Custom<Value>, AuthenticationError> {
+    let res = UserService::verify_email(users.as_ref(), config, token).await?;
+    let message = json!({"success": true, "message": "Email Verified", "data": res});
+    Ok(status::Custom(Status::Ok, message))
+}
+
+#[post("/resend-verification", data = "<request>")]
+pub async fn resend_verification(
+    request: FormBody<Strict<ResendVerification>>,
+    users: &State<Box<dyn UserRepository>>,
+    config: &State<AppConfig>,
+    mailer: &State<Arc<dyn Mailer>>,
+) -> Result<status::Custom<Value>, AuthenticationError> {
+    UserService::resend_verification(users.as_ref(), config, &request.email_id, mailer.as_ref()).await?;
+    let message = json!({"success": true, "message": "If the account exists and is unverified, a new verification email has been sent"});
+    Ok(status::Custom(Status::Ok, message))
+}
+
+#[post("/forgot-password", data = "<request>")]
+pub async fn forgot_password(
+    request: FormBody<Strict<ForgotPassword>>,
+    users: &State<Box<dyn UserRepository>>,
+    config: &State<AppConfig>,
+    mailer: &State<Arc<dyn Mailer>>,
+) -> Result<status::Custom<Value>, AuthenticationError> {
+    // Same response whether or not the account exists
+    let mailer = mailer.inner().clone();
+    if let Err(e) =
+        UserService::forgot_password(users.as_ref(), config, &request.email_id, mailer).await
+    {
+        log::error!("Forgot password failed with error: {:?}", e);
+    }
+    let message = json!({"success": true, "message": "If an account exists for this email, a password reset link has been sent"});
+    Ok(status::Custom(Status::Ok, message))
+}
+
+#[post("/reset-password", data = "<request>")]
+pub async fn reset_password(
+    request: FormBody<Strict<ResetPassword>>,
+    client: ClientInfo,
+    users: &State<Box<dyn UserRepository>>,
+    refresh_tokens: &State<Box<dyn RefreshTokenRepository>>,
+    audit: &State<Box<dyn AuditRepository>>,
+    config: &State<AppConfig>,
+) -> Result<status::Custom<Value>, AuthenticationError> {
+    let res = UserService::reset_password(
+        users.as_ref(),
+        refresh_tokens.as_ref(),
+        config,
+        request.into_inner().into_inner(),
+    )
+    .await?;
+    let (actor, target) = (AuditService::actor(&res), Some(res.email_id.clone()));
+    AuditService::record(audit.as_ref(), &client, AuditEventKind::PasswordReset, actor, target).await;
+    let message = json!({"success": true, "message": "Password Reset Successful", "data": res});
+    Ok(status::Custom(Status::Ok, message))
+}
+
+#[allow(clippy::too_many_arguments)]
+#[post("/change-password", data = "<request>")]
+pub async fn change_password(
+    auth: AuthenticatedUser,
+    request: FormBody<Strict<ChangePassword>>,
+    client: ClientInfo,
+    users: &State<Box<dyn UserRepository>>,
+    refresh_tokens: &State<Box<dyn RefreshTokenRepository>>,
+    audit: &State<Box<dyn AuditRepository>>,
+    config: &State<AppConfig>,
+    cookies: &CookieJar<'_>,
+) -> Result<status::Custom<Value>, AuthenticationError> {
+    auth.require(Permission::ManageCredentials)?;
+    let (user, access_token) = UserService::change_password(
+        users.as_ref(),
+        refresh_tokens.as_ref(),
+        config,
+        auth.user,
+        auth.claims,
+        request.into_inner().into_inner(),
+    )
+    .await?;
+    let (actor, target) = (AuditService::actor(&user), Some(user.email_id.clone()));
+    AuditService::record(audit.as_ref(), &client, AuditEventKind::PasswordChanged, actor, target).await;
+
+    if let Some(token) = &access_token {
+        cookies.add_private(Cookie::new(ACCESS_TOKEN_COOKIE, token.clone()));
+    }
+    let message = json!({"success": true, "message": "Password Changed", "data": {"user": user, "access_token": access_token}});
+    Ok(status::Custom(Status::Ok, message))
+}
+
+#[get("/me")]
+pub fn get_profile(auth: AuthenticatedUser) -> Result<status::Custom<Value>, AuthenticationError> {
+    auth.require(Permission::ManageOwnAccount)?;
+    let message = json!({"success": true, "message": "Profile", "data": auth.user});
+    Ok(status::Custom(Status::Ok, message))
+}
+
+#[patch("/me", data = "<profile>")]
+pub async fn update_profile(
+    auth: AuthenticatedUser,
+    profile: JsonBody<UpdateProfile>,
+    users: &State<Box<dyn UserRepository>>,
+) -> Result<status::Custom<Value>, AuthenticationError> {
+    auth.require(Permission::ManageOwnAccount)?;
+    let res = UserService::update_profile(users.as_ref(), auth.user, profile.into_inner()).await?;
+    let message = json!({"success": true, "message": "Profile Updated", "data": res});
+    Ok(status::Custom(Status::Ok, message))
+}
+
+#[post("/me/avatar", data = "<form_data>")]
+pub async fn upload_avatar(
+    auth: AuthenticatedUser,
+    content_type: &ContentType,
+    form_data: Data<'_>,
+    users: &State<Box<dyn UserRepository>>,
+    config: &State<AppConfig>,
+) -> Result<status::Custom<Value>, TransmissionError> {
+    auth.require(Permission::ManageOwnAccount)?;
+    let size_limit = config.storage.max_avatar_size;
+    let mut multipart =
+        MultipartHandler::from_field(content_type, form_data, "avatar", size_limit).await?;
 
-    let result = UserService::register(user.into_inner().into_inner()).await.map_err(|e| {
-        let message = json!({"success": false, "message": format!("User Registration Failed with error: {:#?}", e)});
-        return status::Custom(Status::NotImplemented, message);
+    let extension = multipart.image_extension().ok_or_else(|| {
+        TransmissionError::UnsupportedMediaType(
+            "Avatar must be a PNG, JPEG, GIF or WebP image".to_owned(),
+        )
+    })?;
+    let prefix = UserService::avatar_prefix(auth.user.user_id.as_ref().unwrap());
+    multipart.file_name = format!("{}{}.{}", prefix, Utc::now().timestamp_millis(), extension);
 
-    }).and_then(|res| {
-        let message = json!({"success": true, "message": "User Registration Successful", "data": res});
-        return Ok(status::Custom(Status::Ok, message));
-    });
-    result
+    let file_data = multipart.save_avatar(config).await?;
+    let res = UserService::set_avatar(users.as_ref(), config, auth.user, file_data.url).await?;
+    let message = json!({"success": true, "message": "Avatar Updated", "data": res});
+    Ok(status::Custom(Status::Ok, message))
 }
 
+/// Looks up any account, which only staff may do.
 #[post("/find-user", data = "<user>")]
-pub async fn find_user(user: Json<Value>) -> Result<status::Custom<Value>, status::Custom<Value>> {
-    MongoUtil::find_one(json!(user.into_inner())).await.map_err(|err| {
-        let message = json!({"success": false, "message": format!("Find User Failed with error: {:#?}", err)});
-        return status::Custom(Status::InternalServerError, message);
+pub async fn find_user(
+    _support: RequireRole<Support>,
+    user: JsonBody<FindUser>,
+    users: &State<Box<dyn UserRepository>>,
+) -> Result<status::Custom<Value>, AuthenticationError> {
+    let found = match (&user.user_id, &user.email_id) {
+        (Some(user_id), _) => users.find_by_id(user_id).await?,
+        (None, Some(email_id)) => users.find_by_email(email_id).await?,
+        (None, None) => {
+            return Err(AuthenticationError::InvalidQuery(
+                "Either _id or email_id is required".to_owned(),
+            ))
+        }
+    };
 
-    }).and_then(|data| {
-        let message = json!({"success": true, "message": "Found User", "data": data});
-        return Ok(status::Custom(Status::Ok, message))
-    })
+    let data = found.ok_or_else(|| AuthenticationError::UserNotFound("User not found".to_owned()))?;
+    let message = json!({"success": true, "message": "Found User", "data": data});
+    Ok(status::Custom(Status::Ok, message))
 }
 
 #[post("/delete-user", data = "<user>")]
 pub async fn delete_user(
-    user: Json<DeleteUser>,
-) -> Result<status::Custom<Value>, status::Custom<Value>> {
-    MongoUtil::delete_one_by_filter(json!({"email_id": user.username})).await.map_err(|err| {
-        let message = json!({"success": false, "message": format!("Delete User Failed with error: {:#?}", err)});
-        return status::Custom(Status::InternalServerError, message);
+    auth: AuthenticatedUser,
+    user: JsonBody<DeleteUser>,
+    client: ClientInfo,
+    users: &State<Box<dyn UserRepository>>,
+    audit: &State<Box<dyn AuditRepository>>,
+) -> Result<status::Custom<Value>, AuthenticationError> {
+    // Users may only delete their own account unless allowed to delete any
+    if auth.user.email_id == user.username {
+        auth.require(Permission::ManageCredentials)?;
+    } else if !auth.allows(Permission::DeleteAnyUser) {
+        return Err(AuthenticationError::Forbidden(
+            "Not allowed to delete other users".to_owned(),
+        ));
+    }
 
-    }).and_then(|data| {
-        let message = json!({"success": true, "message": "User Deleted", "data": data});
-        return Ok(status::Custom(Status::Ok, message))
-    })
+    let deleted_count = users.delete_by_email(&user.username).await?;
+    if deleted_count > 0 {
+        let (actor, target) = (AuditService::actor(&auth.user), Some(user.username.clone()));
+        AuditService::record(audit.as_ref(), &client, AuditEventKind::UserDeleted, actor, target).await;
+    }
+    let message = json!({"success": true, "message": "User Deleted", "data": {"deleted_count": deleted_count}});
+    Ok(status::Custom(Status::Ok, message))
 }
 
 #[get("/get-user-tags")]
-pub fn get_user_tags() -> Result<status::Custom<Value>, status::Custom<Value>> {
+pub fn get_user_tags() -> Result<status::Custom<Value>, AuthenticationError> {
     let message = json!({"success": true, "message": "User Tags", "data": UserTags::VARIANTS});
     Ok(status::Custom(Status::Ok, message))
 }
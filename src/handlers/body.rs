@@ -0,0 +1,139 @@
+use rocket::data::{Data, FromData, Outcome};
+use rocket::form::{self, error::ErrorKind, Form, FromForm};
+use rocket::request::Request;
+use rocket::serde::json::{self, Json};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::ops::Deref;
+
+/// Name the errors that are not about any one field are reported under, as
+/// for `validator`'s schema validations.
+const WHOLE_BODY: &str = "__all__";
+
+type FieldErrors = BTreeMap<String, Vec<Value>>;
+
+/// The failed fields of a request body that could not be parsed, in the
+/// shape of validation errors, cached by the data guards below for the 422
+/// catcher to report.
+pub struct BodyErrors(pub Option<Value>);
+
+/// A form body, like `Form<T>`, whose field errors are left for the 422
+/// catcher.
+pub struct FormBody<T>(T);
+
+impl<T> FormBody<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for FormBody<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+#[rocket::async_trait]
+impl<'r, T: FromForm<'r>> FromData<'r> for FormBody<T> {
+    type Error = form::Errors<'r>;
+
+    async fn from_data(request: &'r Request<'_>, data: Data<'r>) -> Outcome<'r, Self> {
+        match <Form<T> as FromData>::from_data(request, data).await {
+            Outcome::Success(form) => Outcome::Success(FormBody(form.into_inner())),
+            Outcome::Failure((status, errors)) => {
+                request.local_cache(|| BodyErrors(Some(form_errors(&errors))));
+                Outcome::Failure((status, errors))
+            }
+            Outcome::Forward(data) => Outcome::Forward(data),
+        }
+    }
+}
+
+/// A JSON body, like `Json<T>`, whose field errors are left for the 422
+/// catcher.
+pub struct JsonBody<T>(T);
+
+impl<T> JsonBody<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for JsonBody<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+#[rocket::async_trait]
+impl<'r, T: Deserialize<'r>> FromData<'r> for JsonBody<T> {
+    type Error = json::Error<'r>;
+
+    async fn from_data(request: &'r Request<'_>, data: Data<'r>) -> Outcome<'r, Self> {
+        match <Json<T> as FromData>::from_data(request, data).await {
+            Outcome::Success(body) => Outcome::Success(JsonBody(body.into_inner())),
+            Outcome::Failure((status, error)) => {
+                if let json::Error::Parse(_, error) = &error {
+                    request.local_cache(|| BodyErrors(Some(json_errors(error))));
+                }
+                Outcome::Failure((status, error))
+            }
+            Outcome::Forward(data) => Outcome::Forward(data),
+        }
+    }
+}
+
+/// The failed rules of every invalid form field, e.g.
+/// `{"email_id": [{"code": "missing", "message": "is required"}]}`.
+fn form_errors(errors: &form::Errors<'_>) -> Value {
+    let mut fields = FieldErrors::new();
+    for error in errors.iter() {
+        let field = match &error.name {
+            Some(name) => name.to_string(),
+            None => WHOLE_BODY.to_owned(),
+        };
+        let (code, message) = match &error.kind {
+            ErrorKind::Missing => ("missing", "is required".to_owned()),
+            ErrorKind::Unexpected => ("unexpected", "is not allowed".to_owned()),
+            ErrorKind::Duplicate => ("duplicate", "must only be given once".to_owned()),
+            ErrorKind::InvalidLength { .. } => ("length", error.kind.to_string()),
+            ErrorKind::InvalidChoice { .. } => ("choice", error.kind.to_string()),
+            ErrorKind::OutOfRange { .. } => ("range", error.kind.to_string()),
+            kind => ("invalid", kind.to_string()),
+        };
+        push(&mut fields, field, code, message);
+    }
+    json!(fields)
+}
+
+/// Errors of a JSON body that does not match its type. `serde_json` only
+/// names the field when it is missing or unknown; other mismatches are
+/// reported for the whole body.
+fn json_errors(error: &serde_json::Error) -> Value {
+    let message = error.to_string();
+    let named = |prefix: &str| {
+        message
+            .strip_prefix(prefix)
+            .and_then(|rest| rest.split('`').next())
+            .map(str::to_owned)
+    };
+    let mut fields = FieldErrors::new();
+    if let Some(field) = named("missing field `") {
+        push(&mut fields, field, "missing", "is required".to_owned());
+    } else if let Some(field) = named("unknown field `") {
+        push(&mut fields, field, "unexpected", "is not allowed".to_owned());
+    } else {
+        push(&mut fields, WHOLE_BODY.to_owned(), "invalid", message);
+    }
+    json!(fields)
+}
+
+fn push(fields: &mut FieldErrors, field: String, code: &str, message: String) {
+    let rule = json!({"code": code, "message": message});
+    fields.entry(field).or_default().push(rule);
+}
@@ -1,13 +1,113 @@
+use rocket::http::{Header, Status};
+use rocket::request::Request;
+use rocket::response::{self, status, Responder};
 use rocket_multipart_form_data::MultipartFormDataError;
-use crate::models::user::User;
+use serde_json::{json, Map, Value};
+use validator::{ValidationError, ValidationErrors};
 
+// Payloads that are not safe to show are only surfaced through `Debug` in
+// the server log.
+#[allow(dead_code)]
 #[derive(Debug, Clone)]
 pub enum AuthenticationError {
     MongoError(mongodb::error::Error),
-    UserAlreadyExists(User),
+    UserAlreadyExists(String),
+    UserNotFound(String),
     DbError(String),
     PasswordMismatch(String),
-    LoginError(String)
+    LoginError(String),
+    TokenError(String),
+    InvalidRefreshToken(String),
+    RefreshTokenReused(String),
+    Unauthorized(String),
+    Forbidden(String),
+    EmailNotVerified(String),
+    MailError(String),
+    /// A token could not be signed, e.g. because no signing key is loaded
+    SigningError(String),
+    ValidationError(validator::ValidationErrors),
+    InvalidQuery(String),
+    /// Seconds until sign-in may be attempted again
+    TooManyAttempts(i64),
+    InvalidMfaCode(String),
+    /// Two-factor enrolment is not in the state the request needs
+    MfaConflict(String),
+}
+
+impl AuthenticationError {
+    pub fn status(&self) -> Status {
+        match self {
+            Self::TokenError(_) | Self::InvalidQuery(_) => Status::BadRequest,
+            Self::PasswordMismatch(_)
+            | Self::InvalidRefreshToken(_)
+            | Self::RefreshTokenReused(_)
+            | Self::InvalidMfaCode(_)
+            | Self::Unauthorized(_) => Status::Unauthorized,
+            Self::Forbidden(_) | Self::EmailNotVerified(_) => Status::Forbidden,
+            Self::UserNotFound(_) => Status::NotFound,
+            Self::UserAlreadyExists(_) | Self::MfaConflict(_) => Status::Conflict,
+            Self::ValidationError(_) => Status::UnprocessableEntity,
+            Self::TooManyAttempts(_) => Status::TooManyRequests,
+            Self::MongoError(_)
+            | Self::DbError(_)
+            | Self::LoginError(_)
+            | Self::MailError(_)
+            | Self::SigningError(_) => Status::InternalServerError,
+        }
+    }
+
+    /// Stable, machine-readable identifier of the error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::UserAlreadyExists(_) => "user_already_exists",
+            Self::UserNotFound(_) => "user_not_found",
+            Self::PasswordMismatch(_) => "invalid_credentials",
+            Self::TokenError(_) => "invalid_token",
+            Self::InvalidRefreshToken(_) => "invalid_refresh_token",
+            Self::RefreshTokenReused(_) => "refresh_token_reused",
+            Self::Unauthorized(_) => "unauthorized",
+            Self::Forbidden(_) => "forbidden",
+            Self::EmailNotVerified(_) => "email_not_verified",
+            Self::ValidationError(_) => "validation_failed",
+            Self::InvalidQuery(_) => "invalid_query",
+            Self::TooManyAttempts(_) => "too_many_attempts",
+            Self::InvalidMfaCode(_) => "invalid_mfa_code",
+            Self::MfaConflict(_) => "mfa_conflict",
+            Self::MongoError(_)
+            | Self::DbError(_)
+            | Self::LoginError(_)
+            | Self::MailError(_)
+            | Self::SigningError(_) => "internal_error",
+        }
+    }
+
+    /// Message that is safe to show to the client. Details of internal
+    /// errors are only logged.
+    pub fn message(&self) -> String {
+        match self {
+            Self::UserAlreadyExists(_) => "An account with this email already exists".to_owned(),
+            Self::UserNotFound(_) => "User not found".to_owned(),
+            Self::ValidationError(_) => "Validation failed".to_owned(),
+            Self::TooManyAttempts(_) => {
+                "Too many failed sign-in attempts, try again later".to_owned()
+            }
+            Self::PasswordMismatch(message)
+            | Self::TokenError(message)
+            | Self::InvalidRefreshToken(message)
+            | Self::RefreshTokenReused(message)
+            | Self::Unauthorized(message)
+            | Self::Forbidden(message)
+            | Self::EmailNotVerified(message)
+            | Self::InvalidQuery(message)
+            | Self::InvalidMfaCode(message)
+            | Self::MfaConflict(message) => message.clone(),
+            Self::MongoError(_)
+            | Self::DbError(_)
+            | Self::LoginError(_)
+            | Self::MailError(_)
+            | Self::SigningError(_) => "Internal server error".to_owned(),
+        }
+    }
 }
 
 impl From<mongodb::error::Error> for AuthenticationError {
@@ -16,12 +116,188 @@ impl From<mongodb::error::Error> for AuthenticationError {
     }
 }
 
+impl<'r> Responder<'r, 'static> for AuthenticationError {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let status = self.status();
+        if status == Status::InternalServerError {
+            log::error!("{} {} failed with error: {:?}", request.method(), request.uri(), self);
+        }
+        let mut response = error_response(status, self.code(), &self.message());
+        if let Self::ValidationError(errors) = &self {
+            response.1["errors"] = field_errors(errors);
+        }
+        let mut response = response.respond_to(request)?;
+        if let Self::TooManyAttempts(retry_after) = self {
+            response.set_header(Header::new("Retry-After", retry_after.to_string()));
+        }
+        Ok(response)
+    }
+}
+
+/// The failed rules of every invalid field, e.g.
+/// `{"first_name": [{"code": "length", "message": "..."}]}`.
+fn field_errors(errors: &ValidationErrors) -> Value {
+    let mut fields = Map::new();
+    for (field, errors) in errors.field_errors() {
+        let rules = errors
+            .iter()
+            .map(|error| json!({ "code": error.code, "message": describe(error) }))
+            .collect();
+        fields.insert(field.to_owned(), Value::Array(rules));
+    }
+    Value::Object(fields)
+}
+
+fn describe(error: &ValidationError) -> String {
+    if let Some(message) = &error.message {
+        return message.to_string();
+    }
+    let param = |name: &str| error.params.get(name).map(|value| value.to_string());
+    match (error.code.as_ref(), param("min"), param("max")) {
+        ("email", _, _) => "must be a valid email address".to_owned(),
+        ("url", _, _) => "must be a valid url".to_owned(),
+        ("length", Some(min), Some(max)) => format!("must be {} to {} characters long", min, max),
+        ("length", Some(min), None) => format!("must be at least {} characters long", min),
+        ("length", None, Some(max)) => format!("must be at most {} characters long", max),
+        ("range", Some(min), Some(max)) => format!("must be between {} and {}", min, max),
+        _ => "is invalid".to_owned(),
+    }
+}
+
+#[allow(dead_code)]
 #[derive(Debug)]
 pub enum TransmissionError {
     RocketError(rocket::Error),
     IOError(async_std::io::Error),
     MultipartFormError(MultipartFormDataError),
+    UnsupportedMediaType(String),
     Message(String),
+    Authentication(AuthenticationError),
+}
+
+impl TransmissionError {
+    pub fn status(&self) -> Status {
+        match self {
+            Self::MultipartFormError(MultipartFormDataError::DataTooLargeError(_)) => {
+                Status::PayloadTooLarge
+            }
+            Self::MultipartFormError(_) | Self::Message(_) => Status::BadRequest,
+            Self::UnsupportedMediaType(_) => Status::UnsupportedMediaType,
+            Self::RocketError(_) | Self::IOError(_) => Status::InternalServerError,
+            Self::Authentication(err) => err.status(),
+        }
+    }
+
+    /// Stable, machine-readable identifier of the error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::MultipartFormError(MultipartFormDataError::DataTooLargeError(_)) => {
+                "payload_too_large"
+            }
+            Self::MultipartFormError(_) | Self::Message(_) => "invalid_upload",
+            Self::UnsupportedMediaType(_) => "unsupported_media_type",
+            Self::RocketError(_) | Self::IOError(_) => "internal_error",
+            Self::Authentication(err) => err.code(),
+        }
+    }
+
+    /// Message that is safe to show to the client. Details of internal
+    /// errors are only logged.
+    pub fn message(&self) -> String {
+        match self {
+            Self::MultipartFormError(MultipartFormDataError::DataTooLargeError(_)) => {
+                "File is too large".to_owned()
+            }
+            Self::MultipartFormError(_) => "Invalid multipart form data".to_owned(),
+            Self::UnsupportedMediaType(message) | Self::Message(message) => message.clone(),
+            Self::RocketError(_) | Self::IOError(_) => "Internal server error".to_owned(),
+            Self::Authentication(err) => err.message(),
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for TransmissionError {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        if let Self::Authentication(err) = self {
+            return err.respond_to(request);
+        }
+        let status = self.status();
+        if status == Status::InternalServerError {
+            log::error!("{} {} failed with error: {:?}", request.method(), request.uri(), self);
+        }
+        error_response(status, self.code(), &self.message()).respond_to(request)
+    }
+}
+
+/// Errors of the OAuth token endpoint, answered in the format of RFC 6749
+/// section 5.2 that clients expect rather than the service's own.
+#[derive(Debug)]
+pub enum OAuthError {
+    InvalidRequest(String),
+    InvalidClient(String),
+    InvalidGrant(String),
+    UnsupportedGrantType(String),
+    Internal(AuthenticationError),
+}
+
+impl OAuthError {
+    pub fn status(&self) -> Status {
+        match self {
+            Self::InvalidClient(_) => Status::Unauthorized,
+            Self::InvalidRequest(_) | Self::InvalidGrant(_) | Self::UnsupportedGrantType(_) => {
+                Status::BadRequest
+            }
+            Self::Internal(err) => err.status(),
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::InvalidRequest(_) => "invalid_request",
+            Self::InvalidClient(_) => "invalid_client",
+            Self::InvalidGrant(_) => "invalid_grant",
+            Self::UnsupportedGrantType(_) => "unsupported_grant_type",
+            Self::Internal(_) => "server_error",
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            Self::InvalidRequest(message)
+            | Self::InvalidClient(message)
+            | Self::InvalidGrant(message)
+            | Self::UnsupportedGrantType(message) => message.clone(),
+            Self::Internal(err) => err.message(),
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for OAuthError {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let status = self.status();
+        if status == Status::InternalServerError {
+            log::error!("{} {} failed with error: {:?}", request.method(), request.uri(), self);
+        }
+        let body = json!({ "error": self.code(), "error_description": self.message() });
+        let mut response = status::Custom(status, body).respond_to(request)?;
+        response.set_header(Header::new("Cache-Control", "no-store"));
+        if let Self::InvalidClient(_) = self {
+            response.set_header(Header::new("WWW-Authenticate", "Basic realm=\"oauth\""));
+        }
+        Ok(response)
+    }
+}
+
+impl From<AuthenticationError> for OAuthError {
+    fn from(error: AuthenticationError) -> Self {
+        Self::Internal(error)
+    }
+}
+
+/// The JSON body every failed request is answered with.
+pub fn error_response(status: Status, code: &str, message: &str) -> status::Custom<Value> {
+    let body = json!({ "success": false, "code": code, "message": message });
+    status::Custom(status, body)
 }
 
 impl From<rocket::Error> for TransmissionError {
@@ -47,3 +323,9 @@ impl From<String> for TransmissionError {
         Self::Message(e)
     }
 }
+
+impl From<AuthenticationError> for TransmissionError {
+    fn from(error: AuthenticationError) -> Self {
+        Self::Authentication(error)
+    }
+}
@@ -0,0 +1,197 @@
+use crate::config::app::AppConfig;
+use crate::config::token::TokenService;
+use crate::handlers::error::AuthenticationError;
+use crate::models::role::{Permission, Role};
+use crate::models::token::Claims;
+use crate::models::user::User;
+use crate::repository::user_repository::UserRepository;
+use mongodb::bson::oid::ObjectId;
+use rocket::http::{RawStr, Status};
+use rocket::request::{FromRequest, Outcome, Request};
+use std::convert::Infallible;
+use std::marker::PhantomData;
+use std::net::IpAddr;
+
+pub const ACCESS_TOKEN_COOKIE: &str = "access_token";
+
+/// Reason an authentication guard rejected the request, picked up by the
+/// `401` and `403` catchers to build their response.
+pub struct AuthFailure(pub Option<String>);
+
+/// A request guard resolving to the signed-in `User`.
+///
+/// The access token is read from an `Authorization: Bearer` header, falling
+/// back to the private session cookie set on sign-in.
+pub struct AuthenticatedUser {
+    pub user: User,
+    pub claims: Claims,
+}
+
+impl AuthenticatedUser {
+    pub(crate) fn access_token(request: &Request<'_>) -> Option<String> {
+        let bearer = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(|token| token.trim().to_owned());
+
+        bearer.or_else(|| {
+            request
+                .cookies()
+                .get_private(ACCESS_TOKEN_COOKIE)
+                .map(|cookie| cookie.value().to_owned())
+        })
+    }
+
+    /// Whether the user's role holds `permission` and the access token allows
+    /// using it.
+    pub fn allows(&self, permission: Permission) -> bool {
+        self.user.user_type.has_permission(permission) && self.claims.allows(permission)
+    }
+
+    pub fn require(&self, permission: Permission) -> Result<(), AuthenticationError> {
+        if self.allows(permission) {
+            Ok(())
+        } else {
+            Err(AuthenticationError::Forbidden(
+                "Access token does not allow this request".to_owned(),
+            ))
+        }
+    }
+
+    async fn authenticate(request: &Request<'_>) -> Result<Self, AuthenticationError> {
+        let token = Self::access_token(request).ok_or_else(|| {
+            AuthenticationError::Unauthorized("Missing access token".to_owned())
+        })?;
+        let config = request
+            .rocket()
+            .state::<AppConfig>()
+            .expect("AppConfig not managed");
+        let claims = TokenService::new(&config.jwt).verify_access_token(&token)?;
+
+        let user_id = ObjectId::with_string(&claims.sub)
+            .map_err(|err| AuthenticationError::Unauthorized(err.to_string()))?;
+        let users = request
+            .rocket()
+            .state::<Box<dyn UserRepository>>()
+            .expect("UserRepository not managed");
+        let user = users
+            .find_by_id(&user_id)
+            .await?
+            .ok_or_else(|| AuthenticationError::Unauthorized("User no longer exists".to_owned()))?;
+
+        // Tokens issued before e.g. a password reset are no longer valid
+        if claims.ver != user.token_version {
+            return Err(AuthenticationError::Unauthorized(
+                "Access token has been revoked".to_owned(),
+            ));
+        }
+
+        Ok(Self { user, claims })
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AuthenticatedUser {
+    type Error = AuthenticationError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match Self::authenticate(request).await {
+            Ok(authenticated) => Outcome::Success(authenticated),
+            Err(err) => {
+                let reason = match &err {
+                    AuthenticationError::Unauthorized(message) => message.clone(),
+                    _ => "Invalid access token".to_owned(),
+                };
+                request.local_cache(|| AuthFailure(Some(reason)));
+                Outcome::Failure((Status::Unauthorized, err))
+            }
+        }
+    }
+}
+
+/// A request guard that only succeeds for signed-in users holding role `R`,
+/// e.g. `RequireRole<Admin>`.
+pub struct RequireRole<R: Role> {
+    role: PhantomData<R>,
+}
+
+#[rocket::async_trait]
+impl<'r, R: Role> FromRequest<'r> for RequireRole<R> {
+    type Error = AuthenticationError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let authenticated = rocket::outcome::try_outcome!(request.guard::<AuthenticatedUser>().await);
+        let reason = if !R::allows(&authenticated.user.user_type) {
+            format!("{} role required", R::NAME)
+        } else if !authenticated.allows(R::PERMISSION) {
+            "Access token does not allow this request".to_owned()
+        } else {
+            return Outcome::Success(Self { role: PhantomData });
+        };
+        request.local_cache(|| AuthFailure(Some(reason.clone())));
+        Outcome::Failure((Status::Forbidden, AuthenticationError::Forbidden(reason)))
+    }
+}
+
+/// Where a request came from, as recorded in the audit log.
+pub struct ClientInfo {
+    pub ip: Option<IpAddr>,
+    pub user_agent: Option<String>,
+}
+
+impl ClientInfo {
+    /// Address of the client. `X-Real-IP` is only believed when the request
+    /// comes from one of `AppConfig.trusted_proxies`, since anyone else can
+    /// send whatever they like in it.
+    pub(crate) fn address(request: &Request<'_>) -> Option<IpAddr> {
+        let remote = request.remote()?.ip();
+        let trusted = request
+            .rocket()
+            .state::<AppConfig>()
+            .is_some_and(|config| config.trusted_proxies.contains(&remote));
+        match request.real_ip() {
+            Some(real_ip) if trusted => Some(real_ip),
+            _ => Some(remote),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ClientInfo {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(Self {
+            ip: Self::address(request),
+            user_agent: request.headers().get_one("User-Agent").map(str::to_owned),
+        })
+    }
+}
+
+/// OAuth client credentials sent with HTTP Basic authentication, RFC 6749
+/// section 2.3.1. `None` if the request has no `Authorization: Basic`
+/// header or it cannot be decoded.
+pub struct ClientCredentials(pub Option<(String, String)>);
+
+impl ClientCredentials {
+    fn decode(header: &str) -> Option<(String, String)> {
+        let encoded = header.strip_prefix("Basic ")?;
+        let decoded = String::from_utf8(base64::decode(encoded.trim()).ok()?).ok()?;
+        let (client_id, client_secret) = decoded.split_once(':')?;
+        // Both parts are form-urlencoded before being joined
+        let client_id = RawStr::new(client_id).url_decode().ok()?;
+        let client_secret = RawStr::new(client_secret).url_decode().ok()?;
+        Some((client_id.into_owned(), client_secret.into_owned()))
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ClientCredentials {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let credentials = request.headers().get_one("Authorization").and_then(Self::decode);
+        Outcome::Success(Self(credentials))
+    }
+}
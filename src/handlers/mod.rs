@@ -1 +1,6 @@
-pub mod error;
\ No newline at end of file
+pub mod body;
+pub mod error;
+pub mod guard;
+// The `rate_limited` route emits a `uri!` helper re-export that is unused.
+#[allow(unused_imports)]
+pub mod rate_limit;
@@ -0,0 +1,220 @@
+use crate::config::app::AppConfig;
+use crate::config::token::TokenService;
+use crate::handlers::error::error_response;
+use crate::handlers::guard::{AuthenticatedUser, ClientInfo};
+use rocket::fairing::{self, Fairing, Info, Kind};
+use rocket::http::uri::Origin;
+use rocket::http::{Header, Method, Status};
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::status;
+use rocket::{Build, Data, Response, Rocket};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Path rate limited requests are rerouted to, answered by `rate_limited`.
+const RATE_LIMITED_PATH: &str = "/__rate_limited";
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RateLimitConfig {
+    /// Clients tracked at once across all groups. Past it the client idle
+    /// for the longest is forgotten.
+    pub max_buckets: usize,
+    pub groups: Vec<RateLimitGroup>,
+}
+
+/// Requests under `prefix` allowed per client in each `period`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RateLimitGroup {
+    /// Path prefix such as `/auth`, matched on whole segments
+    pub prefix: String,
+    /// Requests a client may make in a burst
+    pub limit: u32,
+    /// Seconds it takes to earn back `limit` requests
+    pub period: u64,
+}
+
+impl RateLimitGroup {
+    /// Whether the percent-decoded, non-empty path `segments` start with
+    /// those of the prefix, the way the router sees them.
+    fn matches(&self, segments: &[&str]) -> bool {
+        let mut segments = segments.iter();
+        self.prefix
+            .split('/')
+            .filter(|prefix| !prefix.is_empty())
+            .all(|prefix| segments.next() == Some(&prefix))
+    }
+
+    /// Requests earned back per second.
+    fn rate(&self) -> f64 {
+        self.limit as f64 / self.period as f64
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    /// `RateLimitGroup.period`, after which an untouched bucket is full
+    period: u64,
+}
+
+/// Outcome of the rate limit check of a request, reported in its headers.
+#[derive(Debug, Clone)]
+struct RateLimitDecision {
+    limit: u32,
+    remaining: u32,
+    /// Seconds until the bucket is full again
+    reset: u64,
+    /// Seconds until the next request is allowed, if this one was not
+    retry_after: Option<u64>,
+}
+
+/// Token bucket rate limiting of the configured route groups.
+///
+/// Requests are counted per signed-in user, or per client address for
+/// anonymous ones. Every limited response carries `RateLimit-Limit`,
+/// `RateLimit-Remaining` and `RateLimit-Reset` headers; once a client has no
+/// requests left it is answered with a JSON `429` and `Retry-After`.
+#[derive(Default)]
+pub struct RateLimiter {
+    buckets: Mutex<HashMap<(String, String), Bucket>>,
+}
+
+impl RateLimiter {
+    /// Who the request is counted for.
+    fn client_key(request: &Request<'_>, config: &AppConfig) -> String {
+        let user = AuthenticatedUser::access_token(request).and_then(|token| {
+            TokenService::new(&config.jwt)
+                .verify_access_token(&token)
+                .ok()
+        });
+        match (user, ClientInfo::address(request)) {
+            (Some(claims), _) => format!("user:{}", claims.sub),
+            (None, Some(ip)) => format!("ip:{}", ip),
+            (None, None) => "anonymous".to_owned(),
+        }
+    }
+
+    fn take(
+        &self,
+        group: &RateLimitGroup,
+        client_key: String,
+        max_buckets: usize,
+    ) -> RateLimitDecision {
+        let now = Instant::now();
+        let limit = group.limit as f64;
+        let key = (group.prefix.clone(), client_key);
+        let mut buckets = self.buckets.lock().unwrap();
+        if !buckets.contains_key(&key) && buckets.len() >= max_buckets {
+            // A full bucket counts the same as none at all
+            buckets.retain(|_, bucket| now.duration_since(bucket.updated).as_secs() < bucket.period);
+            if buckets.len() >= max_buckets {
+                let oldest = buckets
+                    .iter()
+                    .min_by_key(|(_, bucket)| bucket.updated)
+                    .map(|(key, _)| key.clone());
+                if let Some(oldest) = oldest {
+                    buckets.remove(&oldest);
+                }
+            }
+        }
+
+        let bucket = buckets
+            .entry(key)
+            .or_insert(Bucket {
+                tokens: limit,
+                updated: now,
+                period: group.period,
+            });
+        let earned = now.duration_since(bucket.updated).as_secs_f64() * group.rate();
+        bucket.tokens = (bucket.tokens + earned).min(limit);
+        bucket.updated = now;
+
+        let retry_after = if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            None
+        } else {
+            Some(((1.0 - bucket.tokens) / group.rate()).ceil() as u64)
+        };
+        RateLimitDecision {
+            limit: group.limit,
+            remaining: bucket.tokens.floor() as u32,
+            reset: ((limit - bucket.tokens) / group.rate()).ceil() as u64,
+            retry_after,
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for RateLimiter {
+    fn info(&self) -> Info {
+        Info {
+            name: "Rate limiting",
+            kind: Kind::Ignite | Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        Ok(rocket.mount("/", routes![rate_limited]))
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        let config = match request.rocket().state::<AppConfig>() {
+            Some(config) => config,
+            None => return,
+        };
+        let segments: Vec<&str> = request.uri().path().segments().collect();
+        let group = match config.rate_limit.groups.iter().find(|group| group.matches(&segments)) {
+            Some(group) => group,
+            None => return,
+        };
+
+        let client_key = Self::client_key(request, config);
+        let decision = self.take(group, client_key, config.rate_limit.max_buckets);
+        let limited = decision.retry_after.is_some();
+        request.local_cache(|| Some(decision));
+        if limited {
+            request.set_method(Method::Get);
+            request.set_uri(Origin::parse(RATE_LIMITED_PATH).unwrap());
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let decision = match request.local_cache(|| None::<RateLimitDecision>) {
+            Some(decision) => decision,
+            None => return,
+        };
+        response.set_header(Header::new("RateLimit-Limit", decision.limit.to_string()));
+        response.set_header(Header::new("RateLimit-Remaining", decision.remaining.to_string()));
+        response.set_header(Header::new("RateLimit-Reset", decision.reset.to_string()));
+        if let Some(retry_after) = decision.retry_after {
+            response.set_header(Header::new("Retry-After", retry_after.to_string()));
+        }
+    }
+}
+
+/// Present on requests the rate limiter rerouted to `rate_limited`.
+struct RateLimited;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RateLimited {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        match request.local_cache(|| None::<RateLimitDecision>) {
+            Some(decision) if decision.retry_after.is_some() => request::Outcome::Success(RateLimited),
+            _ => request::Outcome::Forward(()),
+        }
+    }
+}
+
+#[get("/__rate_limited")]
+fn rate_limited(_limited: RateLimited) -> status::Custom<Value> {
+    error_response(
+        Status::TooManyRequests,
+        "rate_limited",
+        "Too many requests, try again later",
+    )
+}
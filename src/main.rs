@@ -8,17 +8,27 @@ mod config;
 mod controller;
 mod handlers;
 mod models;
+mod repository;
 mod services;
 mod utils;
+mod views;
 
 use rocket::{
-    fairing::{Fairing, Info, Kind},
-    http::{ContentType, Header, Status},
+    fairing::{AdHoc, Fairing, Info, Kind},
+    figment::Figment,
+    http::{Header, Status},
     response::status,
-    Request, Response,
+    Build, Request, Response, Rocket,
 };
 use serde_json::{json, Value};
 
+use config::app::{AllowedOrigin, AppConfig};
+use handlers::body::BodyErrors;
+use handlers::error::error_response;
+use handlers::guard::AuthFailure;
+use handlers::rate_limit::RateLimiter;
+use repository::Repositories;
+
 #[get("/")]
 fn api_home() -> status::Custom<Value> {
     let message = json!({"success": true, "message": "Authentication Server"});
@@ -26,24 +36,52 @@ fn api_home() -> status::Custom<Value> {
 }
 
 #[get("/files")]
-pub fn file_home() -> (ContentType, &'static str) {
-    let html = r#"<html>
-      <body>
-        <form method="post" enctype="multipart/form-data">
-            <input type="file" name="somefile"/>
-            <!-- <input type="text" name="username"/> -->
-            <!-- <input type="file" name="somefile"/> -->
-            <button type="submit">Submit</button>
-        </form>
-      </body>
-    </html>"#;
-    (ContentType::HTML, html)
+pub fn file_home() -> views::Page {
+    views::files::upload_page()
+}
+
+#[catch(400)]
+fn bad_request() -> status::Custom<Value> {
+    error_response(Status::BadRequest, "bad_request", "Malformed request")
 }
 
 #[catch(404)]
 fn not_found() -> status::Custom<Value> {
-    let message = json!({ "success": false, "message": "Not found!" });
-    status::Custom(Status::NotFound, message)
+    error_response(Status::NotFound, "not_found", "Not found!")
+}
+
+#[catch(401)]
+fn unauthorized(request: &Request<'_>) -> status::Custom<Value> {
+    let AuthFailure(reason) = request.local_cache(|| AuthFailure(None));
+    let reason = reason.as_deref().unwrap_or("Authentication required");
+    error_response(Status::Unauthorized, "unauthorized", &format!("Unauthorized: {}", reason))
+}
+
+#[catch(403)]
+fn forbidden(request: &Request<'_>) -> status::Custom<Value> {
+    let AuthFailure(reason) = request.local_cache(|| AuthFailure(None));
+    let reason = reason.as_deref().unwrap_or("Access denied");
+    error_response(Status::Forbidden, "forbidden", &format!("Forbidden: {}", reason))
+}
+
+#[catch(413)]
+fn payload_too_large() -> status::Custom<Value> {
+    error_response(Status::PayloadTooLarge, "payload_too_large", "Request body is too large")
+}
+
+#[catch(422)]
+fn unprocessable_entity(request: &Request<'_>) -> status::Custom<Value> {
+    let mut response =
+        error_response(Status::UnprocessableEntity, "invalid_request", "Request body could not be parsed");
+    if let BodyErrors(Some(errors)) = request.local_cache(|| BodyErrors(None)) {
+        response.1["errors"] = errors.clone();
+    }
+    response
+}
+
+#[catch(500)]
+fn internal_error() -> status::Custom<Value> {
+    error_response(Status::InternalServerError, "internal_error", "Internal server error")
 }
 
 pub struct CORS;
@@ -57,74 +95,385 @@ impl Fairing for CORS {
         }
     }
 
-    async fn on_response<'r>(&self, _request: &'r Request<'_>, response: &mut Response<'r>) {
-        response.set_header(Header::new("Access-Control-Allow-Origin", "*"));
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let origin = request.headers().get_one("Origin");
+        let allowed = request
+            .rocket()
+            .state::<AppConfig>()
+            .and_then(|config| config.cors.allow_origin(origin));
+        match allowed {
+            Some(AllowedOrigin::Exact(origin)) => {
+                response.set_header(Header::new("Access-Control-Allow-Origin", origin));
+                response.set_header(Header::new("Access-Control-Allow-Credentials", "true"));
+                response.set_header(Header::new("Vary", "Origin"));
+            }
+            Some(AllowedOrigin::Any) => {
+                response.set_header(Header::new("Access-Control-Allow-Origin", "*"));
+                response.set_header(Header::new("Vary", "Origin"));
+            }
+            None => {}
+        }
         response.set_header(Header::new(
             "Access-Control-Allow-Methods",
             "POST, GET, PATCH, OPTIONS",
         ));
         response.set_header(Header::new("Access-Control-Allow-Headers", "*"));
-        response.set_header(Header::new("Access-Control-Allow-Credentials", "true"));
     }
 }
 
-#[launch]
-async fn rocket() -> _ {
-    rocket::build()
+/// The server configured from `figment`, without a mailer, which is added by
+/// `rocket()` or by the tests.
+fn build(figment: Figment) -> Rocket<Build> {
+    rocket::custom(figment)
         .mount(
             "/",
             routes![
                 api_home,
                 file_home,
+                controller::userinfo,
+                controller::userinfo_post,
             ],
         )
         .mount(
             "/auth",
             routes![
                 controller::sign_in,
+                controller::refresh,
                 controller::sign_up,
+                controller::verify_email,
+                controller::resend_verification,
+                controller::forgot_password,
+                controller::reset_password,
+                controller::change_password,
+                controller::verify_mfa,
+                controller::enroll_mfa,
+                controller::confirm_mfa,
+                controller::regenerate_recovery_codes,
+                controller::get_profile,
+                controller::update_profile,
+                controller::upload_avatar,
                 controller::find_user,
                 controller::delete_user,
                 controller::get_user_tags,
             ],
         )
+        .mount(
+            "/admin",
+            routes![
+                controller::list_users,
+                controller::unlock_user,
+                controller::list_audit_events,
+                controller::verify_audit_log,
+                controller::export_audit_log,
+                controller::register_oauth_client,
+                controller::list_oauth_clients,
+            ],
+        )
+        .mount(
+            "/.well-known",
+            routes![
+                controller::openid_configuration,
+                controller::jwks,
+            ],
+        )
+        .mount(
+            "/oauth",
+            routes![
+                controller::authorize,
+                controller::approve,
+                controller::token,
+            ],
+        )
         .mount(
             "/files",
             routes![
                 controller::upload_file,
                 controller::download_file,
+                controller::download_avatar,
             ],
         )
+        .attach(AppConfig::fairing())
+        .attach(Repositories)
+        .attach(RateLimiter::default())
         .attach(CORS)
-        .register("/", catchers![not_found])
+        .register("/", catchers![
+            bad_request,
+            not_found,
+            unauthorized,
+            forbidden,
+            payload_too_large,
+            unprocessable_entity,
+            internal_error,
+        ])
+}
+
+#[launch]
+async fn rocket() -> _ {
+    build(AppConfig::figment()).attach(AdHoc::on_ignite("Mailer", |rocket| async {
+        let mailer = rocket
+            .state::<AppConfig>()
+            .map(|config| services::mail_service::mailer_from_config(&config.mail));
+        match mailer {
+            Some(mailer) => rocket.manage(mailer),
+            None => rocket,
+        }
+    }))
 }
 
 
-/// Note: Run the test using a single thread
-/// cargo test -- --test-threads=1
+/// Every test runs against its own in-memory repositories, no database is
+/// needed.
 #[cfg(test)]
 mod test {
-    use super::rocket;
+    use super::build;
+    use crate::config::app::AppConfig;
+    use crate::config::token::TokenService;
+    use crate::handlers::error::AuthenticationError;
+    use crate::models::login_attempt::LoginAttempts;
+    use crate::models::oauth::Scope;
+    use crate::models::user::{LoginUser, UserChanges, UserType};
+    use crate::repository::login_attempt_repository::{
+        InMemoryLoginAttemptRepository, LoginAttemptRepository,
+    };
+    use crate::repository::refresh_token_repository::RefreshTokenRepository;
+    use crate::repository::user_repository::UserRepository;
+    use crate::services::mail_service::{Email, Mailer};
+    use crate::services::user_service::UserService;
+    use chrono::NaiveDateTime;
+    use mongodb::bson::oid::ObjectId;
+    use rocket::figment::{providers::Serialized, Figment};
     use rocket::http::{ContentType, Header, Status};
     use rocket::local::asynchronous::Client;
+    use rocket::{Build, Rocket};
+    use sha1::{Digest, Sha1};
+    use std::sync::{Arc, Mutex};
     const REQ_BODY_SIGN_UP: &str = "first_name=kakashi&last_name=hatake&user_type=Customer&email_id=kakashi@gmail.com&password=12!@qwer&user_tags[0]=WebDevelopment&user_tags[1]=MobileDevelopment";
     const REQ_BODY_LOG_IN: &str = "username=kakashi@gmail.com&password=12!@qwer";
     const REQ_BODY_DEL_USER: &str = r#"{
         "username": "kakashi@gmail.com"
     }"#;
 
+    /// Mail sent by the server under test
+    #[derive(Clone, Default)]
+    struct Outbox(Arc<Mutex<Vec<Email>>>);
+
+    #[rocket::async_trait]
+    impl Mailer for Outbox {
+        async fn send(&self, email: Email) -> Result<(), AuthenticationError> {
+            self.0.lock().unwrap().push(email);
+            Ok(())
+        }
+    }
+
+    fn test_figment() -> Figment {
+        AppConfig::figment().merge(Serialized::global("database.backend", "memory"))
+    }
+
+    fn test_rocket() -> Rocket<Build> {
+        test_rocket_from(test_figment())
+    }
+
+    fn test_rocket_from(figment: Figment) -> Rocket<Build> {
+        let outbox = Outbox::default();
+        build(figment)
+            .manage(Arc::new(outbox.clone()) as Arc<dyn Mailer>)
+            .manage(outbox)
+    }
+
+    /// Returns the link to `path` from the most recent email sent to the test
+    /// user containing one
+    fn mailed_link(client: &Client, path: &str) -> String {
+        let outbox = client.rocket().state::<Outbox>().unwrap();
+        let sent = outbox.0.lock().unwrap();
+        sent.iter()
+            .rev()
+            .filter(|email| email.to == "kakashi@gmail.com")
+            .find_map(|email| {
+                let link_start = email.body.find(path)?;
+                Some(email.body[link_start..].split_whitespace().next()?.to_owned())
+            })
+            .expect("email with link in outbox")
+    }
+
+    /// Like `mailed_link`, for mail that is sent in the background
+    async fn awaited_mailed_link(client: &Client, path: &str) -> String {
+        let outbox = client.rocket().state::<Outbox>().unwrap();
+        for _ in 0..100 {
+            let sent = outbox.0.lock().unwrap().iter().any(|email| {
+                email.to == "kakashi@gmail.com" && email.body.contains(path)
+            });
+            if sent {
+                break;
+            }
+            rocket::tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        mailed_link(client, path)
+    }
+
+    /// A client connecting from `ip`
+    fn peer(ip: &str) -> std::net::SocketAddr {
+        std::net::SocketAddr::new(ip.parse().unwrap(), 50000)
+    }
+
+    /// Confirms the test user's email address using the emailed link
+    async fn verify_test_user(client: &Client) {
+        let link = mailed_link(client, "/auth/verify-email?");
+        let response = client.get(link).dispatch();
+        assert_eq!(response.await.status(), Status::Ok);
+    }
+
+    /// The id of the signed up test user
+    async fn test_user_id(client: &Client) -> ObjectId {
+        let users = client.rocket().state::<Box<dyn UserRepository>>().unwrap();
+        let user = users.find_by_email("kakashi@gmail.com").await.unwrap();
+        user.and_then(|user| user.user_id).expect("signed up test user")
+    }
+
+    /// Changes the role of the test user. Staff accounts are provisioned
+    /// directly in the db.
+    async fn set_test_user_type(client: &Client, user_type: UserType) {
+        let users = client.rocket().state::<Box<dyn UserRepository>>().unwrap();
+        let changes = UserChanges {
+            user_type: Some(user_type),
+            ..Default::default()
+        };
+        users.update(&test_user_id(client).await, changes).await.unwrap();
+    }
+
+    /// Signs up the test user with a verified email and the role
+    /// `user_type`, then signs them in and returns the `Authorization` header
+    async fn signed_in_user(client: &Client, user_type: UserType) -> Header<'static> {
+        let response = client
+            .post("/auth/sign-up")
+            .header(ContentType::Form)
+            .body(REQ_BODY_SIGN_UP)
+            .dispatch();
+        assert_eq!(response.await.status(), Status::Ok);
+        verify_test_user(client).await;
+        if user_type != UserType::Customer {
+            set_test_user_type(client, user_type).await;
+        }
+        sign_in_header(client).await
+    }
+
+    /// Enables two-factor authentication for the signed-in user, returning
+    /// the TOTP secret and the recovery codes
+    async fn enable_totp(client: &Client, auth: &Header<'static>) -> (String, Vec<String>) {
+        let response = client.post("/auth/mfa/enroll").header(auth.clone()).dispatch();
+        let content = response.await.into_string().await.unwrap();
+        let json_body: serde_json::Value = serde_json::from_str(&content).unwrap();
+        let secret = json_body["data"]["secret"].as_str().unwrap().to_owned();
+
+        let code = crate::config::totp::TotpService::code(&secret, chrono::Utc::now().timestamp());
+        let response = client
+            .post("/auth/mfa/confirm")
+            .header(ContentType::Form)
+            .header(auth.clone())
+            .body(format!("code={}", code.unwrap()))
+            .dispatch();
+        let content = response.await.into_string().await.unwrap();
+        let json_body: serde_json::Value = serde_json::from_str(&content).unwrap();
+        let recovery_codes = serde_json::from_value(json_body["data"]["recovery_codes"].clone());
+        (secret, recovery_codes.unwrap())
+    }
+
+    /// Signs in the test user and returns the matching `Authorization` header
+    async fn sign_in_header(client: &Client) -> Header<'static> {
+        let response = client
+            .post("/auth/sign-in")
+            .header(ContentType::Form)
+            .body(REQ_BODY_LOG_IN)
+            .dispatch()
+            .await;
+        let content = response.into_string().await.unwrap();
+        let json_body: serde_json::Value = serde_json::from_str(&content).unwrap();
+        let token = json_body["data"]["access_token"].as_str().unwrap();
+        Header::new("Authorization", format!("Bearer {}", token))
+    }
+
+    /// Checks the signature of `token` against the published key set and
+    /// returns its claims
+    async fn verify_with_jwks(client: &Client, token: &str) -> serde_json::Value {
+        let response = client.get("/.well-known/jwks.json").dispatch();
+        let content = response.await.into_string().await.unwrap();
+        let jwks: serde_json::Value = serde_json::from_str(&content).unwrap();
+        let decode = |part: &str| base64::decode_config(part, base64::URL_SAFE_NO_PAD).unwrap();
+
+        let parts: Vec<&str> = token.split('.').collect();
+        let header: serde_json::Value = serde_json::from_slice(&decode(parts[0])).unwrap();
+        let jwk = jwks["keys"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|key| key["kid"] == header["kid"])
+            .expect("signing key in key set");
+        assert_eq!(header["alg"], "EdDSA");
+        assert_eq!(jwk["crv"], "Ed25519");
+        let public_key = decode(jwk["x"].as_str().unwrap());
+        ring::signature::UnparsedPublicKey::new(&ring::signature::ED25519, public_key)
+            .verify(format!("{}.{}", parts[0], parts[1]).as_bytes(), &decode(parts[2]))
+            .expect("valid signature");
+        serde_json::from_slice(&decode(parts[1])).unwrap()
+    }
+
     #[rocket::async_test]
     async fn it_works_with_correct_status_for_api_home_route() {
-        let client = Client::tracked(rocket().await)
+        let client = Client::tracked(test_rocket())
             .await
             .expect("valid rocket instance");
         let response = client.get("/").dispatch();
         assert_eq!(response.await.status(), Status::Ok);
     }
 
+    #[rocket::async_test]
+    async fn invalid_configuration_aborts_launch() {
+        let figment = test_figment()
+            .merge(Serialized::global("jwt.access_token_lifetime", 0))
+            .merge(Serialized::global("public_base_url", "not a url"));
+        let error = Client::tracked(build(figment)).await.err().unwrap();
+        assert!(matches!(
+            error.kind(),
+            rocket::error::ErrorKind::FailedFairings(_)
+        ));
+    }
+
+    #[rocket::async_test]
+    async fn cors_allows_credentials_only_for_listed_origins() {
+        let client = Client::tracked(test_rocket())
+            .await
+            .expect("valid rocket instance");
+        let origin = Header::new("Origin", "https://elsewhere.example.com");
+        let response = client.get("/").header(origin).dispatch().await;
+        let headers = response.headers();
+        assert_eq!(headers.get_one("Access-Control-Allow-Origin"), Some("*"));
+        assert_eq!(headers.get_one("Access-Control-Allow-Credentials"), None);
+
+        let figment = test_figment().merge(Serialized::global(
+            "cors.allowed_origins",
+            ["https://app.example.com"],
+        ));
+        let client = Client::tracked(test_rocket_from(figment))
+            .await
+            .expect("valid rocket instance");
+        let origin = Header::new("Origin", "https://app.example.com");
+        let response = client.get("/").header(origin).dispatch().await;
+        let headers = response.headers();
+        assert_eq!(
+            headers.get_one("Access-Control-Allow-Origin"),
+            Some("https://app.example.com")
+        );
+        assert_eq!(headers.get_one("Access-Control-Allow-Credentials"), Some("true"));
+
+        let origin = Header::new("Origin", "https://elsewhere.example.com");
+        let response = client.get("/").header(origin).dispatch().await;
+        let headers = response.headers();
+        assert_eq!(headers.get_one("Access-Control-Allow-Origin"), None);
+        assert_eq!(headers.get_one("Access-Control-Allow-Credentials"), None);
+    }
+
     #[rocket::async_test]
     async fn correct_response_for_page_not_found() {
-        let client = Client::tracked(rocket().await)
+        let client = Client::tracked(test_rocket())
             .await
             .expect("valid rocket instance");
         let response = client.get("/path-does-not-exist").dispatch();
@@ -134,21 +483,24 @@ mod test {
     #[rocket::async_test]
     async fn it_works_for_sign_up_and_delete_user_once_signed_up() {
         let content_type =
-            Header::new("Content-Type", format!("application/x-www-form-urlencoded"));
-        let client = Client::tracked(rocket().await)
+            Header::new("Content-Type", "application/x-www-form-urlencoded");
+        let client = Client::tracked(test_rocket())
             .await
             .expect("valid rocket instance");
         let response = client
             .post("/auth/sign-up")
             .header(content_type.clone())
-            .body(REQ_BODY_SIGN_UP.clone())
+            .body(REQ_BODY_SIGN_UP)
             .dispatch();
         assert_eq!(response.await.status(), Status::Ok);
+        verify_test_user(&client).await;
 
+        let auth = sign_in_header(&client).await;
         let response = client
             .post("/auth/delete-user")
             .header(ContentType::JSON)
-            .body(REQ_BODY_DEL_USER.clone())
+            .header(auth)
+            .body(REQ_BODY_DEL_USER)
             .dispatch();
         assert_eq!(response.await.status(), Status::Ok);
     }
@@ -158,150 +510,765 @@ mod test {
         let content_type = Header::new(
             "Content-Type",
             // format!("multipart/form-data; boundary={}", BOUNDARY),
-            format!("application/x-www-form-urlencoded"),
+            "application/x-www-form-urlencoded",
         );
         let accept = Header::new("Accept", "application/json");
 
-        let client = Client::tracked(rocket().await)
+        let client = Client::tracked(test_rocket())
             .await
             .expect("valid rocket instance");
 
         let response = client
             .post("/auth/sign-up")
             .header(content_type.clone())
-            .body(REQ_BODY_SIGN_UP.clone())
+            .body(REQ_BODY_SIGN_UP)
             .dispatch();
         // println!("sign-up {:?}", response.await);
         assert_eq!(response.await.status(), Status::Ok);
+        verify_test_user(&client).await;
 
         let response = client
             .post("/auth/sign-in")
             .header(content_type)
             .header(accept)
-            .body(REQ_BODY_LOG_IN.clone())
-            .dispatch();
-        assert_eq!(response.await.status(), Status::Ok);
+            .body(REQ_BODY_LOG_IN)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
 
+        let content = response.into_string().await.unwrap();
+        let json_body: serde_json::Value = serde_json::from_str(&content).unwrap();
+        let data = json_body.get("data").unwrap();
+        assert_eq!(data.get("token_type").unwrap(), "Bearer");
+        assert!(data.get("access_token").unwrap().is_string());
+        assert!(data.get("user").unwrap().get("password").is_none());
+
+        let auth = sign_in_header(&client).await;
         let response = client
             .post("/auth/delete-user")
             .header(ContentType::JSON)
-            .body(REQ_BODY_DEL_USER.clone())
+            .header(auth)
+            .body(REQ_BODY_DEL_USER)
             .dispatch();
         assert_eq!(response.await.status(), Status::Ok);
     }
 
     #[rocket::async_test]
-    async fn correct_error_response_if_user_already_registered() {
-        let content_type =
-            Header::new("Content-Type", format!("application/x-www-form-urlencoded"));
+    async fn sign_in_requires_a_verified_email() {
+        let client = Client::tracked(test_rocket())
+            .await
+            .expect("valid rocket instance");
+
+        let response = client
+            .post("/auth/sign-up")
+            .header(ContentType::Form)
+            .body(REQ_BODY_SIGN_UP)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let content = response.into_string().await.unwrap();
+        let json_body: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(json_body["data"]["email_verified"], false);
+
+        let response = client
+            .post("/auth/sign-in")
+            .header(ContentType::Form)
+            .body(REQ_BODY_LOG_IN)
+            .dispatch();
+        assert_eq!(response.await.status(), Status::Forbidden);
 
-        let client = Client::tracked(rocket().await)
+        verify_test_user(&client).await;
+        sign_in_header(&client).await;
+    }
+
+    #[rocket::async_test]
+    async fn verify_email_rejects_invalid_tokens() {
+        let client = Client::tracked(test_rocket())
             .await
             .expect("valid rocket instance");
 
+        let response = client.get("/auth/verify-email?token=not-a-token").dispatch();
+        assert_eq!(response.await.status(), Status::BadRequest);
+    }
+
+    #[rocket::async_test]
+    async fn password_reset_links_are_mailed_in_the_background() {
+        /// Takes a while to deliver every email
+        struct SlowMailer(Outbox);
+
+        #[rocket::async_trait]
+        impl Mailer for SlowMailer {
+            async fn send(&self, email: Email) -> Result<(), AuthenticationError> {
+                rocket::tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+                self.0.send(email).await
+            }
+        }
+
+        let outbox = Outbox::default();
+        let rocket = build(test_figment())
+            .manage(Arc::new(SlowMailer(outbox.clone())) as Arc<dyn Mailer>)
+            .manage(outbox);
+        let client = Client::tracked(rocket).await.expect("valid rocket instance");
         let response = client
             .post("/auth/sign-up")
-            .header(content_type.clone())
-            .body(REQ_BODY_SIGN_UP.clone())
+            .header(ContentType::Form)
+            .body(REQ_BODY_SIGN_UP)
+            .dispatch();
+        assert_eq!(response.await.status(), Status::Ok);
+
+        // Known addresses are answered without waiting for the mail
+        let started = std::time::Instant::now();
+        let response = client
+            .post("/auth/forgot-password")
+            .header(ContentType::Form)
+            .body("email_id=kakashi@gmail.com")
             .dispatch();
         assert_eq!(response.await.status(), Status::Ok);
+        assert!(started.elapsed() < std::time::Duration::from_millis(500));
+        awaited_mailed_link(&client, "/auth/reset-password?").await;
+    }
+
+    #[rocket::async_test]
+    async fn password_reset_is_single_use_and_revokes_sessions() {
+        let client = Client::tracked(test_rocket())
+            .await
+            .expect("valid rocket instance");
 
         let response = client
             .post("/auth/sign-up")
-            .header(content_type.clone())
-            .body(REQ_BODY_SIGN_UP.clone())
+            .header(ContentType::Form)
+            .body(REQ_BODY_SIGN_UP)
             .dispatch();
-        assert_eq!(response.await.status(), Status::NotImplemented);
+        assert_eq!(response.await.status(), Status::Ok);
+        verify_test_user(&client).await;
+        let old_auth = sign_in_header(&client).await;
 
         let response = client
-            .post("/auth/delete-user")
+            .post("/auth/forgot-password")
+            .header(ContentType::Form)
+            .body("email_id=kakashi@gmail.com")
+            .dispatch();
+        assert_eq!(response.await.status(), Status::Ok);
+
+        let link = awaited_mailed_link(&client, "/auth/reset-password?").await;
+        let token = link.split("token=").nth(1).unwrap().to_owned();
+        let req_body_reset = format!("token={}&new_password=n3w!p4ssword", token);
+        let response = client
+            .post("/auth/reset-password")
+            .header(ContentType::Form)
+            .body(req_body_reset.clone())
+            .dispatch();
+        assert_eq!(response.await.status(), Status::Ok);
+
+        // Reset links only work once
+        let response = client
+            .post("/auth/reset-password")
+            .header(ContentType::Form)
+            .body(req_body_reset)
+            .dispatch();
+        assert_eq!(response.await.status(), Status::BadRequest);
+
+        // Existing sessions and the old password stop working
+        let response = client
+            .post("/auth/find-user")
             .header(ContentType::JSON)
-            .body(REQ_BODY_DEL_USER.clone())
+            .header(old_auth)
+            .body(REQ_BODY_DEL_USER)
+            .dispatch();
+        assert_eq!(response.await.status(), Status::Unauthorized);
+
+        let response = client
+            .post("/auth/sign-in")
+            .header(ContentType::Form)
+            .body(REQ_BODY_LOG_IN)
+            .dispatch();
+        assert_eq!(response.await.status(), Status::Unauthorized);
+
+        let response = client
+            .post("/auth/sign-in")
+            .header(ContentType::Form)
+            .body("username=kakashi@gmail.com&password=n3w!p4ssword")
             .dispatch();
         assert_eq!(response.await.status(), Status::Ok);
     }
 
     #[rocket::async_test]
-    async fn correct_error_for_user_sign_in_without_sign_up() {
-        let content_type =
-            Header::new("Content-Type", format!("application/x-www-form-urlencoded"));
+    async fn passwords_are_hashed_with_a_salt_of_their_own() {
+        let client = Client::tracked(test_rocket())
+            .await
+            .expect("valid rocket instance");
+        let users = client.rocket().state::<Box<dyn UserRepository>>().unwrap();
+
+        for email_id in ["kakashi@gmail.com", "obito@gmail.com"] {
+            let response = client
+                .post("/auth/sign-up")
+                .header(ContentType::Form)
+                .body(REQ_BODY_SIGN_UP.replace("kakashi@gmail.com", email_id))
+                .dispatch();
+            assert_eq!(response.await.status(), Status::Ok);
+        }
+        let mut hashes = vec![];
+        for email_id in ["kakashi@gmail.com", "obito@gmail.com"] {
+            let user = users.find_by_email(email_id).await.unwrap().unwrap();
+            hashes.push(user.password.unwrap());
+        }
+        let salt = |hash: &str| hash.rsplit('$').nth(1).unwrap().to_owned();
+        assert_ne!(salt(&hashes[0]), salt(&hashes[1]));
 
-        let client = Client::tracked(rocket().await)
+        // Hashes from when the salt was the configured secret key still verify
+        verify_test_user(&client).await;
+        let legacy_hash = argon2::hash_encoded(
+            b"12!@qwer",
+            b"my-secretkey-to-change-in-prod",
+            &argon2::Config::default(),
+        );
+        let changes = UserChanges {
+            password: Some(legacy_hash.unwrap()),
+            ..Default::default()
+        };
+        users.update(&test_user_id(&client).await, changes).await.unwrap();
+        sign_in_header(&client).await;
+    }
+
+    #[rocket::async_test]
+    async fn change_password_can_revoke_other_sessions() {
+        let client = Client::tracked(test_rocket())
             .await
             .expect("valid rocket instance");
 
         let response = client
-            .post("/auth/sign-in")
-            .header(content_type)
-            .body(REQ_BODY_LOG_IN.clone())
+            .post("/auth/change-password")
+            .header(ContentType::Form)
+            .body("current_password=12!@qwer&new_password=n3w!p4ssword")
+            .dispatch();
+        assert_eq!(response.await.status(), Status::Unauthorized);
+
+        let response = client
+            .post("/auth/sign-up")
+            .header(ContentType::Form)
+            .body(REQ_BODY_SIGN_UP)
+            .dispatch();
+        assert_eq!(response.await.status(), Status::Ok);
+        verify_test_user(&client).await;
+        let auth = sign_in_header(&client).await;
+        let other_auth = sign_in_header(&client).await;
+
+        let response = client
+            .post("/auth/change-password")
+            .header(ContentType::Form)
+            .header(auth.clone())
+            .body("current_password=wrong-password&new_password=n3w!p4ssword")
+            .dispatch();
+        assert_eq!(response.await.status(), Status::Unauthorized);
+
+        let response = client
+            .post("/auth/change-password")
+            .header(ContentType::Form)
+            .header(auth)
+            .body("current_password=12!@qwer&new_password=n3w!p4ssword&revoke_other_sessions=true")
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let content = response.into_string().await.unwrap();
+        let json_body: serde_json::Value = serde_json::from_str(&content).unwrap();
+        let token = json_body["data"]["access_token"].as_str().unwrap();
+        let new_auth = Header::new("Authorization", format!("Bearer {}", token));
+
+        let response = client
+            .post("/auth/find-user")
+            .header(ContentType::JSON)
+            .header(other_auth)
+            .body(REQ_BODY_DEL_USER)
             .dispatch();
-        assert_eq!(response.await.status(), Status::NotImplemented);
+        assert_eq!(response.await.status(), Status::Unauthorized);
+
+        let response = client.get("/auth/me").header(new_auth).dispatch();
+        assert_eq!(response.await.status(), Status::Ok);
     }
 
     #[rocket::async_test]
-    async fn correct_error_for_incorrect_password_sign_in() {
+    async fn update_profile_changes_only_given_fields() {
+        let client = Client::tracked(test_rocket())
+            .await
+            .expect("valid rocket instance");
+
+        let response = client
+            .patch("/auth/me")
+            .header(ContentType::JSON)
+            .body(r#"{"bio": "Copy ninja"}"#)
+            .dispatch();
+        assert_eq!(response.await.status(), Status::Unauthorized);
+
+        let auth = signed_in_user(&client, UserType::Customer).await;
+
+        let response = client
+            .patch("/auth/me")
+            .header(ContentType::JSON)
+            .header(auth.clone())
+            .body(r#"{"bio": "Copy ninja", "user_tags": ["MachineLearning"]}"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let content = response.into_string().await.unwrap();
+        let json_body: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(json_body["data"]["bio"], "Copy ninja");
+        assert_eq!(json_body["data"]["first_name"], "kakashi");
+        assert_eq!(json_body["data"]["user_tags"], serde_json::json!(["MachineLearning"]));
+        assert!(json_body["data"]["updated_at"].is_string());
+
+        let response = client
+            .patch("/auth/me")
+            .header(ContentType::JSON)
+            .header(auth.clone())
+            .body(r#"{"password": "something-else"}"#)
+            .dispatch();
+        assert_eq!(response.await.status(), Status::UnprocessableEntity);
+
+        // Avatars can only be set by uploading them
+        let response = client
+            .patch("/auth/me")
+            .header(ContentType::JSON)
+            .header(auth.clone())
+            .body(r#"{"image": "http://0.0.0.0:7001/file/download/avatar.png"}"#)
+            .dispatch();
+        assert_eq!(response.await.status(), Status::UnprocessableEntity);
+
+        let response = client
+            .patch("/auth/me")
+            .header(ContentType::JSON)
+            .header(auth.clone())
+            .body(r#"{"first_name": "k"}"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let content = response.into_string().await.unwrap();
+        let json_body: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(json_body["errors"]["first_name"][0]["code"], "length");
+    }
+
+    #[rocket::async_test]
+    async fn refresh_token_rotation_revokes_family_on_reuse() {
         let content_type =
-            Header::new("Content-Type", format!("application/x-www-form-urlencoded"));
+            Header::new("Content-Type", "application/x-www-form-urlencoded");
 
-        let client = Client::tracked(rocket().await)
+        let client = Client::tracked(test_rocket())
             .await
             .expect("valid rocket instance");
 
         let response = client
             .post("/auth/sign-up")
             .header(content_type.clone())
-            .body(REQ_BODY_SIGN_UP.clone())
+            .body(REQ_BODY_SIGN_UP)
             .dispatch();
         assert_eq!(response.await.status(), Status::Ok);
+        verify_test_user(&client).await;
 
-        let req_body_incorrect_pass = "username=kakashi@gmail.com&password=somethingelse";
         let response = client
             .post("/auth/sign-in")
             .header(content_type.clone())
-            .body(req_body_incorrect_pass)
+            .body(REQ_BODY_LOG_IN)
+            .dispatch()
+            .await;
+        let content = response.into_string().await.unwrap();
+        let json_body: serde_json::Value = serde_json::from_str(&content).unwrap();
+        let first_token = json_body["data"]["refresh_token"].as_str().unwrap().to_owned();
+
+        let response = client
+            .post("/auth/refresh")
+            .header(content_type.clone())
+            .body(format!("refresh_token={}", first_token))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let content = response.into_string().await.unwrap();
+        let json_body: serde_json::Value = serde_json::from_str(&content).unwrap();
+        let second_token = json_body["data"]["refresh_token"].as_str().unwrap().to_owned();
+        assert_ne!(first_token, second_token);
+
+        // Replaying the first token revokes the rotated one as well
+        let response = client
+            .post("/auth/refresh")
+            .header(content_type.clone())
+            .body(format!("refresh_token={}", first_token))
             .dispatch();
-        assert_eq!(response.await.status(), Status::NotImplemented);
+        assert_eq!(response.await.status(), Status::Unauthorized);
 
         let response = client
-            .post("/auth/delete-user")
-            .header(ContentType::JSON)
-            .body(REQ_BODY_DEL_USER.clone())
+            .post("/auth/refresh")
+            .header(content_type.clone())
+            .body(format!("refresh_token={}", second_token))
             .dispatch();
-        assert_eq!(response.await.status(), Status::Ok);
+        assert_eq!(response.await.status(), Status::Unauthorized);
     }
 
     #[rocket::async_test]
-    async fn upload_and_download_file() {
-        let content_type = "multipart/form-data; boundary=X-BOUNDARY"
-            .parse::<ContentType>()
-            .unwrap();
+    async fn correct_error_response_if_user_already_registered() {
+        let content_type =
+            Header::new("Content-Type", "application/x-www-form-urlencoded");
 
-        let client = Client::tracked(rocket().await)
+        let client = Client::tracked(test_rocket())
             .await
             .expect("valid rocket instance");
 
-        let multipart_body = &[
-            "--X-BOUNDARY",
-            r#"Content-Disposition: form-data; name="somefile"; filename="foo.txt""#,
-            "Content-Type: text/plain",
-            "",
-            "hi there",
-            "--X-BOUNDARY--",
-            "",
-        ].join("\r\n");
+        let response = client
+            .post("/auth/sign-up")
+            .header(content_type.clone())
+            .body(REQ_BODY_SIGN_UP)
+            .dispatch();
+        assert_eq!(response.await.status(), Status::Ok);
+        verify_test_user(&client).await;
 
-        let upload_file = client
-            .post("/files")
+        let response = client
+            .post("/auth/sign-up")
             .header(content_type.clone())
-            .body(multipart_body)
+            .body(REQ_BODY_SIGN_UP)
             .dispatch()
             .await;
-        assert_eq!(upload_file.status(), Status::Ok);
+        assert_eq!(response.status(), Status::Conflict);
+        let content = response.into_string().await.unwrap();
+        let json_body: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(json_body["code"], "user_already_exists");
+        assert_eq!(json_body["message"], "An account with this email already exists");
 
-        let content = std::fs::read_to_string("uploads/foo.txt").unwrap();
-        assert_eq!(content, "hi there");
+        let auth = sign_in_header(&client).await;
+        let response = client
+            .post("/auth/delete-user")
+            .header(ContentType::JSON)
+            .header(auth)
+            .body(REQ_BODY_DEL_USER)
+            .dispatch();
+        assert_eq!(response.await.status(), Status::Ok);
+    }
 
-        let content = upload_file.into_string();
+    #[rocket::async_test]
+    async fn correct_error_for_user_sign_in_without_sign_up() {
+        let content_type =
+            Header::new("Content-Type", "application/x-www-form-urlencoded");
+
+        let client = Client::tracked(test_rocket())
+            .await
+            .expect("valid rocket instance");
+
+        let response = client
+            .post("/auth/sign-in")
+            .header(content_type)
+            .body(REQ_BODY_LOG_IN)
+            .dispatch();
+        assert_eq!(response.await.status(), Status::Unauthorized);
+    }
+
+    #[rocket::async_test]
+    async fn unparseable_bodies_are_rejected_with_the_failed_fields() {
+        let client = Client::tracked(test_rocket())
+            .await
+            .expect("valid rocket instance");
+
+        let response = client
+            .post("/auth/sign-up")
+            .header(ContentType::Form)
+            .body(
+                REQ_BODY_SIGN_UP
+                    .replace("&email_id=kakashi@gmail.com", "&nickname=kaka")
+                    .replace("user_type=Customer", "user_type=Ninja"),
+            )
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let content = response.into_string().await.unwrap();
+        let json_body: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(json_body["code"], "invalid_request");
+        assert_eq!(json_body["errors"]["email_id"][0]["code"], "missing");
+        assert_eq!(json_body["errors"]["nickname"][0]["code"], "unexpected");
+        assert_eq!(json_body["errors"]["user_type"][0]["code"], "choice");
+
+        let auth = signed_in_user(&client, UserType::Customer).await;
+
+        let response = client
+            .patch("/auth/me")
+            .header(ContentType::JSON)
+            .header(auth.clone())
+            .body(r#"{"password": "something-else"}"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let content = response.into_string().await.unwrap();
+        let json_body: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(json_body["errors"]["password"][0]["code"], "unexpected");
+
+        let response = client
+            .patch("/auth/me")
+            .header(ContentType::JSON)
+            .header(auth)
+            .body(r#"{"first_name": 3}"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let content = response.into_string().await.unwrap();
+        let json_body: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(json_body["errors"]["__all__"][0]["code"], "invalid");
+    }
+
+    #[rocket::async_test]
+    async fn weak_passwords_are_rejected_with_the_failed_rules() {
+        let client = Client::tracked(test_rocket())
+            .await
+            .expect("valid rocket instance");
+
+        let failed_rules = |json_body: &serde_json::Value, field: &str| -> Vec<String> {
+            json_body["errors"][field]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|rule| rule["code"].as_str().unwrap().to_owned())
+                .collect()
+        };
+
+        let response = client
+            .post("/auth/sign-up")
+            .header(ContentType::Form)
+            .body(REQ_BODY_SIGN_UP.replace("password=12!@qwer", "password=short"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let content = response.into_string().await.unwrap();
+        let json_body: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(
+            failed_rules(&json_body, "password"),
+            vec!["password_too_short", "password_digit", "password_symbol"]
+        );
+        assert_eq!(
+            json_body["errors"]["password"][0]["message"],
+            "must be at least 8 characters long"
+        );
+
+        let response = client
+            .post("/auth/sign-up")
+            .header(ContentType::Form)
+            .body(REQ_BODY_SIGN_UP.replace("password=12!@qwer", "password=Kakashi!123"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let content = response.into_string().await.unwrap();
+        let json_body: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(failed_rules(&json_body, "password"), vec!["password_personal_info"]);
+
+        let auth = signed_in_user(&client, UserType::Customer).await;
+
+        let response = client
+            .post("/auth/change-password")
+            .header(ContentType::Form)
+            .header(auth.clone())
+            .body("current_password=12!@qwer&new_password=hatake-1234")
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let content = response.into_string().await.unwrap();
+        let json_body: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(failed_rules(&json_body, "new_password"), vec!["password_personal_info"]);
+
+        // The policy is not revealed without the current password
+        let response = client
+            .post("/auth/change-password")
+            .header(ContentType::Form)
+            .header(auth.clone())
+            .body("current_password=wrong-password&new_password=hatake-1234")
+            .dispatch();
+        assert_eq!(response.await.status(), Status::Unauthorized);
+
+        let response = client
+            .post("/auth/forgot-password")
+            .header(ContentType::Form)
+            .body("email_id=kakashi@gmail.com")
+            .dispatch();
+        assert_eq!(response.await.status(), Status::Ok);
+        let link = awaited_mailed_link(&client, "/auth/reset-password?").await;
+        let token = link.split("token=").nth(1).unwrap().to_owned();
+        let response = client
+            .post("/auth/reset-password")
+            .header(ContentType::Form)
+            .body(format!("token={}&new_password=abcdefgh", token))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let content = response.into_string().await.unwrap();
+        let json_body: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(
+            failed_rules(&json_body, "new_password"),
+            vec!["password_digit", "password_symbol"]
+        );
+    }
+
+    #[rocket::async_test]
+    async fn breached_passwords_are_rejected() {
+        let directory =
+            std::env::temp_dir().join(format!("breached-passwords-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let hash = format!("{:X}", Sha1::digest(b"Tr0ub4dor&3"));
+        let (prefix, suffix) = hash.split_at(5);
+        let range = format!("00000000000000000000000000000000000:0\r\n{}:42\r\n", suffix);
+        std::fs::write(directory.join(format!("{}.txt", prefix)), range).unwrap();
+
+        let figment = test_figment().merge(Serialized::global(
+            "password.breached_passwords",
+            directory.to_str().unwrap(),
+        ));
+        let client = Client::tracked(test_rocket_from(figment))
+            .await
+            .expect("valid rocket instance");
+
+        let response = client
+            .post("/auth/sign-up")
+            .header(ContentType::Form)
+            .body(REQ_BODY_SIGN_UP.replace("password=12!@qwer", "password=Tr0ub4dor%263"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let content = response.into_string().await.unwrap();
+        let json_body: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(json_body["errors"]["password"][0]["code"], "password_breached");
+
+        let response = client
+            .post("/auth/sign-up")
+            .header(ContentType::Form)
+            .body(REQ_BODY_SIGN_UP)
+            .dispatch();
+        assert_eq!(response.await.status(), Status::Ok);
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[rocket::async_test]
+    async fn invalid_input_is_rejected_with_field_errors() {
+        let client = Client::tracked(test_rocket())
+            .await
+            .expect("valid rocket instance");
+
+        let req_body_invalid_sign_up = REQ_BODY_SIGN_UP
+            .replace("first_name=kakashi", "first_name=a")
+            .replace("kakashi@gmail.com", "not-an-email");
+        let response = client
+            .post("/auth/sign-up")
+            .header(ContentType::Form)
+            .body(req_body_invalid_sign_up)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let content = response.into_string().await.unwrap();
+        let json_body: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(json_body["code"], "validation_failed");
+        assert_eq!(json_body["errors"]["first_name"][0]["code"], "length");
+        assert_eq!(
+            json_body["errors"]["first_name"][0]["message"],
+            "must be at least 3 characters long"
+        );
+        assert_eq!(json_body["errors"]["email_id"][0]["code"], "email");
+        assert!(json_body["errors"].get("last_name").is_none());
+
+        let response = client
+            .post("/auth/sign-in")
+            .header(ContentType::Form)
+            .body("username=kakashi&password=12!@qwer")
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let content = response.into_string().await.unwrap();
+        let json_body: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(json_body["errors"]["username"][0]["code"], "email");
+    }
+
+    #[rocket::async_test]
+    async fn correct_error_for_incorrect_password_sign_in() {
+        let content_type =
+            Header::new("Content-Type", "application/x-www-form-urlencoded");
+
+        let client = Client::tracked(test_rocket())
+            .await
+            .expect("valid rocket instance");
+
+        let response = client
+            .post("/auth/sign-up")
+            .header(content_type.clone())
+            .body(REQ_BODY_SIGN_UP)
+            .dispatch();
+        assert_eq!(response.await.status(), Status::Ok);
+        verify_test_user(&client).await;
+
+        let req_body_incorrect_pass = "username=kakashi@gmail.com&password=somethingelse";
+        let response = client
+            .post("/auth/sign-in")
+            .header(content_type.clone())
+            .body(req_body_incorrect_pass)
+            .dispatch();
+        assert_eq!(response.await.status(), Status::Unauthorized);
+
+        let auth = sign_in_header(&client).await;
+        let response = client
+            .post("/auth/delete-user")
+            .header(ContentType::JSON)
+            .header(auth)
+            .body(REQ_BODY_DEL_USER)
+            .dispatch();
+        assert_eq!(response.await.status(), Status::Ok);
+    }
+
+    #[rocket::async_test]
+    async fn upload_and_download_file() {
+        let content_type = "multipart/form-data; boundary=X-BOUNDARY"
+            .parse::<ContentType>()
+            .unwrap();
+
+        let client = Client::tracked(test_rocket())
+            .await
+            .expect("valid rocket instance");
+
+        let response = client
+            .post("/auth/sign-up")
+            .header(ContentType::Form)
+            .body(REQ_BODY_SIGN_UP)
+            .dispatch();
+        assert_eq!(response.await.status(), Status::Ok);
+        verify_test_user(&client).await;
+        let auth = sign_in_header(&client).await;
+
+        let multipart_body = |file_name: &str| {
+            [
+                "--X-BOUNDARY",
+                &format!(r#"Content-Disposition: form-data; name="somefile"; filename="{}""#, file_name),
+                "Content-Type: text/plain",
+                "",
+                "hi there",
+                "--X-BOUNDARY--",
+                "",
+            ]
+            .join("\r\n")
+        };
+
+        // Names that would land outside the storage directory or in the
+        // avatar one are refused
+        for file_name in ["../foo.txt", "nested/foo.txt", "..", ".hidden", "avatars"] {
+            let response = client
+                .post("/files")
+                .header(content_type.clone())
+                .header(auth.clone())
+                .body(multipart_body(file_name))
+                .dispatch();
+            assert_eq!(response.await.status(), Status::BadRequest);
+        }
+
+        let upload_file = client
+            .post("/files")
+            .header(content_type.clone())
+            .header(auth.clone())
+            .body(multipart_body("foo.txt"))
+            .dispatch()
+            .await;
+        assert_eq!(upload_file.status(), Status::Ok);
+
+        let user_directory = format!("uploads/{}", test_user_id(&client).await.to_hex());
+        let content = std::fs::read_to_string(format!("{}/foo.txt", user_directory)).unwrap();
+        assert_eq!(content, "hi there");
+
+        let content = upload_file.into_string();
         let json_body: serde_json::Value = serde_json::from_str(&content.await.unwrap()).unwrap();
         let download_obj = json_body.get("data").unwrap();
         let download_url = download_obj.get("url").unwrap();
@@ -309,19 +1276,1229 @@ mod test {
 
         let download_file = client
             .get("/files/foo.txt")
+            .header(auth.clone())
             .dispatch()
             .await;
         assert_eq!(download_file.status(), Status::Ok);
 
         let content = download_file.into_string();
         assert_eq!(content.await.unwrap(), "hi there");
+        std::fs::remove_file(format!("{}/foo.txt", user_directory)).unwrap();
+        std::fs::remove_dir(&user_directory).unwrap();
+
+        // Only the files of the signed-in user can be downloaded
+        for path in ["/files/..%2F.env", "/files/..%2F..%2FRocket.toml"] {
+            let response = client.get(path).header(auth.clone()).dispatch();
+            assert_eq!(response.await.status(), Status::BadRequest);
+        }
+        let other_directory = format!("uploads/{}", ObjectId::new().to_hex());
+        std::fs::create_dir_all(&other_directory).unwrap();
+        std::fs::write(format!("{}/bar.txt", other_directory), "not yours").unwrap();
+        let response = client.get("/files/bar.txt").header(auth.clone()).dispatch();
+        assert_eq!(response.await.status(), Status::NotFound);
+        std::fs::remove_dir_all(other_directory).unwrap();
+
+        let response = client
+            .post("/auth/delete-user")
+            .header(ContentType::JSON)
+            .header(auth)
+            .body(REQ_BODY_DEL_USER)
+            .dispatch();
+        assert_eq!(response.await.status(), Status::Ok);
+    }
+
+    #[rocket::async_test]
+    async fn protected_routes_reject_missing_or_invalid_tokens() {
+        let client = Client::tracked(test_rocket())
+            .await
+            .expect("valid rocket instance");
+
+        let response = client
+            .post("/auth/delete-user")
+            .header(ContentType::JSON)
+            .body(REQ_BODY_DEL_USER)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Unauthorized);
+        let content = response.into_string().await.unwrap();
+        let json_body: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(json_body["success"], false);
+
+        let response = client
+            .post("/auth/find-user")
+            .header(ContentType::JSON)
+            .header(Header::new("Authorization", "Bearer not-a-jwt"))
+            .body(REQ_BODY_DEL_USER)
+            .dispatch();
+        assert_eq!(response.await.status(), Status::Unauthorized);
+
+        let response = client.get("/files/foo.txt").dispatch();
+        assert_eq!(response.await.status(), Status::Unauthorized);
+    }
+
+    #[rocket::async_test]
+    async fn staff_roles_cannot_be_self_assigned_or_bypassed() {
+        let client = Client::tracked(test_rocket())
+            .await
+            .expect("valid rocket instance");
+
+        let req_body_admin_sign_up = REQ_BODY_SIGN_UP.replace("user_type=Customer", "user_type=Admin");
+        let response = client
+            .post("/auth/sign-up")
+            .header(ContentType::Form)
+            .body(req_body_admin_sign_up)
+            .dispatch();
+        assert_eq!(response.await.status(), Status::Forbidden);
+
+        let response = client.get("/admin/users").dispatch();
+        assert_eq!(response.await.status(), Status::Unauthorized);
+    }
+
+    #[rocket::async_test]
+    async fn ordinary_users_cannot_list_users() {
+        let client = Client::tracked(test_rocket())
+            .await
+            .expect("valid rocket instance");
+
+        let auth = signed_in_user(&client, UserType::Customer).await;
+
+        let response = client.get("/admin/users").header(auth.clone()).dispatch();
+        assert_eq!(response.await.status(), Status::Forbidden);
+        let response = client
+            .post("/auth/find-user")
+            .header(ContentType::JSON)
+            .header(auth)
+            .body(r#"{"email_id": "kakashi@gmail.com"}"#)
+            .dispatch();
+        assert_eq!(response.await.status(), Status::Forbidden);
+    }
+
+    #[rocket::async_test]
+    async fn support_staff_can_find_and_unlock_users() {
+        let client = Client::tracked(test_rocket())
+            .await
+            .expect("valid rocket instance");
+
+        let customer = signed_in_user(&client, UserType::Customer).await;
+        let user_id = test_user_id(&client).await;
+        let unlock = format!("/admin/users/{}/unlock", user_id);
+        let response = client.get("/admin/users").header(customer.clone()).dispatch();
+        assert_eq!(response.await.status(), Status::Forbidden);
+        let response = client.post(unlock.clone()).header(customer).dispatch();
+        assert_eq!(response.await.status(), Status::Forbidden);
+
+        set_test_user_type(&client, UserType::Support).await;
+        let support = sign_in_header(&client).await;
+        let response = client
+            .post("/auth/find-user")
+            .header(ContentType::JSON)
+            .header(support.clone())
+            .body(r#"{"email_id": "kakashi@gmail.com"}"#)
+            .dispatch();
+        assert_eq!(response.await.status(), Status::Ok);
+        let response = client.post(unlock).header(support.clone()).dispatch();
+        assert_eq!(response.await.status(), Status::Ok);
+
+        // Listing accounts, the audit log and deleting other accounts stay
+        // with administrators
+        let response = client.get("/admin/users").header(support.clone()).dispatch();
+        assert_eq!(response.await.status(), Status::Forbidden);
+        let response = client.get("/admin/audit").header(support.clone()).dispatch();
+        assert_eq!(response.await.status(), Status::Forbidden);
+        let response = client
+            .post("/auth/delete-user")
+            .header(ContentType::JSON)
+            .header(support)
+            .body(r#"{"username": "someone-else@gmail.com"}"#)
+            .dispatch();
+        assert_eq!(response.await.status(), Status::Forbidden);
+    }
+
+    fn avatar_body(content_type: &str, content: &[u8]) -> Vec<u8> {
+        let mut body = [
+            "--X-BOUNDARY",
+            r#"Content-Disposition: form-data; name="avatar"; filename="me.png""#,
+            &format!("Content-Type: {}", content_type),
+            "",
+            "",
+        ]
+        .join("\r\n")
+        .into_bytes();
+        body.extend_from_slice(content);
+        body.extend_from_slice(b"\r\n--X-BOUNDARY--\r\n");
+        body
+    }
+
+    #[rocket::async_test]
+    async fn avatar_upload_replaces_previous_image() {
+        let content_type = "multipart/form-data; boundary=X-BOUNDARY"
+            .parse::<ContentType>()
+            .unwrap();
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+        let client = Client::tracked(test_rocket())
+            .await
+            .expect("valid rocket instance");
+
+        let auth = signed_in_user(&client, UserType::Customer).await;
+
+        let response = client
+            .post("/auth/me/avatar")
+            .header(content_type.clone())
+            .header(auth.clone())
+            .body(avatar_body("text/plain", b"hi there"))
+            .dispatch();
+        assert_eq!(response.await.status(), Status::UnsupportedMediaType);
+
+        let mut image_urls = vec![];
+        for _ in 0..2 {
+            let response = client
+                .post("/auth/me/avatar")
+                .header(content_type.clone())
+                .header(auth.clone())
+                .body(avatar_body("image/png", png))
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::Ok);
+            let content = response.into_string().await.unwrap();
+            let json_body: serde_json::Value = serde_json::from_str(&content).unwrap();
+            image_urls.push(json_body["data"]["image"].as_str().unwrap().to_owned());
+        }
+
+        let avatars = std::path::Path::new("uploads/avatars");
+        let file_name = |url: &str| url.rsplit('/').next().unwrap().to_owned();
+        assert!(!avatars.join(file_name(&image_urls[0])).exists());
+        let current = avatars.join(file_name(&image_urls[1]));
+        assert_eq!(std::fs::read(&current).unwrap(), png);
+        let download_path = image_urls[1].strip_prefix("http://0.0.0.0:7001").unwrap();
+        let response = client.get(download_path).header(auth.clone()).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.into_bytes().await.unwrap(), png);
+        std::fs::remove_file(current).unwrap();
+
+        // Names that lead out of the avatar directory are refused
+        for path in ["/files/avatars/..%2F..%2FCargo.toml", "/files/avatars/..%2FRocket.toml"] {
+            let response = client.get(path).header(auth.clone()).dispatch();
+            assert_eq!(response.await.status(), Status::BadRequest);
+        }
+
+        // Avatars of other users are left alone, whatever the image points at
+        let other_avatar = format!("avatar-{}-1.png", ObjectId::new().to_hex());
+        let other_path = avatars.join(&other_avatar);
+        std::fs::write(&other_path, png).unwrap();
+        let config = client.rocket().state::<AppConfig>().unwrap();
+        let changes = UserChanges {
+            image: Some(format!("{}/files/avatars/{}", config.public_base_url, other_avatar)),
+            ..Default::default()
+        };
+        let users = client.rocket().state::<Box<dyn UserRepository>>().unwrap();
+        users.update(&test_user_id(&client).await, changes).await.unwrap();
+        let response = client
+            .post("/auth/me/avatar")
+            .header(content_type)
+            .header(auth)
+            .body(avatar_body("image/png", png))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        assert!(other_path.exists());
+        std::fs::remove_file(other_path).unwrap();
+        let content = response.into_string().await.unwrap();
+        let json_body: serde_json::Value = serde_json::from_str(&content).unwrap();
+        let current = file_name(json_body["data"]["image"].as_str().unwrap());
+        std::fs::remove_file(avatars.join(current)).unwrap();
+    }
+
+    #[rocket::async_test]
+    async fn repeated_sign_in_failures_lock_out_accounts_and_addresses() {
+        let figment = test_figment().merge(Serialized::global(
+            "lockout",
+            serde_json::json!({
+                "free_failures": 2,
+                "backoff_base": 60,
+                "backoff_max": 60,
+                "max_account_failures": 5,
+                "max_ip_failures": 4,
+                "lockout_duration": 900,
+                "failure_window": 3600,
+            }),
+        ));
+        let client = Client::tracked(test_rocket_from(figment))
+            .await
+            .expect("valid rocket instance");
+
+        let sign_in = |body: String, ip: &'static str| {
+            client
+                .post("/auth/sign-in")
+                .header(ContentType::Form)
+                .remote(peer(ip))
+                .body(body)
+                .dispatch()
+        };
+
+        // Failures from one address are counted across accounts
+        for name in ["a", "b", "c", "d"] {
+            let body = format!("username={}@unknown.com&password=wrong-password", name);
+            assert_eq!(sign_in(body, "10.0.0.1").await.status(), Status::Unauthorized);
+        }
+        let body = "username=e@unknown.com&password=wrong-password".to_owned();
+        assert_eq!(sign_in(body.clone(), "10.0.0.1").await.status(), Status::TooManyRequests);
+        assert_eq!(sign_in(body, "10.0.0.9").await.status(), Status::Unauthorized);
+
+        let auth = signed_in_user(&client, UserType::Admin).await;
+        let user_id = test_user_id(&client).await;
+
+        // The account has to wait after the free failures, even with the
+        // right password
+        let wrong_password = "username=kakashi@gmail.com&password=wrong-password";
+        for _ in 0..3 {
+            let response = sign_in(wrong_password.to_owned(), "10.0.0.2").await;
+            assert_eq!(response.status(), Status::Unauthorized);
+        }
+        let response = sign_in(REQ_BODY_LOG_IN.to_owned(), "10.0.0.3").await;
+        assert_eq!(response.status(), Status::TooManyRequests);
+        let retry_after: i64 = response.headers().get_one("Retry-After").unwrap().parse().unwrap();
+        assert!(retry_after > 0 && retry_after <= 60);
+        let content = response.into_string().await.unwrap();
+        let json_body: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(json_body["code"], "too_many_attempts");
+
+        let response = client
+            .post(format!("/admin/users/{}/unlock", user_id))
+            .header(auth)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let content = response.into_string().await.unwrap();
+        let json_body: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(json_body["data"]["unlocked"], true);
+
+        let response = sign_in(REQ_BODY_LOG_IN.to_owned(), "10.0.0.3").await;
+        assert_eq!(response.status(), Status::Ok);
+    }
+
+    #[rocket::async_test]
+    async fn successful_sign_ins_do_not_extend_lockouts() {
+        let client = Client::tracked(test_rocket())
+            .await
+            .expect("valid rocket instance");
+        let _ = signed_in_user(&client, UserType::Customer).await;
+        let sign_in = |body: &'static str| {
+            client
+                .post("/auth/sign-in")
+                .header(ContentType::Form)
+                .remote(peer("10.0.0.1"))
+                .body(body)
+                .dispatch()
+        };
+        let wrong_password = "username=kakashi@gmail.com&password=wrong-password";
+        assert_eq!(sign_in(wrong_password).await.status(), Status::Unauthorized);
+
+        let attempts = client.rocket().state::<Box<dyn LoginAttemptRepository>>().unwrap();
+        let failed = attempts.find("ip:10.0.0.1").await.unwrap().unwrap();
+        rocket::tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        assert_eq!(sign_in(REQ_BODY_LOG_IN).await.status(), Status::Ok);
+
+        // The address keeps its failure, dated when it happened
+        let counter = attempts.find("ip:10.0.0.1").await.unwrap().unwrap();
+        assert_eq!(counter.failures, 1);
+        assert_eq!(counter.last_failure, failed.last_failure);
+        assert!(attempts.find("account:kakashi@gmail.com").await.unwrap().is_none());
+
+        assert_eq!(sign_in(wrong_password).await.status(), Status::Unauthorized);
+        let counter = attempts.find("ip:10.0.0.1").await.unwrap().unwrap();
+        assert_eq!(counter.failures, 2);
+        assert!(counter.last_failure > failed.last_failure);
+
+        // Failures for another spelling of the email count for that one,
+        // which no user is looked up by
+        let other_case = "username=KAKASHI@gmail.com&password=wrong-password";
+        assert_eq!(sign_in(other_case).await.status(), Status::Unauthorized);
+        assert!(attempts.find("account:KAKASHI@gmail.com").await.unwrap().is_some());
+        let counter = attempts.find("account:kakashi@gmail.com").await.unwrap().unwrap();
+        assert_eq!(counter.failures, 1);
+    }
+
+    #[rocket::async_test]
+    async fn forged_client_addresses_do_not_dodge_lockouts() {
+        let lockout = serde_json::json!({
+            "free_failures": 10,
+            "backoff_base": 60,
+            "backoff_max": 60,
+            "max_account_failures": 10,
+            "max_ip_failures": 2,
+            "lockout_duration": 900,
+            "failure_window": 3600,
+        });
+        let figment = test_figment().merge(Serialized::global("lockout", lockout.clone()));
+        let client = Client::tracked(test_rocket_from(figment))
+            .await
+            .expect("valid rocket instance");
+        async fn sign_in(client: &Client, real_ip: &str) -> Status {
+            let response = client
+                .post("/auth/sign-in")
+                .header(ContentType::Form)
+                .remote(peer("10.0.0.1"))
+                .header(Header::new("X-Real-IP", real_ip.to_owned()))
+                .body(format!(
+                    "username={}@unknown.com&password=wrong-password",
+                    real_ip
+                ))
+                .dispatch();
+            response.await.status()
+        }
+
+        // Without a trusted proxy the header is ignored
+        assert_eq!(sign_in(&client, "10.1.0.1").await, Status::Unauthorized);
+        assert_eq!(sign_in(&client, "10.1.0.2").await, Status::Unauthorized);
+        assert_eq!(sign_in(&client, "10.1.0.3").await, Status::TooManyRequests);
+
+        let figment = test_figment()
+            .merge(Serialized::global("lockout", lockout))
+            .merge(Serialized::global("trusted_proxies", ["10.0.0.1"]));
+        let client = Client::tracked(test_rocket_from(figment))
+            .await
+            .expect("valid rocket instance");
+        for real_ip in ["10.1.0.1", "10.1.0.2", "10.1.0.3"] {
+            assert_eq!(sign_in(&client, real_ip).await, Status::Unauthorized);
+        }
+    }
+
+    #[rocket::async_test]
+    async fn parallel_sign_in_failures_cannot_exceed_the_lockout() {
+        let figment = test_figment().merge(Serialized::global(
+            "lockout",
+            serde_json::json!({
+                "free_failures": 10,
+                "backoff_base": 60,
+                "backoff_max": 60,
+                "max_account_failures": 2,
+                "max_ip_failures": 10,
+                "lockout_duration": 900,
+                "failure_window": 3600,
+            }),
+        ));
+        let client = Client::tracked(test_rocket_from(figment))
+            .await
+            .expect("valid rocket instance");
+        let response = client
+            .post("/auth/sign-up")
+            .header(ContentType::Form)
+            .body(REQ_BODY_SIGN_UP)
+            .dispatch();
+        assert_eq!(response.await.status(), Status::Ok);
+        verify_test_user(&client).await;
+
+        // Yields before every counter operation so the guesses interleave the
+        // way they would against a remote database.
+        #[derive(Default)]
+        struct Interleaved(InMemoryLoginAttemptRepository);
+
+        #[rocket::async_trait]
+        impl LoginAttemptRepository for Interleaved {
+            async fn find(&self, key: &str) -> Result<Option<LoginAttempts>, AuthenticationError> {
+                let _ = rocket::tokio::task::yield_now().await;
+                self.0.find(key).await
+            }
+
+            async fn count_attempt(
+                &self,
+                key: &str,
+                at: NaiveDateTime,
+            ) -> Result<Option<LoginAttempts>, AuthenticationError> {
+                let _ = rocket::tokio::task::yield_now().await;
+                self.0.count_attempt(key, at).await
+            }
+
+            async fn record_failure(
+                &self,
+                key: &str,
+                at: NaiveDateTime,
+            ) -> Result<(), AuthenticationError> {
+                let _ = rocket::tokio::task::yield_now().await;
+                self.0.record_failure(key, at).await
+            }
+
+            async fn forgive(&self, key: &str) -> Result<(), AuthenticationError> {
+                let _ = rocket::tokio::task::yield_now().await;
+                self.0.forgive(key).await
+            }
+
+            async fn reset(&self, key: &str) -> Result<bool, AuthenticationError> {
+                let _ = rocket::tokio::task::yield_now().await;
+                self.0.reset(key).await
+            }
+        }
+
+        let rocket = client.rocket();
+        let users = rocket.state::<Box<dyn UserRepository>>().unwrap();
+        let refresh_tokens = rocket.state::<Box<dyn RefreshTokenRepository>>().unwrap();
+        let config = rocket.state::<AppConfig>().unwrap();
+        let attempts = Interleaved::default();
+        let guesses = (0..6).map(|_| {
+            UserService::login(
+                users.as_ref(),
+                refresh_tokens.as_ref(),
+                &attempts,
+                config,
+                LoginUser {
+                    username: "kakashi@gmail.com".to_string(),
+                    password: "wrong-password".to_string(),
+                },
+                None,
+            )
+        });
+        let outcomes = futures::future::join_all(guesses).await;
+        let checked = outcomes
+            .iter()
+            .filter(|outcome| matches!(outcome, Err(AuthenticationError::PasswordMismatch(_))))
+            .count();
+        assert_eq!(checked, 2);
+        assert!(outcomes.iter().all(|outcome| matches!(
+            outcome,
+            Err(AuthenticationError::PasswordMismatch(_))
+                | Err(AuthenticationError::TooManyAttempts(_))
+        )));
+    }
+
+    #[rocket::async_test]
+    async fn route_groups_are_rate_limited_per_client() {
+        let figment = test_figment().merge(Serialized::global(
+            "rate_limit.groups",
+            serde_json::json!([{ "prefix": "/auth", "limit": 2, "period": 3600 }]),
+        ));
+        let client = Client::tracked(test_rocket_from(figment))
+            .await
+            .expect("valid rocket instance");
+
+        let user_tags = |ip: &'static str| {
+            client
+                .get("/auth/get-user-tags")
+                .remote(peer(ip))
+                .dispatch()
+        };
+
+        for remaining in ["1", "0"] {
+            let response = user_tags("10.0.0.1").await;
+            assert_eq!(response.status(), Status::Ok);
+            assert_eq!(response.headers().get_one("RateLimit-Limit"), Some("2"));
+            assert_eq!(response.headers().get_one("RateLimit-Remaining"), Some(remaining));
+        }
+
+        let response = user_tags("10.0.0.1").await;
+        assert_eq!(response.status(), Status::TooManyRequests);
+        assert_eq!(response.headers().get_one("RateLimit-Remaining"), Some("0"));
+        let retry_after: u64 = response.headers().get_one("Retry-After").unwrap().parse().unwrap();
+        assert!(retry_after > 0 && retry_after <= 1800);
+        let content = response.into_string().await.unwrap();
+        let json_body: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(json_body["code"], "rate_limited");
+
+        let response = client
+            .post("/auth/sign-in")
+            .header(ContentType::Form)
+            .remote(peer("10.0.0.1"))
+            .body(REQ_BODY_LOG_IN)
+            .dispatch();
+        assert_eq!(response.await.status(), Status::TooManyRequests);
+
+        // Spellings of the path the router treats the same count alike
+        for path in ["//auth/get-user-tags", "/%61uth/get-user-tags", "/auth//get-user-tags"] {
+            let response = client.get(path).remote(peer("10.0.0.1")).dispatch().await;
+            assert_eq!(response.status(), Status::TooManyRequests);
+            assert_eq!(response.headers().get_one("RateLimit-Remaining"), Some("0"));
+        }
+
+        // Other clients and routes outside the groups are not affected
+        assert_eq!(user_tags("10.0.0.2").await.status(), Status::Ok);
+        let response = client.get("/").remote(peer("10.0.0.1")).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert!(response.headers().get_one("RateLimit-Limit").is_none());
+
+        let response = client.get("/__rate_limited").dispatch();
+        assert_eq!(response.await.status(), Status::NotFound);
+    }
+
+    #[rocket::async_test]
+    async fn rate_limiter_forgets_the_longest_idle_client_at_capacity() {
+        let figment = test_figment()
+            .merge(Serialized::global("rate_limit.max_buckets", 2))
+            .merge(Serialized::global(
+                "rate_limit.groups",
+                serde_json::json!([{ "prefix": "/auth", "limit": 1, "period": 3600 }]),
+            ));
+        let client = Client::tracked(test_rocket_from(figment))
+            .await
+            .expect("valid rocket instance");
+
+        let user_tags = |ip: &'static str| {
+            client
+                .get("/auth/get-user-tags")
+                .remote(peer(ip))
+                .dispatch()
+        };
+
+        assert_eq!(user_tags("10.0.0.1").await.status(), Status::Ok);
+        assert_eq!(user_tags("10.0.0.1").await.status(), Status::TooManyRequests);
+        assert_eq!(user_tags("10.0.0.2").await.status(), Status::Ok);
+
+        // New clients are still served at the cap, pushing out the oldest
+        assert_eq!(user_tags("10.0.0.3").await.status(), Status::Ok);
+        assert_eq!(user_tags("10.0.0.2").await.status(), Status::TooManyRequests);
+        assert_eq!(user_tags("10.0.0.3").await.status(), Status::TooManyRequests);
+        assert_eq!(user_tags("10.0.0.1").await.status(), Status::Ok);
+    }
+
+    #[rocket::async_test]
+    async fn authentication_events_are_audited() {
+        let client = Client::tracked(test_rocket())
+            .await
+            .expect("valid rocket instance");
+
+        let response = client
+            .post("/auth/sign-up")
+            .header(ContentType::Form)
+            .remote(peer("10.0.0.1"))
+            .header(Header::new("User-Agent", "audit-test"))
+            .body(REQ_BODY_SIGN_UP)
+            .dispatch()
+            .await;
+        let content = response.into_string().await.unwrap();
+        let json_body: serde_json::Value = serde_json::from_str(&content).unwrap();
+        let user_id = json_body["data"]["_id"]["$oid"].as_str().unwrap().to_owned();
+        verify_test_user(&client).await;
+
+        let response = client
+            .post("/auth/sign-in")
+            .header(ContentType::Form)
+            .remote(peer("10.0.0.2"))
+            .body("username=kakashi@gmail.com&password=wrong-password")
+            .dispatch();
+        assert_eq!(response.await.status(), Status::Unauthorized);
+
+        set_test_user_type(&client, UserType::Admin).await;
+        let auth = sign_in_header(&client).await;
+
+        let audit = |query: &'static str| {
+            let request = client
+                .get(format!("/admin/audit?{}", query))
+                .header(auth.clone());
+            async move {
+                let response = request.dispatch().await;
+                assert_eq!(response.status(), Status::Ok);
+                let content = response.into_string().await.unwrap();
+                serde_json::from_str::<serde_json::Value>(&content).unwrap()["data"].clone()
+            }
+        };
+
+        let page = audit("").await;
+        let kinds: Vec<&str> = page["events"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|event| event["kind"].as_str().unwrap())
+            .collect();
+        assert_eq!(kinds, vec!["sign_in_succeeded", "sign_in_failed", "sign_up"]);
+
+        let page = audit("kind=sign_up").await;
+        let event = &page["events"][0];
+        assert_eq!(event["actor"], user_id.as_str());
+        assert_eq!(event["target"], "kakashi@gmail.com");
+        assert_eq!(event["ip"], "10.0.0.1");
+        assert_eq!(event["user_agent"], "audit-test");
+        assert!(event["created_at"].is_string());
+
+        let page = audit("kind=sign_in_failed&target=kakashi@gmail.com").await;
+        assert_eq!(page["events"][0]["actor"], serde_json::Value::Null);
+        assert_eq!(page["events"][0]["ip"], "10.0.0.2");
+
+        let page = audit("limit=2").await;
+        assert_eq!(page["events"].as_array().unwrap().len(), 2);
+        let cursor = page["next_cursor"].as_str().unwrap().to_owned();
+        let request = client
+            .get(format!("/admin/audit?before={}", cursor))
+            .header(auth.clone());
+        let content = request.dispatch().await.into_string().await.unwrap();
+        let json_body: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(json_body["data"]["events"][0]["kind"], "sign_up");
+        assert!(json_body["data"]["next_cursor"].is_null());
+    }
+
+    #[rocket::async_test]
+    async fn audit_log_is_hash_chained_and_exported_signed() {
+        use crate::models::audit::AuditEvent;
+        use crate::services::audit_service::AuditService;
+
+        let client = Client::tracked(test_rocket())
+            .await
+            .expect("valid rocket instance");
+
+        let auth = signed_in_user(&client, UserType::Admin).await;
+
+        let response = client.get("/admin/audit/verify").header(auth.clone()).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let content = response.into_string().await.unwrap();
+        let json_body: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(json_body["data"]["valid"], true);
+        assert_eq!(json_body["data"]["checked"], 2);
+        assert_eq!(json_body["data"]["head_seq"], 2);
+        assert!(json_body["data"]["broken_link"].is_null());
+        let head_hash = json_body["data"]["head_hash"].clone();
+
+        let response = client
+            .get("/admin/audit/export?from=2&to=1")
+            .header(auth.clone())
+            .dispatch();
+        assert_eq!(response.await.status(), Status::BadRequest);
+
+        let response = client
+            .get("/admin/audit/export?from=1&to=2")
+            .header(auth.clone())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let content = response.into_string().await.unwrap();
+        let json_body: serde_json::Value = serde_json::from_str(&content).unwrap();
+        let export = &json_body["data"]["export"];
+        assert_eq!(export["events"][1]["hash"], head_hash);
+
+        // The signature covers the export exactly as sent and verifies with
+        // the published keys
+        let signature = json_body["data"]["signature"].as_str().unwrap();
+        let (header, signature) = signature.split_once("..").unwrap();
+        let payload = base64::encode_config(serde_json::to_vec(export).unwrap(), base64::URL_SAFE_NO_PAD);
+        let signed = verify_with_jwks(&client, &format!("{}.{}.{}", header, payload, signature)).await;
+        assert_eq!(&signed, export);
+
+        let mut events: Vec<AuditEvent> = serde_json::from_value(export["events"].clone()).unwrap();
+        assert!(AuditService::broken_link(None, &events[0]).is_none());
+        assert!(AuditService::broken_link(Some(&events[0]), &events[1]).is_none());
+
+        // Editing an event breaks its own hash, recomputing it breaks the next link
+        events[0].target = Some("someone-else@gmail.com".to_owned());
+        let reason = AuditService::broken_link(None, &events[0]).unwrap();
+        assert!(reason.contains("content"));
+        events[0].hash = events[0].compute_hash();
+        assert!(AuditService::broken_link(None, &events[0]).is_none());
+        let reason = AuditService::broken_link(Some(&events[0]), &events[1]).unwrap();
+        assert!(reason.contains("Previous hash"));
+        assert!(AuditService::broken_link(None, &events[1]).is_some());
+    }
+
+    #[rocket::async_test]
+    async fn totp_second_factor_is_required_once_enrolled() {
+        use crate::config::totp::TotpService;
+
+        // RFC 6238 test vectors, truncated to six digits
+        let rfc_secret = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+        assert_eq!(TotpService::code(rfc_secret, 59).unwrap(), "287082");
+        assert_eq!(TotpService::code(rfc_secret, 1111111109).unwrap(), "081804");
+
+        // Untracked, so that only the returned tokens authenticate
+        let client = Client::untracked(test_rocket())
+            .await
+            .expect("valid rocket instance");
+        let auth = signed_in_user(&client, UserType::Customer).await;
+
+        let confirm = |code: String| {
+            client
+                .post("/auth/mfa/confirm")
+                .header(ContentType::Form)
+                .header(auth.clone())
+                .body(format!("code={}", code))
+                .dispatch()
+        };
+        assert_eq!(confirm("123456".to_owned()).await.status(), Status::Conflict);
+
+        let response = client.post("/auth/mfa/enroll").header(auth.clone()).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let content = response.into_string().await.unwrap();
+        let json_body: serde_json::Value = serde_json::from_str(&content).unwrap();
+        let secret = json_body["data"]["secret"].as_str().unwrap().to_owned();
+        let uri = json_body["data"]["otpauth_uri"].as_str().unwrap();
+        assert!(uri.starts_with("otpauth://totp/authentication-service:"));
+        assert!(uri.contains(&format!("secret={}&issuer=authentication-service", secret)));
+
+        let now = chrono::Utc::now().timestamp();
+        let code = TotpService::code(&secret, now).unwrap();
+        let wrong_code = format!("{:06}", (code.parse::<u32>().unwrap() + 1) % 1_000_000);
+        let response = confirm(wrong_code).await;
+        assert_eq!(response.status(), Status::Unauthorized);
+        let content = response.into_string().await.unwrap();
+        assert!(content.contains("invalid_mfa_code"));
+
+        let response = confirm(code.clone()).await;
+        assert_eq!(response.status(), Status::Ok);
+        let content = response.into_string().await.unwrap();
+        let json_body: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(json_body["data"]["user"]["totp_enabled"], true);
+        assert!(json_body["data"]["user"].get("totp_secret").is_none());
+        assert_eq!(json_body["data"]["recovery_codes"].as_array().unwrap().len(), 10);
+        let response = client.post("/auth/mfa/enroll").header(auth.clone()).dispatch();
+        assert_eq!(response.await.status(), Status::Conflict);
+
+        // The password alone only yields a challenge
+        let response = client
+            .post("/auth/sign-in")
+            .header(ContentType::Form)
+            .body(REQ_BODY_LOG_IN)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        assert!(response.cookies().get("access_token").is_none());
+        let content = response.into_string().await.unwrap();
+        let json_body: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(json_body["data"]["mfa_required"], true);
+        assert!(json_body["data"]["access_token"].is_null());
+        let challenge = json_body["data"]["challenge_token"].as_str().unwrap().to_owned();
+
+        let verify = |challenge: &str, code: &str| {
+            client
+                .post("/auth/mfa/verify")
+                .header(ContentType::Form)
+                .body(format!("challenge_token={}&code={}", challenge, code))
+                .dispatch()
+        };
+        assert_eq!(verify("not-a-token", &code).await.status(), Status::BadRequest);
+        // Codes cannot be used twice
+        assert_eq!(verify(&challenge, &code).await.status(), Status::Unauthorized);
+
+        let next_code = TotpService::code(&secret, now + 30).unwrap();
+        let response = verify(&challenge, &next_code).await;
+        assert_eq!(response.status(), Status::Ok);
+        let content = response.into_string().await.unwrap();
+        let json_body: serde_json::Value = serde_json::from_str(&content).unwrap();
+        let token = json_body["data"]["access_token"].as_str().unwrap();
+        let response = client
+            .get("/auth/me")
+            .header(Header::new("Authorization", format!("Bearer {}", token)))
+            .dispatch();
+        assert_eq!(response.await.status(), Status::Ok);
+    }
+
+    #[rocket::async_test]
+    async fn recovery_codes_are_single_use_second_factors() {
+        use crate::models::audit::{AuditEventKind, AuditFilter};
+        use crate::repository::audit_repository::AuditRepository;
+
+        let client = Client::untracked(test_rocket())
+            .await
+            .expect("valid rocket instance");
+        let auth = signed_in_user(&client, UserType::Customer).await;
+        let (_, recovery_codes) = enable_totp(&client, &auth).await;
+        assert_eq!(recovery_codes.len(), 10);
+        assert!(recovery_codes.iter().all(|code| code.len() == 11 && code.as_bytes()[5] == b'-'));
+
+        // Every code is hashed with a salt of its own
+        let users = client.rocket().state::<Box<dyn UserRepository>>().unwrap();
+        let user = users.find_by_email("kakashi@gmail.com").await.unwrap().unwrap();
+        let salts: std::collections::HashSet<&str> = user
+            .recovery_codes
+            .iter()
+            .map(|hash| hash.rsplit('$').nth(1).unwrap())
+            .collect();
+        assert_eq!(salts.len(), recovery_codes.len());
+
+        let sign_in_with = |code: String| {
+            let client = &client;
+            async move {
+                let response = client
+                    .post("/auth/sign-in")
+                    .header(ContentType::Form)
+                    .body(REQ_BODY_LOG_IN)
+                    .dispatch();
+                let content = response.await.into_string().await.unwrap();
+                let json_body: serde_json::Value = serde_json::from_str(&content).unwrap();
+                let challenge = json_body["data"]["challenge_token"].as_str().unwrap();
+                client
+                    .post("/auth/mfa/verify")
+                    .header(ContentType::Form)
+                    .body(format!("challenge_token={}&code={}", challenge, code))
+                    .dispatch()
+                    .await
+                    .status()
+            }
+        };
+        // Recovery codes are accepted regardless of case and separators
+        let typed = recovery_codes[0].to_uppercase().replace('-', " ");
+        assert_eq!(sign_in_with(typed).await, Status::Ok);
+        assert_eq!(sign_in_with(recovery_codes[0].clone()).await, Status::Unauthorized);
+
+        // Of two sign-ins racing with the same code, only one gets through
+        let (first, second) = futures::join!(
+            sign_in_with(recovery_codes[3].clone()),
+            sign_in_with(recovery_codes[3].clone())
+        );
+        let mut statuses = vec![first, second];
+        statuses.sort_by_key(|status| status.code);
+        assert_eq!(statuses, vec![Status::Ok, Status::Unauthorized]);
+        let hash = &user.recovery_codes[4];
+        let user_id = user.user_id.as_ref().unwrap();
+        assert!(users.remove_recovery_code(user_id, hash).await.unwrap());
+        assert!(!users.remove_recovery_code(user_id, hash).await.unwrap());
+
+        let regenerate = |code: &str| {
+            client
+                .post("/auth/mfa/recovery-codes")
+                .header(ContentType::Form)
+                .header(auth.clone())
+                .body(format!("code={}", code))
+                .dispatch()
+        };
+        assert_eq!(regenerate("aaaaa-aaaaa").await.status(), Status::Unauthorized);
+        let response = regenerate(&recovery_codes[1]).await;
+        assert_eq!(response.status(), Status::Ok);
+        let content = response.into_string().await.unwrap();
+        let json_body: serde_json::Value = serde_json::from_str(&content).unwrap();
+        let new_codes: Vec<String> =
+            serde_json::from_value(json_body["data"]["recovery_codes"].clone()).unwrap();
+        assert_eq!(new_codes.len(), 10);
+
+        // Earlier codes stop working once new ones are generated
+        assert_eq!(sign_in_with(recovery_codes[2].clone()).await, Status::Unauthorized);
+        assert_eq!(sign_in_with(new_codes[0].clone()).await, Status::Ok);
+
+        let audit = client.rocket().state::<Box<dyn AuditRepository>>().unwrap();
+        let count = |kind| {
+            let filter = AuditFilter {
+                kind: Some(kind),
+                actor: None,
+                target: Some("kakashi@gmail.com".to_owned()),
+                before: None,
+                limit: 50,
+            };
+            async move { audit.list(&filter).await.unwrap().len() }
+        };
+        assert_eq!(count(AuditEventKind::RecoveryCodeUsed).await, 4);
+        assert_eq!(count(AuditEventKind::RecoveryCodesRegenerated).await, 1);
+    }
+
+    #[rocket::async_test]
+    async fn oauth_authorization_code_flow_requires_pkce() {
+        use rocket::http::Cookie;
+
+        // Cookies are passed by hand, so that the consent form signs in
+        let client = Client::untracked(test_rocket())
+            .await
+            .expect("valid rocket instance");
+
+        let auth = signed_in_user(&client, UserType::Admin).await;
+
+        for redirect_uri in [
+            "http://printer.example/callback",
+            "javascript:alert(1)",
+            "data:text/html,hello",
+            "file:///etc/passwd",
+        ] {
+            let body = serde_json::json!({
+                "name": "Printer",
+                "redirect_uris": [redirect_uri],
+                "scopes": ["profile"],
+                "confidential": false,
+            });
+            let response = client
+                .post("/admin/oauth/clients")
+                .header(ContentType::JSON)
+                .header(auth.clone())
+                .body(body.to_string())
+                .dispatch();
+            assert_eq!(response.await.status(), Status::UnprocessableEntity);
+        }
+
+        let response = client
+            .post("/admin/oauth/clients")
+            .header(ContentType::JSON)
+            .header(auth.clone())
+            .body(r#"{"name": "Printer", "redirect_uris": ["https://printer.example/callback"], "scopes": ["openid", "profile", "files"], "confidential": true}"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Created);
+        let content = response.into_string().await.unwrap();
+        let json_body: serde_json::Value = serde_json::from_str(&content).unwrap();
+        let client_id = json_body["data"]["client"]["client_id"].as_str().unwrap().to_owned();
+        let client_secret = json_body["data"]["client_secret"].as_str().unwrap().to_owned();
+        assert_eq!(json_body["data"]["client"]["confidential"], true);
+
+        let verifier = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+        let challenge = "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM";
+        let callback = "https%3A%2F%2Fprinter.example%2Fcallback";
+        let query = format!(
+            "response_type=code&client_id={}&redirect_uri={}&scope=openid+profile&state=xyz&nonce=n-0S6",
+            client_id, callback
+        );
+
+        let response = client.get("/oauth/authorize?response_type=code&client_id=unknown").dispatch();
+        assert_eq!(response.await.status(), Status::BadRequest);
+        let response = client
+            .get(format!("/oauth/authorize?client_id={}&redirect_uri=https%3A%2F%2Fevil.example", client_id))
+            .dispatch();
+        assert_eq!(response.await.status(), Status::BadRequest);
+
+        // Other errors are reported to the client
+        let response = client.get(format!("/oauth/authorize?{}", query)).dispatch().await;
+        assert_eq!(response.status(), Status::SeeOther);
+        let location = response.headers().get_one("Location").unwrap();
+        assert!(location.starts_with("https://printer.example/callback?error=invalid_request&"));
+        assert!(location.ends_with("&state=xyz"));
+
+        let query = format!("{}&code_challenge={}&code_challenge_method=S256", query, challenge);
+        let response = client.get(format!("/oauth/authorize?{}", query)).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::HTML));
+        assert_eq!(response.headers().get_one("X-Frame-Options"), Some("DENY"));
+        let page = response.into_string().await.unwrap();
+        assert!(page.contains("<strong>Printer</strong>"));
+        assert!(page.contains(r#"name="password""#));
+        // Denying works without filling in the required sign-in fields
+        assert!(page.contains(r#"value="deny" formnovalidate"#));
+        let csrf_start = page.find(r#"name="csrf_token" value=""#).unwrap() + 26;
+        let csrf_token = page[csrf_start..].split('"').next().unwrap().to_owned();
+
+        let form = |password: &str| {
+            let fields = query.replace('&', "&request.");
+            format!(
+                "request.{}&csrf_token={}&username=kakashi%40gmail.com&password={}&decision=approve",
+                fields, csrf_token, password
+            )
+        };
+        let response = client
+            .post("/oauth/authorize")
+            .header(ContentType::Form)
+            .body(form("12!%40qwer"))
+            .dispatch();
+        assert_eq!(response.await.status(), Status::Forbidden);
+
+        let response = client
+            .post("/oauth/authorize")
+            .header(ContentType::Form)
+            .private_cookie(Cookie::new("oauth_csrf", csrf_token.clone()))
+            .body(form("wrong-password"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Unauthorized);
+        assert!(response.into_string().await.unwrap().contains("Invalid email or password"));
+
+        let authorize = || async {
+            let response = client
+                .post("/oauth/authorize")
+                .header(ContentType::Form)
+                .private_cookie(Cookie::new("oauth_csrf", csrf_token.clone()))
+                .body(form("12!%40qwer"))
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::SeeOther);
+            let location = response.headers().get_one("Location").unwrap().to_owned();
+            let code = location
+                .strip_prefix("https://printer.example/callback?code=")
+                .and_then(|rest| rest.strip_suffix("&state=xyz"))
+                .unwrap();
+            code.to_owned()
+        };
+        let exchange = |code: String, verifier: &'static str, basic: Option<String>| {
+            let mut request = client.post("/oauth/token").header(ContentType::Form).body(format!(
+                "grant_type=authorization_code&code={}&redirect_uri={}&client_id={}&code_verifier={}",
+                code, callback, client_id, verifier
+            ));
+            if let Some(basic) = basic {
+                request = request.header(Header::new("Authorization", format!("Basic {}", basic)));
+            }
+            request.dispatch()
+        };
+        let basic = base64::encode(format!("{}:{}", client_id, client_secret));
+
+        // Confidential clients have to authenticate, a wrong verifier uses up the code
+        let code = authorize().await;
+        let response = exchange(code.clone(), verifier, None).await;
+        assert_eq!(response.status(), Status::Unauthorized);
+        assert!(response.headers().get_one("WWW-Authenticate").is_some());
+        let wrong_verifier = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXx";
+        let response = exchange(code.clone(), wrong_verifier, Some(basic.clone())).await;
+        assert_eq!(response.status(), Status::BadRequest);
+        let response = exchange(code, verifier, Some(basic.clone())).await;
+        assert_eq!(response.status(), Status::BadRequest);
+        let content = response.into_string().await.unwrap();
+        let json_body: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(json_body["error"], "invalid_grant");
+
+        let code = authorize().await;
+        let response = exchange(code.clone(), verifier, Some(basic.clone())).await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.headers().get_one("Cache-Control"), Some("no-store"));
+        let content = response.into_string().await.unwrap();
+        let json_body: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(json_body["token_type"], "Bearer");
+        assert_eq!(json_body["scope"], "openid profile");
+        let access_token = json_body["access_token"].as_str().unwrap();
+        let id_claims = verify_with_jwks(&client, json_body["id_token"].as_str().unwrap()).await;
+        assert_eq!(id_claims["aud"], client_id.as_str());
+        assert_eq!(id_claims["nonce"], "n-0S6");
+        assert_eq!(id_claims["email"], "kakashi@gmail.com");
+
+        // ID tokens are not access tokens, though signed with the same key
+        let id_auth = Header::new(
+            "Authorization",
+            format!("Bearer {}", json_body["id_token"].as_str().unwrap()),
+        );
+        let response = client.get("/auth/me").header(id_auth.clone()).dispatch();
+        assert_eq!(response.await.status(), Status::Unauthorized);
+        let response = client.get("/userinfo").header(id_auth).dispatch();
+        assert_eq!(response.await.status(), Status::Unauthorized);
+        let response = exchange(code, verifier, Some(basic)).await;
+        assert_eq!(response.status(), Status::BadRequest);
+
+        // The token only allows what its scopes stand for
+        let client_auth = Header::new("Authorization", format!("Bearer {}", access_token));
+        let response = client.get("/auth/me").header(client_auth.clone()).dispatch();
+        assert_eq!(response.await.status(), Status::Ok);
+        let response = client.get("/userinfo").header(client_auth.clone()).dispatch();
+        assert_eq!(response.await.status(), Status::Ok);
+        let response = client.get("/admin/users").header(client_auth.clone()).dispatch();
+        assert_eq!(response.await.status(), Status::Forbidden);
+        let response = client
+            .post("/auth/change-password")
+            .header(ContentType::Form)
+            .header(client_auth)
+            .body("current_password=12!@qwer&new_password=n3w!p4ssword")
+            .dispatch();
+        assert_eq!(response.await.status(), Status::Forbidden);
+    }
+
+    #[rocket::async_test]
+    async fn admin_scoped_client_tokens_cannot_act_as_administrator() {
+        let client = Client::tracked(test_rocket())
+            .await
+            .expect("valid rocket instance");
+
+        signed_in_user(&client, UserType::Admin).await;
+        let users = client.rocket().state::<Box<dyn UserRepository>>().unwrap();
+        let user = users.find_by_email("kakashi@gmail.com").await.unwrap().unwrap();
+        let config = client.rocket().state::<AppConfig>().unwrap();
+        let token = TokenService::new(&config.jwt)
+            .issue_client_access_token(&user, "printer", &[Scope::Admin])
+            .unwrap();
+        let client_auth = Header::new("Authorization", format!("Bearer {}", token));
+
+        let response = client.get("/admin/oauth/clients").header(client_auth.clone()).dispatch();
+        assert_eq!(response.await.status(), Status::Forbidden);
+        let response = client.get("/admin/audit/verify").header(client_auth.clone()).dispatch();
+        assert_eq!(response.await.status(), Status::Forbidden);
+
+        // What the scope stands for is still allowed
+        let response = client
+            .post(format!("/admin/users/{}/unlock", test_user_id(&client).await))
+            .header(client_auth)
+            .dispatch();
+        assert_eq!(response.await.status(), Status::Ok);
+    }
+
+    #[rocket::async_test]
+    async fn openid_connect_tokens_verify_with_published_keys() {
+        let client = Client::tracked(test_rocket())
+            .await
+            .expect("valid rocket instance");
+
+        let response = client.get("/.well-known/openid-configuration").dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let content = response.into_string().await.unwrap();
+        let discovery: serde_json::Value = serde_json::from_str(&content).unwrap();
+        let config = client.rocket().state::<AppConfig>().unwrap();
+        assert_eq!(discovery["issuer"], config.jwt.issuer.as_str());
+        assert_eq!(
+            discovery["jwks_uri"],
+            format!("{}/.well-known/jwks.json", config.public_base_url)
+        );
+        assert_eq!(discovery["id_token_signing_alg_values_supported"][0], "EdDSA");
+        assert_eq!(discovery["code_challenge_methods_supported"][0], "S256");
+
+        let auth = signed_in_user(&client, UserType::Customer).await;
+
+        // Access tokens verify with the public key alone
+        let token = auth.value().strip_prefix("Bearer ").unwrap().to_owned();
+        let claims = verify_with_jwks(&client, &token).await;
+        assert_eq!(claims["iss"], config.jwt.issuer.as_str());
+        assert_eq!(claims["user_type"], "Customer");
+
+        let response = client.get("/userinfo").header(auth.clone()).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let content = response.into_string().await.unwrap();
+        let user_info: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(user_info["sub"], claims["sub"]);
+        assert_eq!(user_info["name"], "kakashi hatake");
+        assert_eq!(user_info["email"], "kakashi@gmail.com");
+        assert_eq!(user_info["email_verified"], true);
+        assert_eq!(user_info["user_type"], "Customer");
+        assert_eq!(user_info["user_tags"][0], "WebDevelopment");
+
+        // Tokens whose claims were changed after signing are rejected
+        let parts: Vec<&str> = token.split('.').collect();
+        let mut forged_claims = claims.clone();
+        forged_claims["user_type"] = "Admin".into();
+        let forged_claims = base64::encode_config(
+            serde_json::to_vec(&forged_claims).unwrap(),
+            base64::URL_SAFE_NO_PAD,
+        );
+        let forged = format!("{}.{}.{}", parts[0], forged_claims, parts[2]);
+        let response = client
+            .get("/userinfo")
+            .header(Header::new("Authorization", format!("Bearer {}", forged)))
+            .dispatch();
+        assert_eq!(response.await.status(), Status::Unauthorized);
+
+        // as are tokens signed with the old shared secret
+        let hs256 = jsonwebtoken::encode(
+            &jsonwebtoken::Header::default(),
+            &claims,
+            &jsonwebtoken::EncodingKey::from_secret(config.jwt.secret.as_bytes()),
+        )
+        .unwrap();
+        let response = client
+            .get("/userinfo")
+            .header(Header::new("Authorization", format!("Bearer {}", hs256)))
+            .dispatch();
+        assert_eq!(response.await.status(), Status::Unauthorized);
+    }
+
+    #[rocket::async_test]
+    async fn admin_can_page_and_filter_users() {
+        let client = Client::tracked(test_rocket())
+            .await
+            .expect("valid rocket instance");
+
+        let auth = signed_in_user(&client, UserType::Admin).await;
+
+        let list = |query: &'static str| {
+            let request = client
+                .get(format!("/admin/users?{}", query))
+                .header(auth.clone());
+            async move {
+                let response = request.dispatch().await;
+                let status = response.status();
+                let content = response.into_string().await.unwrap_or_default();
+                (status, serde_json::from_str::<serde_json::Value>(&content).ok())
+            }
+        };
+
+        let (status, body) = list("q=KAKASHI&user_type=Admin&user_tags=WebDevelopment&verified=true").await;
+        assert_eq!(status, Status::Ok);
+        let users = body.unwrap()["data"]["users"].as_array().unwrap().clone();
+        assert_eq!(users.len(), 1);
+        assert_eq!(users[0]["email_id"], "kakashi@gmail.com");
+
+        let (status, body) = list("q=kakashi&verified=false").await;
+        assert_eq!(status, Status::Ok);
+        assert!(body.unwrap()["data"]["users"].as_array().unwrap().is_empty());
+
+        let (status, body) = list("q=kakashi&limit=1&order=asc").await;
+        assert_eq!(status, Status::Ok);
+        assert!(body.unwrap()["data"]["next_cursor"].is_null());
 
-        std::fs::remove_file("uploads/foo.txt").unwrap();
+        let (status, _) = list("cursor=not-a-cursor").await;
+        assert_eq!(status, Status::BadRequest);
     }
 
     #[rocket::async_test]
     async fn it_works_with_correct_status_for_getting_all_user_tags() {
-        let client = Client::tracked(rocket().await)
+        let client = Client::tracked(test_rocket())
             .await
             .expect("valid rocket instance");
         let response = client.get("/auth/get-user-tags").dispatch();
@@ -0,0 +1,173 @@
+use chrono::NaiveDateTime;
+use mongodb::bson;
+use rocket::form::FromForm;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, FromFormField, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditEventKind {
+    #[field(value = "sign_up")]
+    SignUp,
+    #[field(value = "sign_in_succeeded")]
+    SignInSucceeded,
+    #[field(value = "sign_in_failed")]
+    SignInFailed,
+    #[field(value = "password_changed")]
+    PasswordChanged,
+    #[field(value = "password_reset")]
+    PasswordReset,
+    #[field(value = "user_deleted")]
+    UserDeleted,
+    #[field(value = "mfa_enabled")]
+    MfaEnabled,
+    #[field(value = "recovery_code_used")]
+    RecoveryCodeUsed,
+    #[field(value = "recovery_codes_regenerated")]
+    RecoveryCodesRegenerated,
+    #[field(value = "file_uploaded")]
+    FileUploaded,
+    #[field(value = "file_downloaded")]
+    FileDownloaded,
+    /// A user let an OAuth client act on their behalf, the target is the
+    /// client id
+    #[field(value = "client_authorized")]
+    ClientAuthorized,
+}
+
+/// `prev_hash` of the first event of the audit log.
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// A security relevant action, as stored in the audit log.
+///
+/// Events form a hash chain: each one includes the hash of the event before
+/// it, so editing or removing an event breaks every link after it.
+#[derive(Serialize, Debug, Clone, Deserialize)]
+pub struct AuditEvent {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub event_id: Option<bson::oid::ObjectId>,
+    /// Position in the chain, starting at 1
+    pub seq: i64,
+    pub kind: AuditEventKind,
+    /// Id of the user who acted, if known
+    pub actor: Option<String>,
+    /// Account or file acted on
+    pub target: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_at: NaiveDateTime,
+    /// `hash` of the previous event
+    pub prev_hash: String,
+    /// blake3 hash of the event's content and `prev_hash`
+    pub hash: String,
+}
+
+/// The hashed part of an `AuditEvent`.
+#[derive(Serialize)]
+struct ChainedContent<'a> {
+    seq: i64,
+    kind: AuditEventKind,
+    actor: &'a Option<String>,
+    target: &'a Option<String>,
+    ip: &'a Option<String>,
+    user_agent: &'a Option<String>,
+    created_at: &'a NaiveDateTime,
+    prev_hash: &'a str,
+}
+
+impl AuditEvent {
+    pub fn compute_hash(&self) -> String {
+        let content = ChainedContent {
+            seq: self.seq,
+            kind: self.kind,
+            actor: &self.actor,
+            target: &self.target,
+            ip: &self.ip,
+            user_agent: &self.user_agent,
+            created_at: &self.created_at,
+            prev_hash: &self.prev_hash,
+        };
+        let json = serde_json::to_vec(&content).unwrap();
+        blake3::hash(&json).to_hex().to_string()
+    }
+
+    /// Appends the event to the chain ending in `head`.
+    pub fn link(&mut self, head: Option<&AuditEvent>) {
+        self.seq = head.map_or(1, |head| head.seq + 1);
+        self.prev_hash = head.map_or_else(|| GENESIS_HASH.to_owned(), |head| head.hash.clone());
+        self.hash = self.compute_hash();
+    }
+}
+
+/// Query of the admin audit log. Events are returned newest first and paged
+/// with the `before` cursor returned alongside the previous page.
+#[derive(FromForm, Debug, Clone)]
+pub struct AuditQuery {
+    #[field(default = 50, validate = range(1..=200))]
+    pub limit: i64,
+    pub before: Option<String>,
+    pub kind: Option<AuditEventKind>,
+    pub actor: Option<String>,
+    pub target: Option<String>,
+}
+
+/// Criteria of a single page of the audit log.
+#[derive(Debug, Clone)]
+pub struct AuditFilter {
+    pub kind: Option<AuditEventKind>,
+    pub actor: Option<String>,
+    pub target: Option<String>,
+    /// Only events older than this one
+    pub before: Option<bson::oid::ObjectId>,
+    pub limit: i64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct AuditPage {
+    pub events: Vec<AuditEvent>,
+    pub next_cursor: Option<String>,
+}
+
+/// Result of walking the audit log's hash chain.
+#[derive(Serialize, Debug, Clone)]
+pub struct ChainVerification {
+    pub valid: bool,
+    /// Events checked, up to the first broken link
+    pub checked: i64,
+    /// Last event of the chain, to compare against earlier exports
+    pub head_seq: Option<i64>,
+    pub head_hash: Option<String>,
+    pub broken_link: Option<BrokenLink>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct BrokenLink {
+    pub seq: i64,
+    pub event_id: Option<bson::oid::ObjectId>,
+    pub reason: String,
+}
+
+/// Range of the audit log to export, by `seq`.
+#[derive(FromForm, Debug, Clone)]
+pub struct AuditExportQuery {
+    #[field(validate = range(1..))]
+    pub from: i64,
+    #[field(validate = range(1..))]
+    pub to: i64,
+}
+
+/// A range of the audit log, as exported.
+#[derive(Serialize, Debug, Clone)]
+pub struct AuditExport {
+    pub from: i64,
+    pub to: i64,
+    pub exported_at: NaiveDateTime,
+    pub events: Vec<AuditEvent>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct SignedAuditExport {
+    pub export: AuditExport,
+    /// Detached JWS of `export`, serialized as compact JSON, verifiable with
+    /// the keys at `/.well-known/jwks.json`
+    pub signature: String,
+}
@@ -0,0 +1,15 @@
+use crate::utils::mongo_util::bson_datetime;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+/// Failed sign-ins counted for an account or a client address.
+#[derive(Serialize, Debug, Clone, Deserialize)]
+pub struct LoginAttempts {
+    /// What the failures are counted for, e.g. `account:<email>` or `ip:<address>`
+    #[serde(rename = "_id")]
+    pub key: String,
+    pub failures: i64,
+    /// Counters are removed by a TTL index once their failures are forgotten
+    #[serde(with = "bson_datetime")]
+    pub last_failure: NaiveDateTime,
+}
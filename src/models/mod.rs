@@ -1,2 +1,8 @@
 pub mod user;
-pub mod file;
\ No newline at end of file
+pub mod file;
+pub mod token;
+pub mod role;
+pub mod login_attempt;
+pub mod audit;
+pub mod oauth;
+pub mod oidc;
@@ -0,0 +1,213 @@
+use crate::models::role::Permission;
+use crate::models::user::UserType;
+use crate::utils::mongo_util::bson_datetime;
+use chrono::NaiveDateTime;
+use mongodb::bson;
+use rocket::form::FromForm;
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+/// What an OAuth client may do on behalf of a user. Each scope stands for
+/// permissions of the user's role, so a user can only grant scopes their
+/// role holds.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    /// Signing in with OpenID Connect, which adds an ID token to the token
+    /// response and allows reading `/userinfo`
+    #[serde(rename = "openid")]
+    OpenId,
+    Profile,
+    Files,
+    Admin,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 4] = [Scope::OpenId, Scope::Profile, Scope::Files, Scope::Admin];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Scope::OpenId => "openid",
+            Scope::Profile => "profile",
+            Scope::Files => "files",
+            Scope::Admin => "admin",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Scope> {
+        Self::ALL.iter().copied().find(|scope| scope.name() == name)
+    }
+
+    /// Scopes of a space separated `scope` parameter, `None` if any of them
+    /// is unknown.
+    pub fn parse_list(scopes: &str) -> Option<Vec<Scope>> {
+        let mut parsed: Vec<Scope> = vec![];
+        for scope in scopes.split_whitespace().map(Self::parse) {
+            let scope = scope?;
+            if !parsed.contains(&scope) {
+                parsed.push(scope);
+            }
+        }
+        Some(parsed)
+    }
+
+    /// Scopes as a space separated `scope` parameter.
+    pub fn join(scopes: &[Scope]) -> String {
+        scopes.iter().map(Scope::name).collect::<Vec<_>>().join(" ")
+    }
+
+    pub fn permissions(&self) -> &'static [Permission] {
+        match self {
+            Scope::OpenId => &[Permission::ViewIdentity],
+            Scope::Profile => &[Permission::ManageOwnAccount],
+            Scope::Files => &[Permission::TransferFiles],
+            Scope::Admin => &[
+                Permission::ListUsers,
+                Permission::UnlockAccounts,
+                Permission::DeleteAnyUser,
+            ],
+        }
+    }
+
+    /// Shown on the consent page.
+    pub fn description(&self) -> &'static str {
+        match self {
+            Scope::OpenId => "See your name and email address",
+            Scope::Profile => "View and update your profile",
+            Scope::Files => "Upload and download files",
+            Scope::Admin => "Look up, unlock and delete user accounts",
+        }
+    }
+
+    pub fn allowed_for(&self, user_type: &UserType) -> bool {
+        self.permissions()
+            .iter()
+            .all(|permission| user_type.has_permission(*permission))
+    }
+}
+
+/// An application allowed to request tokens on behalf of users.
+#[derive(Serialize, Debug, Clone, Deserialize)]
+pub struct OAuthClient {
+    #[serde(rename = "_id")]
+    pub client_id: String,
+    pub name: String,
+    /// Hash of the secret of confidential clients, public clients have none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret_hash: Option<String>,
+    /// Exact URIs codes may be sent to
+    pub redirect_uris: Vec<String>,
+    /// Scopes the client may request
+    pub scopes: Vec<Scope>,
+    pub created_at: NaiveDateTime,
+}
+
+/// A registered client as shown to administrators.
+#[derive(Serialize, Debug, Clone)]
+pub struct RegisteredClient {
+    pub client_id: String,
+    pub name: String,
+    pub confidential: bool,
+    pub redirect_uris: Vec<String>,
+    pub scopes: Vec<Scope>,
+    pub created_at: NaiveDateTime,
+}
+
+impl From<OAuthClient> for RegisteredClient {
+    fn from(client: OAuthClient) -> Self {
+        Self {
+            client_id: client.client_id,
+            name: client.name,
+            confidential: client.secret_hash.is_some(),
+            redirect_uris: client.redirect_uris,
+            scopes: client.scopes,
+            created_at: client.created_at,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Deserialize, Validate, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RegisterClient {
+    #[validate(length(min = 1, max = 100))]
+    pub name: String,
+    #[validate(length(min = 1, max = 10))]
+    pub redirect_uris: Vec<String>,
+    #[validate(length(min = 1))]
+    pub scopes: Vec<Scope>,
+    /// Confidential clients, such as server side applications, get a secret
+    /// they have to present when exchanging codes
+    pub confidential: bool,
+}
+
+/// A single-use code handed to a client after the user consented. Only the
+/// hash of the code is kept.
+#[derive(Serialize, Debug, Clone, Deserialize)]
+pub struct AuthorizationCode {
+    #[serde(rename = "_id")]
+    pub code_hash: String,
+    pub client_id: String,
+    pub user_id: bson::oid::ObjectId,
+    pub redirect_uri: String,
+    pub scopes: Vec<Scope>,
+    /// PKCE S256 challenge the code verifier has to match
+    pub code_challenge: String,
+    /// OpenID Connect nonce to put in the ID token
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    /// Expired codes are removed by a TTL index
+    #[serde(with = "bson_datetime")]
+    pub expires_at: NaiveDateTime,
+}
+
+/// Parameters of an authorization request, RFC 6749 section 4.1.1 with the
+/// PKCE parameters of RFC 7636 and the OpenID Connect nonce.
+#[derive(FromForm, Serialize, Debug, Deserialize, Clone)]
+pub struct AuthorizeRequest {
+    pub response_type: Option<String>,
+    pub client_id: Option<String>,
+    pub redirect_uri: Option<String>,
+    pub scope: Option<String>,
+    pub state: Option<String>,
+    pub code_challenge: Option<String>,
+    pub code_challenge_method: Option<String>,
+    pub nonce: Option<String>,
+}
+
+/// The login and consent form, posted back with the authorization request.
+#[derive(FromForm, Debug, Clone)]
+pub struct AuthorizeDecision {
+    pub request: AuthorizeRequest,
+    pub csrf_token: String,
+    /// Only present when the user is not signed in yet
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Code from the authenticator, for accounts with two-factor
+    /// authentication
+    pub otp: Option<String>,
+    /// `approve` or `deny`
+    pub decision: String,
+}
+
+/// Parameters of a token request, RFC 6749 section 4.1.3. Missing
+/// parameters are reported as OAuth errors, so all of them are optional.
+#[derive(FromForm, Debug, Clone)]
+pub struct TokenRequest {
+    pub grant_type: Option<String>,
+    pub code: Option<String>,
+    pub redirect_uri: Option<String>,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+    pub code_verifier: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub token_type: String,
+    pub expires_in: i64,
+    pub scope: String,
+    /// Issued when the `openid` scope was granted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id_token: Option<String>,
+}
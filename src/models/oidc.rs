@@ -0,0 +1,57 @@
+use crate::config::signing::SigningAlgorithm;
+use crate::models::user::{User, UserTags, UserType};
+use serde::Serialize;
+use serde_json::Value;
+
+/// OpenID Connect provider metadata, served at
+/// `/.well-known/openid-configuration`.
+#[derive(Serialize, Debug, Clone)]
+pub struct DiscoveryDocument {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub userinfo_endpoint: String,
+    pub jwks_uri: String,
+    pub scopes_supported: Vec<&'static str>,
+    pub response_types_supported: Vec<&'static str>,
+    pub grant_types_supported: Vec<&'static str>,
+    pub subject_types_supported: Vec<&'static str>,
+    pub id_token_signing_alg_values_supported: Vec<SigningAlgorithm>,
+    pub token_endpoint_auth_methods_supported: Vec<&'static str>,
+    pub code_challenge_methods_supported: Vec<&'static str>,
+    pub claims_supported: Vec<&'static str>,
+}
+
+/// A JSON Web Key Set, served at `/.well-known/jwks.json`.
+#[derive(Serialize, Debug, Clone)]
+pub struct Jwks {
+    pub keys: Vec<Value>,
+}
+
+/// Claims about the signed-in user returned by `/userinfo`.
+#[derive(Serialize, Debug, Clone)]
+pub struct UserInfo {
+    pub sub: String,
+    pub name: String,
+    pub given_name: String,
+    pub family_name: String,
+    pub email: String,
+    pub email_verified: bool,
+    pub user_type: UserType,
+    pub user_tags: Vec<UserTags>,
+}
+
+impl From<&User> for UserInfo {
+    fn from(user: &User) -> Self {
+        Self {
+            sub: user.user_id.as_ref().map(|id| id.to_hex()).unwrap_or_default(),
+            name: format!("{} {}", user.first_name, user.last_name),
+            given_name: user.first_name.clone(),
+            family_name: user.last_name.clone(),
+            email: user.email_id.clone(),
+            email_verified: user.email_verified,
+            user_type: user.user_type.clone(),
+            user_tags: user.user_tags.clone(),
+        }
+    }
+}
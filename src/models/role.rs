@@ -0,0 +1,93 @@
+use crate::models::user::UserType;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Permission {
+    /// Read who the user is, as `/userinfo` returns it
+    ViewIdentity,
+    ManageOwnAccount,
+    /// Change the password, second factor or existence of the own account.
+    /// No OAuth scope grants it, so only first-party tokens hold it.
+    ManageCredentials,
+    TransferFiles,
+    ListUsers,
+    /// Lift a sign-in lockout from any account
+    UnlockAccounts,
+    DeleteAnyUser,
+    /// List every account, manage OAuth clients and read the audit log. No
+    /// OAuth scope grants it, so client tokens never act as an administrator.
+    ManageService,
+}
+
+impl UserType {
+    pub fn permissions(&self) -> &'static [Permission] {
+        match self {
+            UserType::Customer | UserType::Worker => &[
+                Permission::ViewIdentity,
+                Permission::ManageOwnAccount,
+                Permission::ManageCredentials,
+                Permission::TransferFiles,
+            ],
+            UserType::Support => &[
+                Permission::ViewIdentity,
+                Permission::ManageOwnAccount,
+                Permission::ManageCredentials,
+                Permission::TransferFiles,
+                Permission::ListUsers,
+                Permission::UnlockAccounts,
+            ],
+            UserType::Admin => &[
+                Permission::ViewIdentity,
+                Permission::ManageOwnAccount,
+                Permission::ManageCredentials,
+                Permission::TransferFiles,
+                Permission::ListUsers,
+                Permission::UnlockAccounts,
+                Permission::DeleteAnyUser,
+                Permission::ManageService,
+            ],
+        }
+    }
+
+    pub fn has_permission(&self, permission: Permission) -> bool {
+        self.permissions().contains(&permission)
+    }
+
+    /// Staff accounts are provisioned by an administrator and cannot be
+    /// chosen at sign-up.
+    pub fn is_self_assignable(&self) -> bool {
+        matches!(self, UserType::Customer | UserType::Worker)
+    }
+}
+
+/// Marker types used with `RequireRole` to restrict a route to a role.
+pub trait Role: Send + Sync + 'static {
+    const NAME: &'static str;
+    /// Permission OAuth access tokens need a scope for to act in the role
+    const PERMISSION: Permission;
+
+    fn allows(user_type: &UserType) -> bool;
+}
+
+pub enum Admin {}
+
+impl Role for Admin {
+    const NAME: &'static str = "Admin";
+    const PERMISSION: Permission = Permission::ManageService;
+
+    fn allows(user_type: &UserType) -> bool {
+        *user_type == UserType::Admin
+    }
+}
+
+/// Support staff, who look up accounts to help their owners. Administrators
+/// can do anything support can.
+pub enum Support {}
+
+impl Role for Support {
+    const NAME: &'static str = "Support";
+    const PERMISSION: Permission = Permission::ListUsers;
+
+    fn allows(user_type: &UserType) -> bool {
+        matches!(user_type, UserType::Support | UserType::Admin)
+    }
+}
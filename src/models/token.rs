@@ -0,0 +1,158 @@
+use crate::models::oauth::Scope;
+use crate::models::role::Permission;
+use crate::models::user::{User, UserTags, UserType};
+use crate::utils::mongo_util::bson_datetime;
+use chrono::NaiveDateTime;
+use mongodb::bson;
+use rocket::form::FromForm;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Debug, Clone, Deserialize)]
+pub struct Claims {
+    pub sub: String,
+    pub exp: i64,
+    pub iat: i64,
+    pub iss: String,
+    /// Refresh token family the access token was issued for
+    pub sid: String,
+    /// `User.token_version` at the time the token was issued
+    #[serde(default)]
+    pub ver: i64,
+    pub user_type: UserType,
+    pub user_tags: Vec<UserTags>,
+    /// Space separated scopes of tokens issued to an OAuth client. Tokens
+    /// without scopes were issued to the user directly.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    /// OAuth client the token was issued to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+}
+
+impl Claims {
+    /// Whether the token may be used for `permission`, on top of the user's
+    /// role holding it.
+    pub fn allows(&self, permission: Permission) -> bool {
+        self.scope.as_deref().is_none_or(|scope| {
+            scope
+                .split_whitespace()
+                .filter_map(Scope::parse)
+                .any(|scope| scope.permissions().contains(&permission))
+        })
+    }
+}
+
+/// Claims of an OpenID Connect ID token, issued to clients granted the
+/// `openid` scope.
+#[derive(Serialize, Debug, Clone, Deserialize)]
+pub struct IdClaims {
+    pub iss: String,
+    pub sub: String,
+    /// Client the token was issued to
+    pub aud: String,
+    pub exp: i64,
+    pub iat: i64,
+    /// Echoed from the authorization request, to tie the token to it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    pub name: String,
+    pub email: String,
+    pub email_verified: bool,
+}
+
+#[derive(Serialize, Debug, Clone, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenPurpose {
+    VerifyEmail,
+    ResetPassword,
+    /// Issued for a correct password when a second factor is still needed
+    MfaChallenge,
+}
+
+/// Claims of a single-purpose token sent to the user, e.g. by email.
+#[derive(Serialize, Debug, Clone, Deserialize)]
+pub struct ActionClaims {
+    pub sub: String,
+    pub exp: i64,
+    pub iat: i64,
+    pub iss: String,
+    pub purpose: TokenPurpose,
+    /// Binds the token to the password it was issued for, so that it stops
+    /// working once the password changes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pwd: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone, Deserialize)]
+pub struct AuthResponse {
+    pub user: User,
+    pub access_token: String,
+    pub refresh_token: String,
+    pub token_type: String,
+    pub expires_in: i64,
+}
+
+/// Stored form of an opaque refresh token. Only the hash of the token is kept;
+/// every token issued from the same sign-in shares a `family_id`.
+#[derive(Serialize, Debug, Clone, Deserialize)]
+pub struct RefreshToken {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub token_id: Option<bson::oid::ObjectId>,
+    pub user_id: bson::oid::ObjectId,
+    pub family_id: String,
+    pub token_hash: String,
+    /// Expired tokens are removed by a TTL index
+    #[serde(with = "bson_datetime")]
+    pub expires_at: NaiveDateTime,
+    pub used_at: Option<NaiveDateTime>,
+    pub revoked: bool,
+    pub created_at: NaiveDateTime,
+}
+
+/// Answer to a correct password when the account has a second factor: the
+/// challenge token is traded for real tokens at `/auth/mfa/verify`.
+#[derive(Serialize, Debug, Clone, Deserialize)]
+pub struct MfaChallenge {
+    pub mfa_required: bool,
+    pub challenge_token: String,
+    pub expires_in: i64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum LoginOutcome {
+    Authenticated(Box<AuthResponse>),
+    MfaRequired(MfaChallenge),
+}
+
+/// What a second factor was proven with.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SecondFactor {
+    Totp,
+    RecoveryCode,
+}
+
+/// A code from the authenticator, or one of the recovery codes where
+/// accepted.
+#[derive(FromForm, Serialize, Debug, Deserialize, Clone)]
+pub struct MfaCode {
+    pub code: String,
+}
+
+#[derive(FromForm, Serialize, Debug, Deserialize, Clone)]
+pub struct MfaVerifyRequest {
+    pub challenge_token: String,
+    /// Code from the authenticator or an unused recovery code
+    pub code: String,
+}
+
+#[derive(FromForm, Serialize, Debug, Deserialize, Clone)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+#[derive(FromForm, Serialize, Debug, Deserialize, Clone)]
+pub struct ResendVerification {
+    pub email_id: String,
+}
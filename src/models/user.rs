@@ -2,17 +2,18 @@ use chrono::NaiveDateTime;
 use mongodb::bson;
 use rocket::form::FromForm;
 use serde::{Deserialize, Serialize};
-use serde_json::{json, Value};
 use strum_macros::{EnumString, EnumVariantNames};
 use validator::Validate;
 
-#[derive(Debug, Serialize, Deserialize, FromFormField, Clone)]
+#[derive(Debug, Serialize, Deserialize, FromFormField, Clone, PartialEq)]
 pub enum UserType {
     Customer,
     Worker,
+    Admin,
+    Support,
 }
 
-#[derive(Debug, Serialize, Deserialize, FromFormField, Clone, EnumString, EnumVariantNames)]
+#[derive(Debug, Serialize, Deserialize, FromFormField, Clone, PartialEq, EnumString, EnumVariantNames)]
 // #[strum(serialize_all = "kebab_case")]
 pub enum UserTags {
     WebDevelopment,
@@ -36,10 +37,35 @@ pub struct User {
     pub user_tags: Vec<UserTags>,
     pub bio: Option<String>,
     pub image: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    /// Bumped to invalidate every access token issued so far
+    #[serde(default, skip_serializing)]
+    pub token_version: i64,
+    /// Base32 TOTP secret, set on enrolment and only used once confirmed
+    #[serde(default, skip_serializing)]
+    pub totp_secret: Option<String>,
+    #[serde(default)]
+    pub totp_enabled: bool,
+    /// Time step of the last accepted code, which may not be used again
+    #[serde(default, skip_serializing)]
+    pub totp_last_step: Option<i64>,
+    /// Argon2 hashes of the unused recovery codes
+    #[serde(default, skip_serializing)]
+    pub recovery_codes: Vec<String>,
     pub created_at: Option<NaiveDateTime>,
     pub updated_at: Option<NaiveDateTime>,
 }
 
+/// Document written to the db when a user registers.
+#[derive(Serialize, Debug, Clone)]
+pub struct NewUser {
+    #[serde(flatten)]
+    pub user: RegisterUser,
+    pub email_verified: bool,
+    pub created_at: NaiveDateTime,
+}
+
 #[derive(FromForm, Serialize, Debug, Deserialize, Validate, Clone)]
 pub struct RegisterUser {
     #[validate(length(min = 3))]
@@ -54,10 +80,12 @@ pub struct RegisterUser {
     pub password: String,
 }
 
-#[derive(FromForm, Serialize, Debug, Deserialize, Clone)]
+#[derive(FromForm, Serialize, Debug, Deserialize, Validate, Clone)]
 pub struct LoginUser {
+    #[validate(email)]
     pub username: String,
     // #[serde(skip_serializing)]
+    #[validate(length(min = 1))]
     pub password: String,
 }
 
@@ -65,3 +93,206 @@ pub struct LoginUser {
 pub struct DeleteUser {
     pub username: String,
 }
+
+#[derive(Serialize, Debug, Deserialize, Clone)]
+pub struct FindUser {
+    #[serde(default, rename = "_id")]
+    pub user_id: Option<bson::oid::ObjectId>,
+    pub email_id: Option<String>,
+}
+
+#[derive(FromForm, Serialize, Debug, Deserialize, Clone)]
+pub struct ForgotPassword {
+    pub email_id: String,
+}
+
+#[derive(FromForm, Serialize, Debug, Deserialize, Clone)]
+pub struct ResetPassword {
+    pub token: String,
+    pub new_password: String,
+}
+
+#[derive(FromForm, Serialize, Debug, Deserialize, Clone)]
+pub struct ChangePassword {
+    pub current_password: String,
+    pub new_password: String,
+    /// Sign out every other session of the user, `false` when omitted
+    pub revoke_other_sessions: Option<bool>,
+}
+
+/// Partial profile update, only the fields present are changed. Password,
+/// email and avatar have their own flows and are rejected here.
+#[derive(Serialize, Debug, Deserialize, Validate, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct UpdateProfile {
+    #[validate(length(min = 3))]
+    pub first_name: Option<String>,
+    #[validate(length(min = 3))]
+    pub last_name: Option<String>,
+    #[validate(length(max = 1000))]
+    pub bio: Option<String>,
+    pub user_tags: Option<Vec<UserTags>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, FromFormField)]
+pub enum SortOrder {
+    #[field(value = "asc")]
+    Ascending,
+    #[field(value = "desc")]
+    Descending,
+}
+
+/// Query of the admin user listing. Results are sorted by `created_at` and
+/// paged with the opaque `cursor` returned alongside the previous page.
+#[derive(FromForm, Debug, Clone)]
+pub struct UserListQuery {
+    #[field(default = 20, validate = range(1..=100))]
+    pub limit: i64,
+    pub cursor: Option<String>,
+    /// Newest first unless given
+    pub order: Option<SortOrder>,
+    pub user_type: Option<UserType>,
+    pub user_tags: Vec<UserTags>,
+    pub verified: Option<bool>,
+    /// Case-insensitive search on first name, last name and email
+    pub q: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct UserPage {
+    pub users: Vec<User>,
+    pub next_cursor: Option<String>,
+}
+
+/// Position of a user in the `created_at`, `_id` ordering of the listing.
+/// Users without a `created_at` sort before every other user.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PageCursor {
+    pub created_at: Option<NaiveDateTime>,
+    pub user_id: bson::oid::ObjectId,
+}
+
+impl PageCursor {
+    pub fn encode(&self) -> String {
+        let json = serde_json::to_vec(self).unwrap();
+        base64::encode_config(json, base64::URL_SAFE_NO_PAD)
+    }
+
+    pub fn decode(cursor: &str) -> Option<Self> {
+        let json = base64::decode_config(cursor, base64::URL_SAFE_NO_PAD).ok()?;
+        serde_json::from_slice(&json).ok()
+    }
+}
+
+impl From<&User> for PageCursor {
+    fn from(user: &User) -> Self {
+        Self {
+            created_at: user.created_at,
+            user_id: user.user_id.clone().unwrap(),
+        }
+    }
+}
+
+/// Criteria of a single page of the user listing.
+#[derive(Debug, Clone)]
+pub struct UserFilter {
+    pub user_type: Option<UserType>,
+    pub user_tags: Vec<UserTags>,
+    pub verified: Option<bool>,
+    pub text: Option<String>,
+    pub after: Option<PageCursor>,
+    pub order: SortOrder,
+    pub limit: i64,
+}
+
+/// Changes to apply to a stored user, `None` fields are left untouched.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct UserChanges {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bio: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_tags: Option<Vec<UserTags>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_type: Option<UserType>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email_verified: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_version: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub totp_secret: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub totp_enabled: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub totp_last_step: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recovery_codes: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<NaiveDateTime>,
+}
+
+impl UserChanges {
+    pub fn apply(self, user: &mut User) {
+        if let Some(first_name) = self.first_name {
+            user.first_name = first_name;
+        }
+        if let Some(last_name) = self.last_name {
+            user.last_name = last_name;
+        }
+        if let Some(bio) = self.bio {
+            user.bio = Some(bio);
+        }
+        if let Some(image) = self.image {
+            user.image = Some(image);
+        }
+        if let Some(user_tags) = self.user_tags {
+            user.user_tags = user_tags;
+        }
+        if let Some(user_type) = self.user_type {
+            user.user_type = user_type;
+        }
+        if let Some(password) = self.password {
+            user.password = Some(password);
+        }
+        if let Some(email_verified) = self.email_verified {
+            user.email_verified = email_verified;
+        }
+        if let Some(token_version) = self.token_version {
+            user.token_version = token_version;
+        }
+        if let Some(totp_secret) = self.totp_secret {
+            user.totp_secret = Some(totp_secret);
+        }
+        if let Some(totp_enabled) = self.totp_enabled {
+            user.totp_enabled = totp_enabled;
+        }
+        if let Some(totp_last_step) = self.totp_last_step {
+            user.totp_last_step = Some(totp_last_step);
+        }
+        if let Some(recovery_codes) = self.recovery_codes {
+            user.recovery_codes = recovery_codes;
+        }
+        if let Some(updated_at) = self.updated_at {
+            user.updated_at = Some(updated_at);
+        }
+    }
+}
+
+impl From<UpdateProfile> for UserChanges {
+    fn from(profile: UpdateProfile) -> Self {
+        Self {
+            first_name: profile.first_name,
+            last_name: profile.last_name,
+            bio: profile.bio,
+            user_tags: profile.user_tags,
+            ..Default::default()
+        }
+    }
+}
@@ -0,0 +1,196 @@
+use crate::handlers::error::AuthenticationError;
+use crate::models::audit::{AuditEvent, AuditFilter};
+use crate::repository::is_duplicate_key;
+use crate::utils::mongo_util::AUDIT_COLLECTION;
+use futures::lock::Mutex;
+use futures::stream::TryStreamExt;
+use mongodb::{
+    bson::{self, doc, oid::ObjectId, Bson, Document},
+    error::Error,
+    options::{FindOneOptions, FindOptions},
+    Collection, Database,
+};
+use std::sync::RwLock;
+
+/// Storage of the audit log. Events are only ever appended.
+#[rocket::async_trait]
+pub trait AuditRepository: Send + Sync {
+    /// Links `event` to the last event of the chain and stores it.
+    async fn append(&self, event: AuditEvent) -> Result<AuditEvent, AuthenticationError>;
+
+    /// Events matching `filter`, newest first.
+    async fn list(&self, filter: &AuditFilter) -> Result<Vec<AuditEvent>, AuthenticationError>;
+
+    /// Up to `limit` events with a `seq` from `from` to `to`, oldest first.
+    async fn range(
+        &self,
+        from: i64,
+        to: Option<i64>,
+        limit: i64,
+    ) -> Result<Vec<AuditEvent>, AuthenticationError>;
+}
+
+pub struct MongoAuditRepository {
+    events: Collection,
+    /// Serializes appends from this process, other processes are caught by
+    /// the unique index on `seq`
+    append_lock: Mutex<()>,
+}
+
+impl MongoAuditRepository {
+    pub fn new(database: &Database) -> Self {
+        Self {
+            events: database.collection(AUDIT_COLLECTION),
+            append_lock: Mutex::new(()),
+        }
+    }
+
+    /// Creates the unique index on `seq` that keeps the chain from forking.
+    pub async fn create_indexes(database: &Database) -> Result<(), Error> {
+        database
+            .run_command(
+                doc! {
+                    "createIndexes": AUDIT_COLLECTION,
+                    "indexes": [{ "key": { "seq": 1 }, "name": "seq", "unique": true }],
+                },
+                None,
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn head(&self) -> Result<Option<AuditEvent>, AuthenticationError> {
+        let options = FindOneOptions::builder().sort(doc! { "seq": -1 }).build();
+        self.events
+            .find_one(doc! {}, options)
+            .await?
+            .map(|document| {
+                bson::from_bson(Bson::Document(document))
+                    .map_err(|err| AuthenticationError::DbError(err.to_string()))
+            })
+            .transpose()
+    }
+}
+
+#[rocket::async_trait]
+impl AuditRepository for MongoAuditRepository {
+    async fn append(&self, mut event: AuditEvent) -> Result<AuditEvent, AuthenticationError> {
+        let _guard = self.append_lock.lock().await;
+        event.event_id = Some(ObjectId::new());
+        loop {
+            event.link(self.head().await?.as_ref());
+            let insertable = bson::to_document(&event)
+                .map_err(|err| AuthenticationError::DbError(err.to_string()))?;
+            match self.events.insert_one(insertable, None).await {
+                Ok(_) => return Ok(event),
+                // Another process appended first, link to its event instead
+                Err(err) if is_duplicate_key(&err) => continue,
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    async fn list(&self, filter: &AuditFilter) -> Result<Vec<AuditEvent>, AuthenticationError> {
+        let mut query = doc! {};
+        if let Some(kind) = &filter.kind {
+            query.insert("kind", bson::to_bson(kind).unwrap());
+        }
+        if let Some(actor) = &filter.actor {
+            query.insert("actor", actor);
+        }
+        if let Some(target) = &filter.target {
+            query.insert("target", target);
+        }
+        if let Some(before) = &filter.before {
+            query.insert("_id", doc! { "$lt": before.clone() });
+        }
+
+        let options = FindOptions::builder()
+            .sort(doc! { "_id": -1 })
+            .limit(filter.limit)
+            .build();
+        let cursor = self.events.find(query, options).await?;
+        let documents: Vec<Document> = cursor.try_collect().await?;
+        documents
+            .into_iter()
+            .map(|document| {
+                bson::from_bson(Bson::Document(document))
+                    .map_err(|err| AuthenticationError::DbError(err.to_string()))
+            })
+            .collect()
+    }
+
+    async fn range(
+        &self,
+        from: i64,
+        to: Option<i64>,
+        limit: i64,
+    ) -> Result<Vec<AuditEvent>, AuthenticationError> {
+        let mut seq = doc! { "$gte": from };
+        if let Some(to) = to {
+            seq.insert("$lte", to);
+        }
+        let options = FindOptions::builder()
+            .sort(doc! { "seq": 1 })
+            .limit(limit)
+            .build();
+        let cursor = self.events.find(doc! { "seq": seq }, options).await?;
+        let documents: Vec<Document> = cursor.try_collect().await?;
+        documents
+            .into_iter()
+            .map(|document| {
+                bson::from_bson(Bson::Document(document))
+                    .map_err(|err| AuthenticationError::DbError(err.to_string()))
+            })
+            .collect()
+    }
+}
+
+/// Keeps the audit log in memory, for tests and running without a database.
+#[derive(Default)]
+pub struct InMemoryAuditRepository {
+    events: RwLock<Vec<AuditEvent>>,
+}
+
+#[rocket::async_trait]
+impl AuditRepository for InMemoryAuditRepository {
+    async fn append(&self, mut event: AuditEvent) -> Result<AuditEvent, AuthenticationError> {
+        let mut events = self.events.write().unwrap();
+        event.event_id = Some(ObjectId::new());
+        event.link(events.last());
+        events.push(event.clone());
+        Ok(event)
+    }
+
+    async fn list(&self, filter: &AuditFilter) -> Result<Vec<AuditEvent>, AuthenticationError> {
+        let events = self.events.read().unwrap();
+        let before = filter.before.as_ref().map(ObjectId::bytes);
+        Ok(events
+            .iter()
+            .rev()
+            .filter(|event| filter.kind.is_none_or(|kind| event.kind == kind))
+            .filter(|event| filter.actor.is_none() || event.actor == filter.actor)
+            .filter(|event| filter.target.is_none() || event.target == filter.target)
+            .filter(|event| {
+                before.is_none_or(|before| event.event_id.as_ref().unwrap().bytes() < before)
+            })
+            .take(filter.limit as usize)
+            .cloned()
+            .collect())
+    }
+
+    async fn range(
+        &self,
+        from: i64,
+        to: Option<i64>,
+        limit: i64,
+    ) -> Result<Vec<AuditEvent>, AuthenticationError> {
+        let events = self.events.read().unwrap();
+        Ok(events
+            .iter()
+            .filter(|event| event.seq >= from && to.is_none_or(|to| event.seq <= to))
+            .take(limit as usize)
+            .cloned()
+            .collect())
+    }
+}
@@ -0,0 +1,212 @@
+use crate::handlers::error::AuthenticationError;
+use crate::models::login_attempt::LoginAttempts;
+use crate::utils::mongo_util::{bson_datetime, LOGIN_ATTEMPT_COLLECTION};
+use chrono::NaiveDateTime;
+use mongodb::bson::{self, doc, Bson};
+use mongodb::error::{Error, ErrorKind};
+use mongodb::options::{FindOneAndUpdateOptions, ReturnDocument};
+use mongodb::{Collection, Database};
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+/// Server error code of an index that exists with other options.
+const INDEX_OPTIONS_CONFLICT: i32 = 85;
+
+/// Storage of failed sign-in counters.
+#[rocket::async_trait]
+pub trait LoginAttemptRepository: Send + Sync {
+    async fn find(&self, key: &str) -> Result<Option<LoginAttempts>, AuthenticationError>;
+
+    /// Counts an attempt for `key` as failed before it is verified, returning
+    /// the counter as it was before, `None` if there was none. A new counter
+    /// has its `last_failure` at `at`; others keep theirs until the attempt
+    /// turns out to have failed.
+    async fn count_attempt(
+        &self,
+        key: &str,
+        at: NaiveDateTime,
+    ) -> Result<Option<LoginAttempts>, AuthenticationError>;
+
+    /// Moves the `last_failure` of `key` up to `at`, once a counted attempt
+    /// has failed.
+    async fn record_failure(&self, key: &str, at: NaiveDateTime)
+        -> Result<(), AuthenticationError>;
+
+    /// Takes back one failure counted for `key`.
+    async fn forgive(&self, key: &str) -> Result<(), AuthenticationError>;
+
+    /// Forgets every failure counted for `key`, returning `false` if there
+    /// were none.
+    async fn reset(&self, key: &str) -> Result<bool, AuthenticationError>;
+}
+
+pub struct MongoLoginAttemptRepository {
+    attempts: Collection,
+}
+
+impl MongoLoginAttemptRepository {
+    pub fn new(database: &Database) -> Self {
+        Self {
+            attempts: database.collection(LOGIN_ATTEMPT_COLLECTION),
+        }
+    }
+
+    /// Has the server remove counters `failure_window` seconds after their
+    /// last failure, when they no longer count.
+    pub async fn create_indexes(database: &Database, failure_window: i64) -> Result<(), Error> {
+        let index = doc! {
+            "key": { "last_failure": 1 },
+            "name": "last_failure",
+            "expireAfterSeconds": failure_window,
+        };
+        let created = database
+            .run_command(
+                doc! { "createIndexes": LOGIN_ATTEMPT_COLLECTION, "indexes": [index] },
+                None,
+            )
+            .await;
+        match created {
+            // The index exists with the expiry of another failure window
+            Err(err) if is_index_options_conflict(&err) => {
+                let index = doc! {
+                    "keyPattern": { "last_failure": 1 },
+                    "expireAfterSeconds": failure_window,
+                };
+                database
+                    .run_command(doc! { "collMod": LOGIN_ATTEMPT_COLLECTION, "index": index }, None)
+                    .await?;
+                Ok(())
+            }
+            created => created.map(|_| ()),
+        }
+    }
+}
+
+fn is_index_options_conflict(err: &Error) -> bool {
+    matches!(err.kind.as_ref(), ErrorKind::CommandError(err) if err.code == INDEX_OPTIONS_CONFLICT)
+}
+
+#[rocket::async_trait]
+impl LoginAttemptRepository for MongoLoginAttemptRepository {
+    async fn find(&self, key: &str) -> Result<Option<LoginAttempts>, AuthenticationError> {
+        self.attempts
+            .find_one(doc! { "_id": key }, None)
+            .await?
+            .map(|document| {
+                bson::from_bson(Bson::Document(document))
+                    .map_err(|err| AuthenticationError::DbError(err.to_string()))
+            })
+            .transpose()
+    }
+
+    async fn count_attempt(
+        &self,
+        key: &str,
+        at: NaiveDateTime,
+    ) -> Result<Option<LoginAttempts>, AuthenticationError> {
+        let last_failure = bson_datetime::to_bson(&at);
+        let options = FindOneAndUpdateOptions::builder()
+            .upsert(true)
+            .return_document(ReturnDocument::Before)
+            .build();
+        self.attempts
+            .find_one_and_update(
+                doc! { "_id": key },
+                doc! {
+                    "$inc": { "failures": 1_i64 },
+                    "$setOnInsert": { "last_failure": last_failure },
+                },
+                options,
+            )
+            .await?
+            .map(|document| {
+                bson::from_bson(Bson::Document(document))
+                    .map_err(|err| AuthenticationError::DbError(err.to_string()))
+            })
+            .transpose()
+    }
+
+    async fn record_failure(
+        &self,
+        key: &str,
+        at: NaiveDateTime,
+    ) -> Result<(), AuthenticationError> {
+        let last_failure = bson_datetime::to_bson(&at);
+        self.attempts
+            .update_one(
+                doc! { "_id": key },
+                doc! { "$max": { "last_failure": last_failure } },
+                None,
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn forgive(&self, key: &str) -> Result<(), AuthenticationError> {
+        self.attempts
+            .update_one(
+                doc! { "_id": key, "failures": { "$gt": 0_i64 } },
+                doc! { "$inc": { "failures": -1_i64 } },
+                None,
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn reset(&self, key: &str) -> Result<bool, AuthenticationError> {
+        let deleted = self.attempts.delete_one(doc! { "_id": key }, None).await?;
+        Ok(deleted.deleted_count > 0)
+    }
+}
+
+/// Keeps failed sign-in counters in memory, for tests and running without a
+/// database.
+#[derive(Default)]
+pub struct InMemoryLoginAttemptRepository {
+    attempts: RwLock<HashMap<String, LoginAttempts>>,
+}
+
+#[rocket::async_trait]
+impl LoginAttemptRepository for InMemoryLoginAttemptRepository {
+    async fn find(&self, key: &str) -> Result<Option<LoginAttempts>, AuthenticationError> {
+        Ok(self.attempts.read().unwrap().get(key).cloned())
+    }
+
+    async fn count_attempt(
+        &self,
+        key: &str,
+        at: NaiveDateTime,
+    ) -> Result<Option<LoginAttempts>, AuthenticationError> {
+        let mut attempts = self.attempts.write().unwrap();
+        let before = attempts.get(key).cloned();
+        let counter = attempts.entry(key.to_owned()).or_insert_with(|| LoginAttempts {
+            key: key.to_owned(),
+            failures: 0,
+            last_failure: at,
+        });
+        counter.failures += 1;
+        Ok(before)
+    }
+
+    async fn record_failure(
+        &self,
+        key: &str,
+        at: NaiveDateTime,
+    ) -> Result<(), AuthenticationError> {
+        if let Some(counter) = self.attempts.write().unwrap().get_mut(key) {
+            counter.last_failure = counter.last_failure.max(at);
+        }
+        Ok(())
+    }
+
+    async fn forgive(&self, key: &str) -> Result<(), AuthenticationError> {
+        if let Some(counter) = self.attempts.write().unwrap().get_mut(key) {
+            counter.failures = (counter.failures - 1).max(0);
+        }
+        Ok(())
+    }
+
+    async fn reset(&self, key: &str) -> Result<bool, AuthenticationError> {
+        Ok(self.attempts.write().unwrap().remove(key).is_some())
+    }
+}
@@ -0,0 +1,123 @@
+pub mod audit_repository;
+pub mod login_attempt_repository;
+pub mod oauth_repository;
+pub mod refresh_token_repository;
+pub mod user_repository;
+
+use crate::config::app::AppConfig;
+use crate::utils::mongo_util::MongoUtil;
+use mongodb::error::{Error, ErrorKind, WriteFailure};
+use audit_repository::{AuditRepository, InMemoryAuditRepository, MongoAuditRepository};
+use login_attempt_repository::{
+    InMemoryLoginAttemptRepository, LoginAttemptRepository, MongoLoginAttemptRepository,
+};
+use oauth_repository::{InMemoryOAuthRepository, MongoOAuthRepository, OAuthRepository};
+use refresh_token_repository::{
+    InMemoryRefreshTokenRepository, MongoRefreshTokenRepository, RefreshTokenRepository,
+};
+use rocket::fairing::{self, Fairing, Info, Kind};
+use rocket::{Build, Rocket};
+use serde::Deserialize;
+use user_repository::{InMemoryUserRepository, MongoUserRepository, UserRepository};
+
+/// Server error code of a unique index violation.
+const DUPLICATE_KEY: i32 = 11000;
+
+/// Whether `err` is a write rejected by a unique index.
+pub(crate) fn is_duplicate_key(err: &Error) -> bool {
+    matches!(
+        err.kind.as_ref(),
+        ErrorKind::WriteError(WriteFailure::WriteError(write_error))
+            if write_error.code == DUPLICATE_KEY
+    )
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DatabaseBackend {
+    Mongo,
+    Memory,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DatabaseConfig {
+    pub backend: DatabaseBackend,
+}
+
+/// Puts the repositories of the configured backend into managed state, where
+/// they are picked up as `&State<Box<dyn UserRepository>>` and so on.
+///
+/// For the Mongo backend the pooled client is connected first and managed as
+/// well. Ignition fails if the server cannot be reached.
+pub struct Repositories;
+
+#[rocket::async_trait]
+impl Fairing for Repositories {
+    fn info(&self) -> Info {
+        Info {
+            name: "Repositories",
+            kind: Kind::Ignite,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        // Without a valid config ignition is already failing
+        let config = match rocket.state::<AppConfig>() {
+            Some(config) => config.clone(),
+            None => return Ok(rocket),
+        };
+
+        match config.database.backend {
+            DatabaseBackend::Memory => Ok(rocket
+                .manage(Box::new(InMemoryUserRepository::default()) as Box<dyn UserRepository>)
+                .manage(Box::new(InMemoryRefreshTokenRepository::default())
+                    as Box<dyn RefreshTokenRepository>)
+                .manage(Box::new(InMemoryLoginAttemptRepository::default())
+                    as Box<dyn LoginAttemptRepository>)
+                .manage(Box::new(InMemoryAuditRepository::default()) as Box<dyn AuditRepository>)
+                .manage(Box::new(InMemoryOAuthRepository::default()) as Box<dyn OAuthRepository>)),
+            DatabaseBackend::Mongo => {
+                let client = match MongoUtil::mongo_client(&config.mongo).await {
+                    Ok(client) => client,
+                    Err(err) => {
+                        log::error!("Could not connect to mongodb: {}", err);
+                        return Err(rocket);
+                    }
+                };
+                let database = client.database(&config.mongo.database);
+                if let Err(err) = MongoUserRepository::create_indexes(&database).await {
+                    log::error!("Could not create the user indexes: {}", err);
+                    return Err(rocket);
+                }
+                if let Err(err) = MongoRefreshTokenRepository::create_indexes(&database).await {
+                    log::error!("Could not create the refresh token indexes: {}", err);
+                    return Err(rocket);
+                }
+                let failure_window = config.lockout.failure_window;
+                if let Err(err) =
+                    MongoLoginAttemptRepository::create_indexes(&database, failure_window).await
+                {
+                    log::error!("Could not create the login attempt indexes: {}", err);
+                    return Err(rocket);
+                }
+                if let Err(err) = MongoAuditRepository::create_indexes(&database).await {
+                    log::error!("Could not create the audit log indexes: {}", err);
+                    return Err(rocket);
+                }
+                if let Err(err) = MongoOAuthRepository::create_indexes(&database).await {
+                    log::error!("Could not create the OAuth indexes: {}", err);
+                    return Err(rocket);
+                }
+                Ok(rocket
+                    .manage(Box::new(MongoUserRepository::new(&database)) as Box<dyn UserRepository>)
+                    .manage(Box::new(MongoRefreshTokenRepository::new(&database))
+                        as Box<dyn RefreshTokenRepository>)
+                    .manage(Box::new(MongoLoginAttemptRepository::new(&database))
+                        as Box<dyn LoginAttemptRepository>)
+                    .manage(Box::new(MongoAuditRepository::new(&database)) as Box<dyn AuditRepository>)
+                    .manage(Box::new(MongoOAuthRepository::new(&database)) as Box<dyn OAuthRepository>)
+                    .manage(client))
+            }
+        }
+    }
+}
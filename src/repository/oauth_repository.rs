@@ -0,0 +1,137 @@
+use crate::handlers::error::AuthenticationError;
+use crate::models::oauth::{AuthorizationCode, OAuthClient};
+use crate::utils::mongo_util::{AUTHORIZATION_CODE_COLLECTION, OAUTH_CLIENT_COLLECTION};
+use futures::stream::TryStreamExt;
+use mongodb::bson::{self, doc, Bson, Document};
+use mongodb::options::FindOptions;
+use mongodb::{error::Error, Collection, Database};
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+/// Storage of registered OAuth clients and of the authorization codes issued
+/// to them.
+#[rocket::async_trait]
+pub trait OAuthRepository: Send + Sync {
+    async fn insert_client(&self, client: OAuthClient) -> Result<(), AuthenticationError>;
+
+    async fn find_client(&self, client_id: &str) -> Result<Option<OAuthClient>, AuthenticationError>;
+
+    /// Every client, oldest first.
+    async fn list_clients(&self) -> Result<Vec<OAuthClient>, AuthenticationError>;
+
+    async fn insert_code(&self, code: AuthorizationCode) -> Result<(), AuthenticationError>;
+
+    /// Removes and returns the code with the given hash, so that it can only
+    /// be exchanged once.
+    async fn take_code(&self, code_hash: &str) -> Result<Option<AuthorizationCode>, AuthenticationError>;
+}
+
+fn decode<T: serde::de::DeserializeOwned>(document: Document) -> Result<T, AuthenticationError> {
+    bson::from_bson(Bson::Document(document))
+        .map_err(|err| AuthenticationError::DbError(err.to_string()))
+}
+
+fn encode<T: serde::Serialize>(value: &T) -> Result<Document, AuthenticationError> {
+    bson::to_document(value).map_err(|err| AuthenticationError::DbError(err.to_string()))
+}
+
+pub struct MongoOAuthRepository {
+    clients: Collection,
+    codes: Collection,
+}
+
+impl MongoOAuthRepository {
+    pub fn new(database: &Database) -> Self {
+        Self {
+            clients: database.collection(OAUTH_CLIENT_COLLECTION),
+            codes: database.collection(AUTHORIZATION_CODE_COLLECTION),
+        }
+    }
+
+    /// Has the server remove authorization codes that were never exchanged
+    /// once they expire.
+    pub async fn create_indexes(database: &Database) -> Result<(), Error> {
+        database
+            .run_command(
+                doc! {
+                    "createIndexes": AUTHORIZATION_CODE_COLLECTION,
+                    "indexes": [
+                        { "key": { "expires_at": 1 }, "name": "expires_at", "expireAfterSeconds": 0 },
+                    ],
+                },
+                None,
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+#[rocket::async_trait]
+impl OAuthRepository for MongoOAuthRepository {
+    async fn insert_client(&self, client: OAuthClient) -> Result<(), AuthenticationError> {
+        self.clients.insert_one(encode(&client)?, None).await?;
+        Ok(())
+    }
+
+    async fn find_client(&self, client_id: &str) -> Result<Option<OAuthClient>, AuthenticationError> {
+        self.clients
+            .find_one(doc! { "_id": client_id }, None)
+            .await?
+            .map(decode)
+            .transpose()
+    }
+
+    async fn list_clients(&self) -> Result<Vec<OAuthClient>, AuthenticationError> {
+        let options = FindOptions::builder().sort(doc! { "created_at": 1 }).build();
+        let cursor = self.clients.find(doc! {}, options).await?;
+        let documents: Vec<Document> = cursor.try_collect().await?;
+        documents.into_iter().map(decode).collect()
+    }
+
+    async fn insert_code(&self, code: AuthorizationCode) -> Result<(), AuthenticationError> {
+        self.codes.insert_one(encode(&code)?, None).await?;
+        Ok(())
+    }
+
+    async fn take_code(&self, code_hash: &str) -> Result<Option<AuthorizationCode>, AuthenticationError> {
+        self.codes
+            .find_one_and_delete(doc! { "_id": code_hash }, None)
+            .await?
+            .map(decode)
+            .transpose()
+    }
+}
+
+/// Keeps OAuth clients and codes in memory, for tests and running without a
+/// database.
+#[derive(Default)]
+pub struct InMemoryOAuthRepository {
+    clients: RwLock<Vec<OAuthClient>>,
+    codes: RwLock<HashMap<String, AuthorizationCode>>,
+}
+
+#[rocket::async_trait]
+impl OAuthRepository for InMemoryOAuthRepository {
+    async fn insert_client(&self, client: OAuthClient) -> Result<(), AuthenticationError> {
+        self.clients.write().unwrap().push(client);
+        Ok(())
+    }
+
+    async fn find_client(&self, client_id: &str) -> Result<Option<OAuthClient>, AuthenticationError> {
+        let clients = self.clients.read().unwrap();
+        Ok(clients.iter().find(|client| client.client_id == client_id).cloned())
+    }
+
+    async fn list_clients(&self) -> Result<Vec<OAuthClient>, AuthenticationError> {
+        Ok(self.clients.read().unwrap().clone())
+    }
+
+    async fn insert_code(&self, code: AuthorizationCode) -> Result<(), AuthenticationError> {
+        self.codes.write().unwrap().insert(code.code_hash.clone(), code);
+        Ok(())
+    }
+
+    async fn take_code(&self, code_hash: &str) -> Result<Option<AuthorizationCode>, AuthenticationError> {
+        Ok(self.codes.write().unwrap().remove(code_hash))
+    }
+}
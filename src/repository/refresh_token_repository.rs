@@ -0,0 +1,190 @@
+use crate::handlers::error::AuthenticationError;
+use crate::models::token::RefreshToken;
+use crate::utils::mongo_util::REFRESH_TOKEN_COLLECTION;
+use chrono::Utc;
+use mongodb::bson::{self, doc, oid::ObjectId, Bson};
+use mongodb::{error::Error, Collection, Database};
+use std::sync::RwLock;
+
+/// Storage of issued refresh tokens.
+#[rocket::async_trait]
+pub trait RefreshTokenRepository: Send + Sync {
+    async fn insert(&self, token: RefreshToken) -> Result<(), AuthenticationError>;
+
+    async fn find_by_hash(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<RefreshToken>, AuthenticationError>;
+
+    /// Marks a refresh token as used, returning `false` if it had already been
+    /// used or revoked by the time the update ran.
+    async fn consume(&self, token_id: &ObjectId) -> Result<bool, AuthenticationError>;
+
+    async fn revoke_family(&self, family_id: &str) -> Result<(), AuthenticationError>;
+
+    /// Revokes every refresh token of the user, except for those of
+    /// `keep_family_id` when given.
+    async fn revoke_for_user(
+        &self,
+        user_id: &ObjectId,
+        keep_family_id: Option<&str>,
+    ) -> Result<(), AuthenticationError>;
+}
+
+pub struct MongoRefreshTokenRepository {
+    tokens: Collection,
+}
+
+impl MongoRefreshTokenRepository {
+    pub fn new(database: &Database) -> Self {
+        Self {
+            tokens: database.collection(REFRESH_TOKEN_COLLECTION),
+        }
+    }
+
+    /// Indexes the lookups by hash, family and user, and has the server
+    /// remove tokens once they expire.
+    pub async fn create_indexes(database: &Database) -> Result<(), Error> {
+        database
+            .run_command(
+                doc! {
+                    "createIndexes": REFRESH_TOKEN_COLLECTION,
+                    "indexes": [
+                        { "key": { "token_hash": 1 }, "name": "token_hash", "unique": true },
+                        { "key": { "family_id": 1 }, "name": "family_id" },
+                        { "key": { "user_id": 1 }, "name": "user_id" },
+                        { "key": { "expires_at": 1 }, "name": "expires_at", "expireAfterSeconds": 0 },
+                    ],
+                },
+                None,
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+#[rocket::async_trait]
+impl RefreshTokenRepository for MongoRefreshTokenRepository {
+    async fn insert(&self, token: RefreshToken) -> Result<(), AuthenticationError> {
+        let insertable = bson::to_document(&token)
+            .map_err(|err| AuthenticationError::DbError(err.to_string()))?;
+        self.tokens.insert_one(insertable, None).await?;
+        Ok(())
+    }
+
+    async fn find_by_hash(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<RefreshToken>, AuthenticationError> {
+        self.tokens
+            .find_one(doc! { "token_hash": token_hash }, None)
+            .await?
+            .map(|document| {
+                bson::from_bson(Bson::Document(document))
+                    .map_err(|err| AuthenticationError::DbError(err.to_string()))
+            })
+            .transpose()
+    }
+
+    async fn consume(&self, token_id: &ObjectId) -> Result<bool, AuthenticationError> {
+        let used_at = bson::to_bson(&Utc::now().naive_utc()).unwrap();
+        let updated = self
+            .tokens
+            .update_one(
+                doc! { "_id": token_id.clone(), "used_at": Bson::Null, "revoked": false },
+                doc! { "$set": { "used_at": used_at } },
+                None,
+            )
+            .await?;
+        Ok(updated.modified_count == 1)
+    }
+
+    async fn revoke_family(&self, family_id: &str) -> Result<(), AuthenticationError> {
+        self.tokens
+            .update_many(
+                doc! { "family_id": family_id },
+                doc! { "$set": { "revoked": true } },
+                None,
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn revoke_for_user(
+        &self,
+        user_id: &ObjectId,
+        keep_family_id: Option<&str>,
+    ) -> Result<(), AuthenticationError> {
+        let mut filter = doc! { "user_id": user_id.clone() };
+        if let Some(family_id) = keep_family_id {
+            filter.insert("family_id", doc! { "$ne": family_id });
+        }
+        self.tokens
+            .update_many(filter, doc! { "$set": { "revoked": true } }, None)
+            .await?;
+        Ok(())
+    }
+}
+
+/// Keeps refresh tokens in memory, for tests and running without a database.
+#[derive(Default)]
+pub struct InMemoryRefreshTokenRepository {
+    tokens: RwLock<Vec<RefreshToken>>,
+}
+
+#[rocket::async_trait]
+impl RefreshTokenRepository for InMemoryRefreshTokenRepository {
+    async fn insert(&self, mut token: RefreshToken) -> Result<(), AuthenticationError> {
+        token.token_id = Some(ObjectId::new());
+        self.tokens.write().unwrap().push(token);
+        Ok(())
+    }
+
+    async fn find_by_hash(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<RefreshToken>, AuthenticationError> {
+        let tokens = self.tokens.read().unwrap();
+        Ok(tokens
+            .iter()
+            .find(|token| token.token_hash == token_hash)
+            .cloned())
+    }
+
+    async fn consume(&self, token_id: &ObjectId) -> Result<bool, AuthenticationError> {
+        let mut tokens = self.tokens.write().unwrap();
+        let token = tokens.iter_mut().find(|token| {
+            token.token_id.as_ref() == Some(token_id) && token.used_at.is_none() && !token.revoked
+        });
+        Ok(match token {
+            Some(token) => {
+                token.used_at = Some(Utc::now().naive_utc());
+                true
+            }
+            None => false,
+        })
+    }
+
+    async fn revoke_family(&self, family_id: &str) -> Result<(), AuthenticationError> {
+        let mut tokens = self.tokens.write().unwrap();
+        tokens
+            .iter_mut()
+            .filter(|token| token.family_id == family_id)
+            .for_each(|token| token.revoked = true);
+        Ok(())
+    }
+
+    async fn revoke_for_user(
+        &self,
+        user_id: &ObjectId,
+        keep_family_id: Option<&str>,
+    ) -> Result<(), AuthenticationError> {
+        let mut tokens = self.tokens.write().unwrap();
+        tokens
+            .iter_mut()
+            .filter(|token| &token.user_id == user_id)
+            .filter(|token| keep_family_id != Some(token.family_id.as_str()))
+            .for_each(|token| token.revoked = true);
+        Ok(())
+    }
+}
@@ -0,0 +1,385 @@
+use crate::handlers::error::AuthenticationError;
+use crate::models::user::{NewUser, PageCursor, SortOrder, User, UserChanges, UserFilter};
+use crate::repository::is_duplicate_key;
+use crate::utils::mongo_util::USER_COLLECTION;
+use chrono::Utc;
+use futures::stream::TryStreamExt;
+use mongodb::{
+    bson::{self, doc, oid::ObjectId, Bson, Document},
+    error::Error,
+    options::FindOptions,
+    Collection, Database,
+};
+use std::cmp::Ordering;
+use std::sync::RwLock;
+
+/// Storage of user accounts.
+#[rocket::async_trait]
+pub trait UserRepository: Send + Sync {
+    async fn insert(&self, user: NewUser) -> Result<User, AuthenticationError>;
+
+    async fn find_by_id(&self, user_id: &ObjectId) -> Result<Option<User>, AuthenticationError>;
+
+    async fn find_by_email(&self, email_id: &str) -> Result<Option<User>, AuthenticationError>;
+
+    /// Applies `changes` and returns the updated user, `None` if there is no
+    /// user with that id.
+    async fn update(
+        &self,
+        user_id: &ObjectId,
+        changes: UserChanges,
+    ) -> Result<Option<User>, AuthenticationError>;
+
+    /// Removes `hash` from the recovery codes of a user in a single update,
+    /// returning whether it was still there, so that a code is only ever
+    /// spent once.
+    async fn remove_recovery_code(
+        &self,
+        user_id: &ObjectId,
+        hash: &str,
+    ) -> Result<bool, AuthenticationError>;
+
+    /// Deletes the user with the given email, returning the number of deleted
+    /// users.
+    async fn delete_by_email(&self, email_id: &str) -> Result<u64, AuthenticationError>;
+
+    /// Users matching `filter`, sorted by `created_at` and then `_id`.
+    async fn list(&self, filter: &UserFilter) -> Result<Vec<User>, AuthenticationError>;
+}
+
+fn decode_user(document: Document) -> Result<User, AuthenticationError> {
+    bson::from_bson(Bson::Document(document))
+        .map_err(|err| AuthenticationError::DbError(err.to_string()))
+}
+
+fn encode<T: serde::Serialize>(value: &T) -> Result<Document, AuthenticationError> {
+    bson::to_document(value).map_err(|err| AuthenticationError::DbError(err.to_string()))
+}
+
+pub struct MongoUserRepository {
+    users: Collection,
+}
+
+impl MongoUserRepository {
+    pub fn new(database: &Database) -> Self {
+        Self {
+            users: database.collection(USER_COLLECTION),
+        }
+    }
+
+    /// Creates the unique index on `email_id`, so that concurrent sign-ups
+    /// cannot register the same address twice.
+    pub async fn create_indexes(database: &Database) -> Result<(), Error> {
+        database
+            .run_command(
+                doc! {
+                    "createIndexes": USER_COLLECTION,
+                    "indexes": [{ "key": { "email_id": 1 }, "name": "email_id", "unique": true }],
+                },
+                None,
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn find_one(&self, filter: Document) -> Result<Option<User>, AuthenticationError> {
+        self.users
+            .find_one(filter, None)
+            .await?
+            .map(decode_user)
+            .transpose()
+    }
+
+    /// Filter matching the users that come after `cursor`.
+    fn after_filter(cursor: &PageCursor, order: SortOrder) -> Document {
+        let after = match order {
+            SortOrder::Ascending => "$gt",
+            SortOrder::Descending => "$lt",
+        };
+        let id = cursor.user_id.clone();
+        let mut branches = match cursor.created_at {
+            Some(created_at) => {
+                let created_at = bson::to_bson(&created_at).unwrap();
+                vec![
+                    doc! { "created_at": { after: created_at.clone() } },
+                    doc! { "created_at": created_at, "_id": { after: id } },
+                ]
+            }
+            None => vec![doc! { "created_at": Bson::Null, "_id": { after: id } }],
+        };
+        match (order, cursor.created_at) {
+            (SortOrder::Descending, Some(_)) => {
+                branches.push(doc! { "created_at": Bson::Null });
+            }
+            (SortOrder::Ascending, None) => {
+                branches.push(doc! { "created_at": { "$ne": Bson::Null } });
+            }
+            _ => {}
+        }
+        doc! { "$or": branches }
+    }
+}
+
+#[rocket::async_trait]
+impl UserRepository for MongoUserRepository {
+    async fn insert(&self, user: NewUser) -> Result<User, AuthenticationError> {
+        let inserted = match self.users.insert_one(encode(&user)?, None).await {
+            Ok(inserted) => inserted,
+            Err(err) if is_duplicate_key(&err) => {
+                return Err(AuthenticationError::UserAlreadyExists(user.user.email_id))
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let user_id: ObjectId = bson::from_bson(inserted.inserted_id)
+            .map_err(|err| AuthenticationError::DbError(err.to_string()))?;
+        self.find_by_id(&user_id).await?.ok_or_else(|| {
+            AuthenticationError::DbError("Could not find inserted user".to_owned())
+        })
+    }
+
+    async fn find_by_id(&self, user_id: &ObjectId) -> Result<Option<User>, AuthenticationError> {
+        self.find_one(doc! { "_id": user_id.clone() }).await
+    }
+
+    async fn find_by_email(&self, email_id: &str) -> Result<Option<User>, AuthenticationError> {
+        self.find_one(doc! { "email_id": email_id }).await
+    }
+
+    async fn update(
+        &self,
+        user_id: &ObjectId,
+        changes: UserChanges,
+    ) -> Result<Option<User>, AuthenticationError> {
+        let fields = encode(&changes)?;
+        if !fields.is_empty() {
+            self.users
+                .update_one(doc! { "_id": user_id.clone() }, doc! { "$set": fields }, None)
+                .await?;
+        }
+        self.find_by_id(user_id).await
+    }
+
+    async fn remove_recovery_code(
+        &self,
+        user_id: &ObjectId,
+        hash: &str,
+    ) -> Result<bool, AuthenticationError> {
+        let changes = UserChanges {
+            updated_at: Some(Utc::now().naive_utc()),
+            ..Default::default()
+        };
+        let updated = self
+            .users
+            .update_one(
+                doc! { "_id": user_id.clone(), "recovery_codes": hash },
+                doc! { "$pull": { "recovery_codes": hash }, "$set": encode(&changes)? },
+                None,
+            )
+            .await?;
+        Ok(updated.modified_count == 1)
+    }
+
+    async fn delete_by_email(&self, email_id: &str) -> Result<u64, AuthenticationError> {
+        let deleted = self
+            .users
+            .delete_one(doc! { "email_id": email_id }, None)
+            .await?;
+        Ok(deleted.deleted_count as u64)
+    }
+
+    async fn list(&self, filter: &UserFilter) -> Result<Vec<User>, AuthenticationError> {
+        let mut filters = vec![];
+        if let Some(user_type) = &filter.user_type {
+            filters.push(doc! { "user_type": bson::to_bson(user_type).unwrap() });
+        }
+        if !filter.user_tags.is_empty() {
+            filters.push(doc! { "user_tags": { "$all": bson::to_bson(&filter.user_tags).unwrap() } });
+        }
+        match filter.verified {
+            Some(true) => filters.push(doc! { "email_verified": true }),
+            Some(false) => filters.push(doc! { "email_verified": { "$ne": true } }),
+            None => {}
+        }
+        if let Some(text) = &filter.text {
+            let pattern = escape_regex(text);
+            filters.push(doc! { "$or": [
+                { "first_name": { "$regex": pattern.clone(), "$options": "i" } },
+                { "last_name": { "$regex": pattern.clone(), "$options": "i" } },
+                { "email_id": { "$regex": pattern, "$options": "i" } },
+            ] });
+        }
+        if let Some(cursor) = &filter.after {
+            filters.push(Self::after_filter(cursor, filter.order));
+        }
+        let query = if filters.is_empty() {
+            doc! {}
+        } else {
+            doc! { "$and": filters }
+        };
+
+        let direction = match filter.order {
+            SortOrder::Ascending => 1,
+            SortOrder::Descending => -1,
+        };
+        let options = FindOptions::builder()
+            .sort(doc! { "created_at": direction, "_id": direction })
+            .limit(filter.limit)
+            .build();
+        let cursor = self.users.find(query, options).await?;
+        let documents: Vec<Document> = cursor.try_collect().await?;
+        documents.into_iter().map(decode_user).collect()
+    }
+}
+
+/// Escapes `text` for use as a literal inside a regular expression.
+fn escape_regex(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "\\^$.|?*+()[]{}".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Keeps users in memory, for tests and running without a database.
+#[derive(Default)]
+pub struct InMemoryUserRepository {
+    users: RwLock<Vec<User>>,
+}
+
+impl InMemoryUserRepository {
+    /// Position of `user` in the listing order, matching how Mongo sorts
+    /// missing `created_at` values before dates.
+    fn compare(user: &User, cursor: &PageCursor) -> Ordering {
+        user.created_at.cmp(&cursor.created_at).then_with(|| {
+            let user_id = user.user_id.as_ref().unwrap();
+            user_id.bytes().cmp(&cursor.user_id.bytes())
+        })
+    }
+
+    fn matches(user: &User, filter: &UserFilter) -> bool {
+        let text_matches = |text: &str| {
+            let text = text.to_lowercase();
+            [&user.first_name, &user.last_name, &user.email_id]
+                .iter()
+                .any(|field| field.to_lowercase().contains(&text))
+        };
+        filter
+            .user_type
+            .as_ref()
+            .is_none_or(|user_type| &user.user_type == user_type)
+            && filter
+                .user_tags
+                .iter()
+                .all(|tag| user.user_tags.contains(tag))
+            && filter
+                .verified
+                .is_none_or(|verified| user.email_verified == verified)
+            && filter.text.as_deref().is_none_or(text_matches)
+            && filter.after.as_ref().is_none_or(|cursor| {
+                let ordering = Self::compare(user, cursor);
+                match filter.order {
+                    SortOrder::Ascending => ordering == Ordering::Greater,
+                    SortOrder::Descending => ordering == Ordering::Less,
+                }
+            })
+    }
+}
+
+#[rocket::async_trait]
+impl UserRepository for InMemoryUserRepository {
+    async fn insert(&self, user: NewUser) -> Result<User, AuthenticationError> {
+        // Round-trip through BSON so the stored user looks like one read
+        // back from Mongo
+        let mut user = decode_user(encode(&user)?)?;
+        user.user_id = Some(ObjectId::new());
+        let mut users = self.users.write().unwrap();
+        if users.iter().any(|existing| existing.email_id == user.email_id) {
+            return Err(AuthenticationError::UserAlreadyExists(user.email_id));
+        }
+        users.push(user.clone());
+        Ok(user)
+    }
+
+    async fn find_by_id(&self, user_id: &ObjectId) -> Result<Option<User>, AuthenticationError> {
+        let users = self.users.read().unwrap();
+        Ok(users
+            .iter()
+            .find(|user| user.user_id.as_ref() == Some(user_id))
+            .cloned())
+    }
+
+    async fn find_by_email(&self, email_id: &str) -> Result<Option<User>, AuthenticationError> {
+        let users = self.users.read().unwrap();
+        Ok(users.iter().find(|user| user.email_id == email_id).cloned())
+    }
+
+    async fn update(
+        &self,
+        user_id: &ObjectId,
+        changes: UserChanges,
+    ) -> Result<Option<User>, AuthenticationError> {
+        let mut users = self.users.write().unwrap();
+        Ok(users
+            .iter_mut()
+            .find(|user| user.user_id.as_ref() == Some(user_id))
+            .map(|user| {
+                changes.apply(user);
+                user.clone()
+            }))
+    }
+
+    async fn remove_recovery_code(
+        &self,
+        user_id: &ObjectId,
+        hash: &str,
+    ) -> Result<bool, AuthenticationError> {
+        let mut users = self.users.write().unwrap();
+        let user = match users
+            .iter_mut()
+            .find(|user| user.user_id.as_ref() == Some(user_id))
+        {
+            Some(user) => user,
+            None => return Ok(false),
+        };
+        match user.recovery_codes.iter().position(|code| code == hash) {
+            Some(index) => {
+                user.recovery_codes.remove(index);
+                user.updated_at = Some(Utc::now().naive_utc());
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn delete_by_email(&self, email_id: &str) -> Result<u64, AuthenticationError> {
+        let mut users = self.users.write().unwrap();
+        match users.iter().position(|user| user.email_id == email_id) {
+            Some(index) => {
+                users.remove(index);
+                Ok(1)
+            }
+            None => Ok(0),
+        }
+    }
+
+    async fn list(&self, filter: &UserFilter) -> Result<Vec<User>, AuthenticationError> {
+        let users = self.users.read().unwrap();
+        let mut matching: Vec<User> = users
+            .iter()
+            .filter(|user| Self::matches(user, filter))
+            .cloned()
+            .collect();
+        matching.sort_by(|a, b| {
+            let ordering = Self::compare(a, &PageCursor::from(b));
+            match filter.order {
+                SortOrder::Ascending => ordering,
+                SortOrder::Descending => ordering.reverse(),
+            }
+        });
+        matching.truncate(filter.limit.max(0) as usize);
+        Ok(matching)
+    }
+}
@@ -0,0 +1,172 @@
+use crate::config::signing::SigningKey;
+use crate::handlers::error::AuthenticationError;
+use crate::handlers::guard::ClientInfo;
+use crate::models::audit::{
+    AuditEvent, AuditEventKind, AuditExport, AuditExportQuery, AuditFilter, AuditPage, AuditQuery,
+    BrokenLink, ChainVerification, SignedAuditExport, GENESIS_HASH,
+};
+use crate::models::user::User;
+use crate::repository::audit_repository::AuditRepository;
+use chrono::Utc;
+use mongodb::bson::oid::ObjectId;
+
+/// Events read at once while verifying the chain.
+const VERIFY_BATCH_SIZE: i64 = 500;
+
+/// Most events a single export may hold.
+const MAX_EXPORT_EVENTS: i64 = 10_000;
+
+pub struct AuditService;
+
+impl AuditService {
+    /// Appends an event to the audit log. Failing to do so is logged, the
+    /// request that caused the event still goes through.
+    pub async fn record(
+        audit: &dyn AuditRepository,
+        client: &ClientInfo,
+        kind: AuditEventKind,
+        actor: Option<String>,
+        target: Option<String>,
+    ) {
+        let event = AuditEvent {
+            event_id: None,
+            seq: 0,
+            kind,
+            actor,
+            target,
+            ip: client.ip.map(|ip| ip.to_string()),
+            user_agent: client.user_agent.clone(),
+            created_at: Utc::now().naive_utc(),
+            prev_hash: String::new(),
+            hash: String::new(),
+        };
+        if let Err(err) = audit.append(event).await {
+            log::error!("Failed to record audit event {:?}: {:?}", kind, err);
+        }
+    }
+
+    /// How `user` is identified in the audit log.
+    pub fn actor(user: &User) -> Option<String> {
+        user.user_id.as_ref().map(ObjectId::to_hex)
+    }
+
+    pub async fn list(
+        audit: &dyn AuditRepository,
+        query: AuditQuery,
+    ) -> Result<AuditPage, AuthenticationError> {
+        let before = match &query.before {
+            Some(before) => Some(
+                ObjectId::with_string(before)
+                    .map_err(|_| AuthenticationError::InvalidQuery("Invalid cursor".to_owned()))?,
+            ),
+            None => None,
+        };
+        let filter = AuditFilter {
+            kind: query.kind,
+            actor: query.actor,
+            target: query.target,
+            before,
+            // Fetch one extra event to know whether there is a next page
+            limit: query.limit + 1,
+        };
+
+        let mut events = audit.list(&filter).await?;
+        let next_cursor = if events.len() as i64 > query.limit {
+            events.truncate(query.limit as usize);
+            events
+                .last()
+                .and_then(|last| last.event_id.as_ref())
+                .map(ObjectId::to_hex)
+        } else {
+            None
+        };
+        Ok(AuditPage {
+            events,
+            next_cursor,
+        })
+    }
+
+    /// Walks the whole chain, stopping at the first event that does not
+    /// follow from the one before it.
+    pub async fn verify(audit: &dyn AuditRepository) -> Result<ChainVerification, AuthenticationError> {
+        let mut previous: Option<AuditEvent> = None;
+        let mut checked = 0;
+        loop {
+            let from = previous.as_ref().map_or(1, |previous| previous.seq + 1);
+            let events = audit.range(from, None, VERIFY_BATCH_SIZE).await?;
+            let done = (events.len() as i64) < VERIFY_BATCH_SIZE;
+            for event in events {
+                if let Some(reason) = Self::broken_link(previous.as_ref(), &event) {
+                    return Ok(ChainVerification {
+                        valid: false,
+                        checked,
+                        head_seq: previous.as_ref().map(|previous| previous.seq),
+                        head_hash: previous.map(|previous| previous.hash),
+                        broken_link: Some(BrokenLink {
+                            seq: event.seq,
+                            event_id: event.event_id,
+                            reason,
+                        }),
+                    });
+                }
+                checked += 1;
+                previous = Some(event);
+            }
+            if done {
+                break;
+            }
+        }
+        Ok(ChainVerification {
+            valid: true,
+            checked,
+            head_seq: previous.as_ref().map(|previous| previous.seq),
+            head_hash: previous.map(|previous| previous.hash),
+            broken_link: None,
+        })
+    }
+
+    /// Why `event` does not follow `previous`, if it does not.
+    pub fn broken_link(previous: Option<&AuditEvent>, event: &AuditEvent) -> Option<String> {
+        let expected_seq = previous.map_or(1, |previous| previous.seq + 1);
+        let expected_prev_hash = previous.map_or(GENESIS_HASH, |previous| previous.hash.as_str());
+        if event.seq != expected_seq {
+            Some(format!("Expected event {}, found event {}", expected_seq, event.seq))
+        } else if event.prev_hash != expected_prev_hash {
+            Some("Previous hash does not match the previous event".to_owned())
+        } else if event.hash != event.compute_hash() {
+            Some("Hash does not match the event's content".to_owned())
+        } else {
+            None
+        }
+    }
+
+    /// The events from `query.from` to `query.to`, signed with the token
+    /// signing key.
+    pub async fn export(
+        audit: &dyn AuditRepository,
+        signing_key: &SigningKey,
+        query: AuditExportQuery,
+    ) -> Result<SignedAuditExport, AuthenticationError> {
+        if query.to < query.from {
+            return Err(AuthenticationError::InvalidQuery(
+                "to must not be less than from".to_owned(),
+            ));
+        }
+        if query.to - query.from >= MAX_EXPORT_EVENTS {
+            return Err(AuthenticationError::InvalidQuery(format!(
+                "At most {} events can be exported at once",
+                MAX_EXPORT_EVENTS
+            )));
+        }
+
+        let events = audit.range(query.from, Some(query.to), MAX_EXPORT_EVENTS).await?;
+        let export = AuditExport {
+            from: query.from,
+            to: query.to,
+            exported_at: Utc::now().naive_utc(),
+            events,
+        };
+        let signature = signing_key.sign_detached(&export)?;
+        Ok(SignedAuditExport { export, signature })
+    }
+}
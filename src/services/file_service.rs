@@ -1,15 +1,16 @@
 use crate::models::file::FileData;
+use mongodb::bson::oid::ObjectId;
 use rocket::data::Data;
-use rocket::http::{ContentType, Status};
-use rocket::response::status;
+use rocket::http::ContentType;
 use rocket_multipart_form_data::{
     mime::Mime, MultipartFormData, MultipartFormDataField, MultipartFormDataOptions,
 };
 
 use async_std::io::prelude::*;
 
+use crate::config::app::AppConfig;
 use crate::handlers::error::TransmissionError;
-use crate::utils::file_util::{FileUtil, STORAGE_DIRECTORY};
+use crate::utils::file_util::FileUtil;
 
 pub struct MultipartHandler {
     pub content_type: Option<Mime>,
@@ -21,18 +22,30 @@ impl MultipartHandler {
     pub async fn from(
         content_type: &ContentType,
         form_data: Data<'_>,
+        size_limit: u64,
+    ) -> Result<Self, TransmissionError> {
+        Self::from_field(content_type, form_data, "somefile", size_limit).await
+    }
+
+    /// Reads the file sent in the multipart field `field`, rejecting files
+    /// larger than `size_limit` bytes.
+    pub async fn from_field(
+        content_type: &ContentType,
+        form_data: Data<'_>,
+        field: &str,
+        size_limit: u64,
     ) -> Result<Self, TransmissionError> {
         let options = MultipartFormDataOptions::with_multipart_form_data_fields(vec![
-            MultipartFormDataField::raw("somefile").size_limit(200 * 1024 * 1024),
+            MultipartFormDataField::raw(field).size_limit(size_limit),
         ]);
         let mut multipart_form_data =
-            MultipartFormData::parse(&content_type, form_data, options).await?;
+            MultipartFormData::parse(content_type, form_data, options).await?;
         // .map_err(|e| e.into())?;
         // .map_err(|e| TransmissionError::Message("Failed to parse"))?;
 
         let content = multipart_form_data
             .raw
-            .remove("somefile")
+            .remove(field)
             .ok_or_else(|| TransmissionError::Message("No data found in file".to_string()))?;
 
         let file_name = content[0]
@@ -42,22 +55,65 @@ impl MultipartHandler {
 
         Ok(Self {
             content_type: content[0].content_type.clone(),
-            file_name: file_name,
+            file_name,
             raw: content[0].raw.clone(),
         })
     }
 
-    pub async fn save_to_file(&self) -> Result<FileData, TransmissionError> {
-        let path = async_std::path::Path::new(STORAGE_DIRECTORY);
+    /// Returns the file extension for the image format detected from the
+    /// file signature, or `None` if the upload is not a supported image.
+    pub fn image_extension(&self) -> Option<&'static str> {
+        let declared_image = self
+            .content_type
+            .as_ref()
+            .is_none_or(|mime| mime.type_() == "image");
+        if !declared_image {
+            return None;
+        }
+
+        let raw = self.raw.as_slice();
+        if raw.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Some("png")
+        } else if raw.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            Some("jpg")
+        } else if raw.starts_with(b"GIF87a") || raw.starts_with(b"GIF89a") {
+            Some("gif")
+        } else if raw.len() >= 12 && &raw[..4] == b"RIFF" && &raw[8..12] == b"WEBP" {
+            Some("webp")
+        } else {
+            None
+        }
+    }
+
+    /// Stores the file among those of the user `owner`, under the name it
+    /// was sent with, which has to be a plain file name.
+    pub async fn save_to_file(
+        &self,
+        config: &AppConfig,
+        owner: &ObjectId,
+    ) -> Result<FileData, TransmissionError> {
+        if !FileUtil::is_valid_file_name(&self.file_name) {
+            return Err(TransmissionError::Message("Invalid file name".to_owned()));
+        }
+        self.save(&FileUtil::user_directory(config, owner), FileUtil::get_basefile_path(config))
+            .await
+    }
+
+    /// Stores the file as an avatar, under the name the service chose.
+    pub async fn save_avatar(&self, config: &AppConfig) -> Result<FileData, TransmissionError> {
+        self.save(&FileUtil::avatar_directory(config), FileUtil::get_avatar_base_path(config))
+            .await
+    }
+
+    async fn save(&self, directory: &str, base_url: String) -> Result<FileData, TransmissionError> {
+        let path = async_std::path::Path::new(directory);
         if !path.exists().await {
-            async_std::fs::create_dir(path).await?;
+            async_std::fs::create_dir_all(path).await?;
             // .map_err(|e| e.into());
             // .map_err(|e| TransmissionError::Message("Failed to save data to file"))?;
         }
 
-        let mut file =
-            async_std::fs::File::create(format!("{}/{}", STORAGE_DIRECTORY, self.file_name))
-                .await?;
+        let mut file = async_std::fs::File::create(path.join(&self.file_name)).await?;
 
         // .map_err(|e| e.into())?;
         // .map_err(|e| TransmissionError::Message("Failed to save data to file"))?;
@@ -70,7 +126,7 @@ impl MultipartHandler {
 
         let file = FileData {
             name: self.file_name.to_owned(),
-            url: format!("{}/{}", FileUtil::get_basefile_path().await, self.file_name),
+            url: format!("{}/{}", base_url, self.file_name),
             size: meta_data.len(),
             size_unit: "bytes".to_owned()
         };
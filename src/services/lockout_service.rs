@@ -0,0 +1,170 @@
+use crate::handlers::error::AuthenticationError;
+use crate::models::login_attempt::LoginAttempts;
+use crate::repository::login_attempt_repository::LoginAttemptRepository;
+use chrono::{Duration, NaiveDateTime, Utc};
+use serde::Deserialize;
+use std::net::IpAddr;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LockoutConfig {
+    /// Failed sign-ins to an account before each further one is delayed
+    pub free_failures: i64,
+    /// Seconds to wait after the first delayed failure, doubled with each one
+    pub backoff_base: i64,
+    /// Upper bound on the delay, in seconds
+    pub backoff_max: i64,
+    /// Failed sign-ins to an account before it is locked
+    pub max_account_failures: i64,
+    /// Failed sign-ins from a client address before it is locked
+    pub max_ip_failures: i64,
+    /// Seconds a lock lasts
+    pub lockout_duration: i64,
+    /// Seconds after which failures are forgotten
+    pub failure_window: i64,
+}
+
+/// What failed sign-ins are counted for.
+#[derive(Debug, Clone)]
+pub enum LockoutSubject<'a> {
+    Account(&'a str),
+    Ip(IpAddr),
+}
+
+impl LockoutSubject<'_> {
+    /// Accounts are keyed by their email exactly as users are looked up by
+    /// it, so an account is only held back by failures against itself.
+    fn key(&self) -> String {
+        match self {
+            Self::Account(email_id) => format!("account:{}", email_id),
+            Self::Ip(ip) => format!("ip:{}", ip),
+        }
+    }
+}
+
+pub struct LockoutService;
+
+impl LockoutService {
+    /// Counts an attempt for each of `subjects` as failed before it is
+    /// verified, so that parallel attempts cannot all get past the limits.
+    /// Fails with `TooManyAttempts`, without counting the attempt, while any
+    /// of them has to wait. Attempts that turn out fine are `release`d, and
+    /// those that do fail are `fail`ed, which is when delays start to run.
+    pub async fn reserve(
+        attempts: &dyn LoginAttemptRepository,
+        config: &LockoutConfig,
+        subjects: &[LockoutSubject<'_>],
+    ) -> Result<(), AuthenticationError> {
+        let now = Utc::now().naive_utc();
+        let mut retry_after = 0;
+        for subject in subjects {
+            let key = subject.key();
+            if let Some(counter) = attempts.find(&key).await? {
+                if Self::expired(config, &counter, now) {
+                    attempts.reset(&key).await?;
+                } else {
+                    retry_after =
+                        retry_after.max(Self::retry_after(config, subject, &counter, now));
+                }
+            }
+        }
+        if retry_after > 0 {
+            return Err(AuthenticationError::TooManyAttempts(retry_after));
+        }
+
+        // Checked again against the counters as they were right before this
+        // attempt, which parallel ones have moved on
+        for (reserved, subject) in subjects.iter().enumerate() {
+            if let Some(counter) = attempts.count_attempt(&subject.key(), now).await? {
+                retry_after = retry_after.max(Self::retry_after(config, subject, &counter, now));
+            }
+            if retry_after > 0 {
+                Self::release(attempts, &subjects[..=reserved]).await?;
+                return Err(AuthenticationError::TooManyAttempts(retry_after));
+            }
+        }
+        Ok(())
+    }
+
+    /// Takes back an attempt `reserve` counted as failed.
+    pub async fn release(
+        attempts: &dyn LoginAttemptRepository,
+        subjects: &[LockoutSubject<'_>],
+    ) -> Result<(), AuthenticationError> {
+        for subject in subjects {
+            attempts.forgive(&subject.key()).await?;
+        }
+        Ok(())
+    }
+
+    /// Dates the failure of an attempt `reserve` counted, from which the
+    /// delays and locks of `subjects` then run.
+    pub async fn fail(
+        attempts: &dyn LoginAttemptRepository,
+        subjects: &[LockoutSubject<'_>],
+    ) -> Result<(), AuthenticationError> {
+        let now = Utc::now().naive_utc();
+        for subject in subjects {
+            attempts.record_failure(&subject.key(), now).await?;
+        }
+        Ok(())
+    }
+
+    /// Forgets the failed sign-ins of `subject`, returning `false` if there
+    /// were none.
+    pub async fn reset(
+        attempts: &dyn LoginAttemptRepository,
+        subject: &LockoutSubject<'_>,
+    ) -> Result<bool, AuthenticationError> {
+        attempts.reset(&subject.key()).await
+    }
+
+    /// Seconds `subject` has to wait before signing in again, rounded up.
+    fn retry_after(
+        config: &LockoutConfig,
+        subject: &LockoutSubject<'_>,
+        counter: &LoginAttempts,
+        now: NaiveDateTime,
+    ) -> i64 {
+        match Self::locked_until(config, subject, counter, now) {
+            Some(locked_until) => ((locked_until - now).num_milliseconds() + 999) / 1000,
+            None => 0,
+        }
+    }
+
+    /// Until when `subject` may not sign in, if it has to wait at all.
+    /// Client addresses are only locked and never delayed, since many users
+    /// may share one.
+    fn locked_until(
+        config: &LockoutConfig,
+        subject: &LockoutSubject<'_>,
+        counter: &LoginAttempts,
+        now: NaiveDateTime,
+    ) -> Option<NaiveDateTime> {
+        if Self::expired(config, counter, now) {
+            return None;
+        }
+        let max_failures = match subject {
+            LockoutSubject::Account(_) => config.max_account_failures,
+            LockoutSubject::Ip(_) => config.max_ip_failures,
+        };
+        let delay = if counter.failures >= max_failures {
+            config.lockout_duration
+        } else if matches!(subject, LockoutSubject::Account(_))
+            && counter.failures > config.free_failures
+        {
+            let doublings = (counter.failures - config.free_failures - 1).min(32) as u32;
+            config
+                .backoff_base
+                .saturating_mul(2_i64.saturating_pow(doublings))
+                .min(config.backoff_max)
+        } else {
+            return None;
+        };
+        let locked_until = counter.last_failure + Duration::seconds(delay);
+        (locked_until > now).then_some(locked_until)
+    }
+
+    fn expired(config: &LockoutConfig, counter: &LoginAttempts, now: NaiveDateTime) -> bool {
+        counter.last_failure + Duration::seconds(config.failure_window) <= now
+    }
+}
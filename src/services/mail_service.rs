@@ -0,0 +1,126 @@
+use crate::handlers::error::AuthenticationError;
+use chrono::Utc;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncStd1Executor, AsyncTransport, Message};
+use serde::Deserialize;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MailTransport {
+    Smtp,
+    File,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MailConfig {
+    pub transport: MailTransport,
+    pub from: String,
+    pub outbox: String,
+    pub verification_url: String,
+    pub reset_url: String,
+    pub smtp_host: Option<String>,
+    pub smtp_port: Option<u16>,
+    pub smtp_username: Option<String>,
+    pub smtp_password: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Transport used to deliver outgoing mail.
+#[rocket::async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, email: Email) -> Result<(), AuthenticationError>;
+}
+
+/// Builds the mailer selected by the `mail.transport` setting.
+pub fn mailer_from_config(config: &MailConfig) -> Arc<dyn Mailer> {
+    match config.transport {
+        MailTransport::Smtp => Arc::new(SmtpMailer::new(config)),
+        MailTransport::File => Arc::new(FileMailer {
+            outbox: PathBuf::from(&config.outbox),
+        }),
+    }
+}
+
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<AsyncStd1Executor>,
+    from: String,
+}
+
+impl SmtpMailer {
+    pub fn new(config: &MailConfig) -> Self {
+        let host = config
+            .smtp_host
+            .as_deref()
+            .expect("mail.smtp_host must be set for the smtp transport");
+        let mut builder = AsyncSmtpTransport::<AsyncStd1Executor>::starttls_relay(host)
+            .expect("Invalid SMTP relay");
+        if let Some(port) = config.smtp_port {
+            builder = builder.port(port);
+        }
+        if let (Some(username), Some(password)) = (&config.smtp_username, &config.smtp_password)
+        {
+            builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+
+        Self {
+            transport: builder.build(),
+            from: config.from.clone(),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, email: Email) -> Result<(), AuthenticationError> {
+        let invalid = |err: lettre::address::AddressError| {
+            AuthenticationError::MailError(format!("Invalid address: {}", err))
+        };
+        let message = Message::builder()
+            .from(self.from.parse().map_err(invalid)?)
+            .to(email.to.parse().map_err(invalid)?)
+            .subject(email.subject)
+            .body(email.body)
+            .map_err(|err| AuthenticationError::MailError(err.to_string()))?;
+
+        self.transport
+            .send(message)
+            .await
+            .map(|_| ())
+            .map_err(|err| AuthenticationError::MailError(err.to_string()))
+    }
+}
+
+/// Writes every message to a file in the outbox directory instead of
+/// delivering it, for development and tests.
+pub struct FileMailer {
+    pub outbox: PathBuf,
+}
+
+#[rocket::async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, email: Email) -> Result<(), AuthenticationError> {
+        let to_io_error = |err: std::io::Error| AuthenticationError::MailError(err.to_string());
+        async_std::fs::create_dir_all(&self.outbox)
+            .await
+            .map_err(to_io_error)?;
+
+        let file_name = format!("{}-{}.txt", Utc::now().timestamp_nanos(), email.to);
+        let contents = format!(
+            "To: {}\nSubject: {}\n\n{}\n",
+            email.to, email.subject, email.body
+        );
+        async_std::fs::write(self.outbox.join(&file_name), contents)
+            .await
+            .map_err(to_io_error)?;
+        log::info!("Mail to {} written to outbox as {}", email.to, file_name);
+        Ok(())
+    }
+}
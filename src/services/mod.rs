@@ -1,2 +1,7 @@
 pub mod user_service;
 pub mod file_service;
+pub mod mail_service;
+pub mod lockout_service;
+pub mod audit_service;
+pub mod oauth_service;
+pub mod oidc_service;
@@ -0,0 +1,355 @@
+use crate::config::app::AppConfig;
+use crate::config::token::TokenService;
+use crate::handlers::error::{AuthenticationError, OAuthError};
+use crate::models::oauth::{
+    AuthorizationCode, AuthorizeRequest, OAuthClient, RegisterClient, RegisteredClient, Scope,
+    TokenRequest, TokenResponse,
+};
+use crate::models::user::User;
+use crate::repository::oauth_repository::OAuthRepository;
+use crate::repository::user_repository::UserRepository;
+use chrono::{Duration, Utc};
+use rand::RngCore;
+use rocket::http::uri::Absolute;
+use rocket::http::RawStr;
+use serde::Deserialize;
+use std::borrow::Cow;
+use validator::{Validate, ValidationError};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct OAuthConfig {
+    /// Seconds a client has to exchange an authorization code
+    pub authorization_code_lifetime: i64,
+}
+
+/// Why an authorization request was rejected.
+#[derive(Debug)]
+pub enum AuthorizeRejection {
+    /// The client or its redirect URI is unknown. The user is shown an error
+    /// instead of being redirected, since the redirect could lead anywhere.
+    Untrusted(String),
+    /// Reported to the client at its redirect URI
+    Redirect {
+        redirect_uri: String,
+        state: Option<String>,
+        error: &'static str,
+        description: String,
+    },
+    Internal(AuthenticationError),
+}
+
+/// An authorization request that passed every check.
+#[derive(Debug, Clone)]
+pub struct ValidAuthorization {
+    pub client: OAuthClient,
+    pub redirect_uri: String,
+    /// Requested scopes, before the ones the user's role lacks are dropped
+    pub scopes: Vec<Scope>,
+    pub state: Option<String>,
+    pub code_challenge: String,
+    pub nonce: Option<String>,
+}
+
+impl ValidAuthorization {
+    /// Where to send the user with `parameters` added, e.g. the code.
+    pub fn redirect(&self, parameters: &[(&str, &str)]) -> String {
+        let mut parameters = parameters.to_vec();
+        if let Some(state) = &self.state {
+            parameters.push(("state", state));
+        }
+        OAuthService::redirect_uri(&self.redirect_uri, &parameters)
+    }
+
+    /// The scopes `user` can grant, those their role does not hold are left
+    /// out of the token.
+    pub fn grantable_scopes(&self, user: &User) -> Vec<Scope> {
+        self.scopes
+            .iter()
+            .copied()
+            .filter(|scope| scope.allowed_for(&user.user_type))
+            .collect()
+    }
+}
+
+pub struct OAuthService;
+
+impl OAuthService {
+    /// Registers a client, returning it along with its secret if it is
+    /// confidential. The secret is only ever shown here.
+    pub async fn register_client(
+        oauth: &dyn OAuthRepository,
+        request: RegisterClient,
+    ) -> Result<(RegisteredClient, Option<String>), AuthenticationError> {
+        let mut errors = match request.validate() {
+            Ok(()) => validator::ValidationErrors::new(),
+            Err(errors) => errors,
+        };
+        if !request.redirect_uris.iter().all(|uri| Self::is_valid_redirect_uri(uri)) {
+            let mut error = ValidationError::new("redirect_uri");
+            error.message = Some(Cow::Borrowed(
+                "must be absolute https, loopback http or custom scheme URIs without a fragment",
+            ));
+            errors.add("redirect_uris", error);
+        }
+        if !errors.is_empty() {
+            return Err(AuthenticationError::ValidationError(errors));
+        }
+
+        let secret = request.confidential.then(Self::random_token);
+        let mut scopes = request.scopes;
+        scopes.dedup();
+        let client = OAuthClient {
+            client_id: Self::random_token(),
+            name: request.name,
+            secret_hash: secret.as_deref().map(TokenService::hash_refresh_token),
+            redirect_uris: request.redirect_uris,
+            scopes,
+            created_at: Utc::now().naive_utc(),
+        };
+        oauth.insert_client(client.clone()).await?;
+        Ok((client.into(), secret))
+    }
+
+    pub async fn list_clients(
+        oauth: &dyn OAuthRepository,
+    ) -> Result<Vec<RegisteredClient>, AuthenticationError> {
+        let clients = oauth.list_clients().await?;
+        Ok(clients.into_iter().map(RegisteredClient::from).collect())
+    }
+
+    /// Checks an authorization request, RFC 6749 section 4.1.2.1. PKCE with
+    /// the S256 method is required of every client.
+    pub async fn validate_authorization(
+        oauth: &dyn OAuthRepository,
+        request: &AuthorizeRequest,
+    ) -> Result<ValidAuthorization, AuthorizeRejection> {
+        let client_id = request
+            .client_id
+            .as_deref()
+            .ok_or_else(|| AuthorizeRejection::Untrusted("Missing client_id".to_owned()))?;
+        let client = oauth
+            .find_client(client_id)
+            .await
+            .map_err(AuthorizeRejection::Internal)?
+            .ok_or_else(|| AuthorizeRejection::Untrusted("Unknown client".to_owned()))?;
+
+        let redirect_uri = match (&request.redirect_uri, client.redirect_uris.as_slice()) {
+            (Some(redirect_uri), registered) if registered.contains(redirect_uri) => {
+                redirect_uri.clone()
+            }
+            (Some(_), _) => {
+                return Err(AuthorizeRejection::Untrusted(
+                    "redirect_uri is not registered for this client".to_owned(),
+                ))
+            }
+            (None, [only]) => only.clone(),
+            (None, _) => {
+                return Err(AuthorizeRejection::Untrusted("Missing redirect_uri".to_owned()))
+            }
+        };
+        let reject = |error: &'static str, description: &str| AuthorizeRejection::Redirect {
+            redirect_uri: redirect_uri.clone(),
+            state: request.state.clone(),
+            error,
+            description: description.to_owned(),
+        };
+
+        if request.response_type.as_deref() != Some("code") {
+            return Err(reject("unsupported_response_type", "response_type must be code"));
+        }
+        let code_challenge = match &request.code_challenge {
+            Some(challenge) if is_code_challenge(challenge) => challenge.clone(),
+            Some(_) => return Err(reject("invalid_request", "Invalid code_challenge")),
+            None => return Err(reject("invalid_request", "PKCE code_challenge is required")),
+        };
+        if request.code_challenge_method.as_deref() != Some("S256") {
+            return Err(reject("invalid_request", "code_challenge_method must be S256"));
+        }
+
+        let scopes = match request.scope.as_deref() {
+            None => client.scopes.clone(),
+            Some(scope) => Scope::parse_list(scope)
+                .filter(|scopes| !scopes.is_empty())
+                .filter(|scopes| scopes.iter().all(|scope| client.scopes.contains(scope)))
+                .ok_or_else(|| reject("invalid_scope", "Unknown or unauthorized scope"))?,
+        };
+
+        Ok(ValidAuthorization {
+            client,
+            redirect_uri,
+            scopes,
+            state: request.state.clone(),
+            code_challenge,
+            nonce: request.nonce.clone(),
+        })
+    }
+
+    /// Issues a single-use code for `user` having granted `scopes`.
+    pub async fn issue_code(
+        oauth: &dyn OAuthRepository,
+        config: &AppConfig,
+        authorization: &ValidAuthorization,
+        user: &User,
+        scopes: Vec<Scope>,
+    ) -> Result<String, AuthenticationError> {
+        let code = Self::random_token();
+        let expires_at =
+            Utc::now().naive_utc() + Duration::seconds(config.oauth.authorization_code_lifetime);
+        oauth
+            .insert_code(AuthorizationCode {
+                code_hash: TokenService::hash_refresh_token(&code),
+                client_id: authorization.client.client_id.clone(),
+                user_id: user.user_id.clone().unwrap(),
+                redirect_uri: authorization.redirect_uri.clone(),
+                scopes,
+                code_challenge: authorization.code_challenge.clone(),
+                nonce: authorization.nonce.clone(),
+                expires_at,
+            })
+            .await?;
+        Ok(code)
+    }
+
+    /// Trades an authorization code for an access token, RFC 6749 section
+    /// 4.1.3. `credentials` are the client id and secret of an HTTP Basic
+    /// `Authorization` header, which take precedence over the form.
+    pub async fn exchange_code(
+        oauth: &dyn OAuthRepository,
+        users: &dyn UserRepository,
+        config: &AppConfig,
+        request: TokenRequest,
+        credentials: Option<(String, String)>,
+    ) -> Result<TokenResponse, OAuthError> {
+        let required = |value: Option<String>, name: &str| {
+            value.ok_or_else(|| OAuthError::InvalidRequest(format!("Missing {}", name)))
+        };
+        match required(request.grant_type, "grant_type")?.as_str() {
+            "authorization_code" => {}
+            _ => {
+                return Err(OAuthError::UnsupportedGrantType(
+                    "Only the authorization_code grant is supported".to_owned(),
+                ))
+            }
+        }
+        let code = required(request.code, "code")?;
+        let redirect_uri = required(request.redirect_uri, "redirect_uri")?;
+        let code_verifier = required(request.code_verifier, "code_verifier")?;
+        let (client_id, client_secret) = match credentials {
+            Some((client_id, client_secret)) => (client_id, Some(client_secret)),
+            None => (required(request.client_id, "client_id")?, request.client_secret),
+        };
+
+        let client = oauth
+            .find_client(&client_id)
+            .await?
+            .ok_or_else(|| OAuthError::InvalidClient("Unknown client".to_owned()))?;
+        if let Some(secret_hash) = &client.secret_hash {
+            let presented = client_secret.as_deref().map(TokenService::hash_refresh_token);
+            if presented.as_ref() != Some(secret_hash) {
+                return Err(OAuthError::InvalidClient("Invalid client credentials".to_owned()));
+            }
+        }
+        if !is_code_verifier(&code_verifier) {
+            return Err(OAuthError::InvalidRequest("Invalid code_verifier".to_owned()));
+        }
+
+        let invalid_grant = || OAuthError::InvalidGrant("Invalid authorization code".to_owned());
+        let stored = oauth
+            .take_code(&TokenService::hash_refresh_token(&code))
+            .await?
+            .ok_or_else(invalid_grant)?;
+        if stored.client_id != client.client_id
+            || stored.redirect_uri != redirect_uri
+            || stored.expires_at <= Utc::now().naive_utc()
+        {
+            return Err(invalid_grant());
+        }
+        let challenge = base64::encode_config(
+            ring::digest::digest(&ring::digest::SHA256, code_verifier.as_bytes()),
+            base64::URL_SAFE_NO_PAD,
+        );
+        if challenge != stored.code_challenge {
+            return Err(OAuthError::InvalidGrant("code_verifier does not match".to_owned()));
+        }
+
+        let user = users.find_by_id(&stored.user_id).await?.ok_or_else(invalid_grant)?;
+        // The user's role may have changed since they consented
+        let scopes: Vec<Scope> = stored
+            .scopes
+            .into_iter()
+            .filter(|scope| scope.allowed_for(&user.user_type))
+            .collect();
+        let tokens = TokenService::new(&config.jwt);
+        let access_token = tokens.issue_client_access_token(&user, &client.client_id, &scopes)?;
+        let id_token = if scopes.contains(&Scope::OpenId) {
+            Some(tokens.issue_id_token(&user, &client.client_id, stored.nonce)?)
+        } else {
+            None
+        };
+        Ok(TokenResponse {
+            access_token,
+            token_type: "Bearer".to_owned(),
+            expires_in: config.jwt.access_token_lifetime,
+            scope: Scope::join(&scopes),
+            id_token,
+        })
+    }
+
+    /// `base` with `parameters` added to its query.
+    pub fn redirect_uri(base: &str, parameters: &[(&str, &str)]) -> String {
+        let mut uri = base.to_owned();
+        for (index, (name, value)) in parameters.iter().enumerate() {
+            let separator = if index == 0 && !base.contains('?') { '?' } else { '&' };
+            uri.push(separator);
+            uri.push_str(name);
+            uri.push('=');
+            uri.push_str(RawStr::new(value).percent_encode().as_str());
+        }
+        uri
+    }
+
+    /// A random URL safe token, used for client ids, secrets, codes and
+    /// CSRF tokens.
+    pub fn random_token() -> String {
+        let mut bytes = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+    }
+
+    /// Redirect URIs have to be absolute and without a fragment. Plain http
+    /// is only allowed to loopback addresses, and other schemes only in the
+    /// reverse domain name form of native apps, RFC 8252 section 7.1.
+    fn is_valid_redirect_uri(uri: &str) -> bool {
+        let parsed = match Absolute::parse(uri) {
+            Ok(parsed) => parsed,
+            Err(_) => return false,
+        };
+        if uri.contains('#') {
+            return false;
+        }
+        match parsed.scheme() {
+            "https" => parsed.authority().is_some(),
+            "http" => parsed
+                .authority()
+                .is_some_and(|authority| matches!(authority.host(), "localhost" | "127.0.0.1" | "[::1]")),
+            // e.g. `com.example.app:/callback`, never `javascript:` or `data:`
+            scheme => scheme.contains('.'),
+        }
+    }
+}
+
+/// A PKCE S256 challenge: the unpadded base64url SHA-256 of a verifier.
+fn is_code_challenge(challenge: &str) -> bool {
+    challenge.len() == 43
+        && challenge
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_')
+}
+
+/// A PKCE code verifier, RFC 7636 section 4.1.
+fn is_code_verifier(verifier: &str) -> bool {
+    (43..=128).contains(&verifier.len())
+        && verifier
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || b"-._~".contains(&byte))
+}
@@ -0,0 +1,59 @@
+use crate::config::app::AppConfig;
+use crate::config::token::TokenService;
+use crate::handlers::error::AuthenticationError;
+use crate::models::oauth::Scope;
+use crate::models::oidc::{DiscoveryDocument, Jwks, UserInfo};
+use crate::models::user::User;
+
+pub struct OidcService;
+
+impl OidcService {
+    /// Provider metadata, OpenID Connect Discovery 1.0 section 3.
+    pub fn discovery(config: &AppConfig) -> Result<DiscoveryDocument, AuthenticationError> {
+        let endpoint = |path: &str| format!("{}{}", config.public_base_url, path);
+        Ok(DiscoveryDocument {
+            issuer: config.jwt.issuer.clone(),
+            authorization_endpoint: endpoint("/oauth/authorize"),
+            token_endpoint: endpoint("/oauth/token"),
+            userinfo_endpoint: endpoint("/userinfo"),
+            jwks_uri: endpoint("/.well-known/jwks.json"),
+            scopes_supported: Scope::ALL.iter().map(Scope::name).collect(),
+            response_types_supported: vec!["code"],
+            grant_types_supported: vec!["authorization_code"],
+            subject_types_supported: vec!["public"],
+            id_token_signing_alg_values_supported: vec![TokenService::new(&config.jwt).signing_key()?.algorithm()],
+            token_endpoint_auth_methods_supported: vec![
+                "client_secret_basic",
+                "client_secret_post",
+                "none",
+            ],
+            code_challenge_methods_supported: vec!["S256"],
+            claims_supported: vec![
+                "iss",
+                "sub",
+                "aud",
+                "exp",
+                "iat",
+                "nonce",
+                "name",
+                "given_name",
+                "family_name",
+                "email",
+                "email_verified",
+                "user_type",
+                "user_tags",
+            ],
+        })
+    }
+
+    /// The public keys tokens are signed with.
+    pub fn jwks(config: &AppConfig) -> Result<Jwks, AuthenticationError> {
+        Ok(Jwks {
+            keys: vec![TokenService::new(&config.jwt).signing_key()?.jwk().clone()],
+        })
+    }
+
+    pub fn user_info(user: &User) -> UserInfo {
+        UserInfo::from(user)
+    }
+}
@@ -1,47 +1,831 @@
+use crate::config::app::AppConfig;
 use crate::config::crypto::CryptoService;
+use crate::config::token::TokenService;
+use crate::config::totp::TotpService;
 use crate::handlers::error::AuthenticationError;
-use crate::models::user::{LoginUser, RegisterUser, User};
-use crate::utils::mongo_util::MongoUtil;
-use serde_json::json;
+use crate::models::token::{
+    AuthResponse, LoginOutcome, MfaChallenge, MfaVerifyRequest, RefreshRequest, RefreshToken,
+    SecondFactor, TokenPurpose,
+};
+use crate::models::token::Claims;
+use crate::models::user::{
+    ChangePassword, LoginUser, NewUser, PageCursor, RegisterUser, ResetPassword, SortOrder,
+    UpdateProfile, User, UserChanges, UserFilter, UserListQuery, UserPage,
+};
+use crate::repository::login_attempt_repository::LoginAttemptRepository;
+use crate::repository::refresh_token_repository::RefreshTokenRepository;
+use crate::repository::user_repository::UserRepository;
+use crate::services::lockout_service::{LockoutService, LockoutSubject};
+use crate::services::mail_service::{Email, Mailer};
+use crate::utils::file_util::FileUtil;
+use chrono::{Duration, Utc};
+use mongodb::bson::oid::ObjectId;
+use std::net::IpAddr;
+use std::sync::Arc;
+use validator::{Validate, ValidationError, ValidationErrors};
+
+/// File name prefix of stored avatar images
+pub const AVATAR_PREFIX: &str = "avatar-";
 
 pub struct UserService;
 
 impl UserService {
-    pub async fn register(user: RegisterUser) -> Result<User, AuthenticationError> {
+    pub async fn register(
+        users: &dyn UserRepository,
+        config: &AppConfig,
+        mut user: RegisterUser,
+        mailer: &dyn Mailer,
+    ) -> Result<User, AuthenticationError> {
+        let errors = user.validate().err().unwrap_or_default();
+        let personal_info = [user.first_name.as_str(), &user.last_name, &user.email_id];
+        Self::check_password(config, errors, "password", &user.password, &personal_info).await?;
+
+        if !user.user_type.is_self_assignable() {
+            return Err(AuthenticationError::Forbidden(format!(
+                "Cannot sign up as {:?}",
+                user.user_type
+            )));
+        }
+
         // Check if the user is already present in db
-        match MongoUtil::find_one(json!({"email_id": user.email_id})).await {
-            Ok(data) => Err(AuthenticationError::UserAlreadyExists(data.unwrap())),
-            Err(_) => {
-                // If user does not exist, create new user
-                let new_user = MongoUtil::insert_one(user.clone()).await?.unwrap();
+        match users.find_by_email(&user.email_id).await? {
+            Some(_) => Err(AuthenticationError::UserAlreadyExists(user.email_id)),
+            None => {
+                user.password = CryptoService::new()
+                    .hash_password(user.password)
+                    .await
+                    .map_err(|err| AuthenticationError::LoginError(err.to_string()))?;
+
+                // If user does not exist, create new unverified user
+                let new_user = users
+                    .insert(NewUser {
+                        user: user.clone(),
+                        email_verified: false,
+                        created_at: Utc::now().naive_utc(),
+                    })
+                    .await?;
+
+                // The account is created either way, the link can be resent
+                if let Err(err) = Self::send_verification_email(config, &new_user, mailer).await {
+                    log::error!("Failed to send verification email: {:?}", err);
+                }
                 Ok(new_user)
             }
         }
     }
 
-    pub async fn login(user: LoginUser) -> Result<User, AuthenticationError> {
-        // Check if the user is already present in db
-        let found_user = MongoUtil::find_one(json!({"email_id": user.username}))
-            .await
-            .map_err(|err| AuthenticationError::DbError(err.to_string()))
-            .and_then(|data| Ok(data.unwrap()));
+    pub async fn login(
+        users: &dyn UserRepository,
+        refresh_tokens: &dyn RefreshTokenRepository,
+        attempts: &dyn LoginAttemptRepository,
+        config: &AppConfig,
+        user: LoginUser,
+        client_ip: Option<IpAddr>,
+    ) -> Result<LoginOutcome, AuthenticationError> {
+        user.validate()
+            .map_err(AuthenticationError::ValidationError)?;
+
+        let account = LockoutSubject::Account(&user.username);
+        let mut subjects = vec![account.clone()];
+        subjects.extend(client_ip.map(LockoutSubject::Ip));
+        LockoutService::reserve(attempts, &config.lockout, &subjects).await?;
+
+        // The reserved attempt stays counted unless the password is right
+        let found_user = match Self::verify_credentials(users, config, &user).await? {
+            Some(found_user) => found_user,
+            None => {
+                LockoutService::fail(attempts, &subjects).await?;
+                return Err(AuthenticationError::PasswordMismatch(
+                    "Invalid email or password".to_owned(),
+                ))
+            }
+        };
+        LockoutService::release(attempts, &subjects).await?;
+        if !found_user.email_verified {
+            return Err(AuthenticationError::EmailNotVerified(
+                "Email address has not been verified".to_owned(),
+            ));
+        }
+
+        // Failures are only forgotten once the second factor is passed too,
+        // so that codes cannot be guessed between correct passwords
+        if found_user.totp_enabled {
+            let challenge_token = TokenService::new(&config.jwt).issue_action_token(
+                &found_user,
+                TokenPurpose::MfaChallenge,
+                config.jwt.mfa_challenge_lifetime,
+            )?;
+            return Ok(LoginOutcome::MfaRequired(MfaChallenge {
+                mfa_required: true,
+                challenge_token,
+                expires_in: config.jwt.mfa_challenge_lifetime,
+            }));
+        }
+        LockoutService::reset(attempts, &account).await?;
+
+        // Every sign-in starts a new refresh token family
+        let family_id = ObjectId::new().to_hex();
+        let res = Self::issue_tokens(refresh_tokens, config, found_user, family_id).await?;
+        Ok(LoginOutcome::Authenticated(Box::new(res)))
+    }
+
+    /// Completes a sign-in that returned an `MfaChallenge` with a code from
+    /// the user's authenticator or a recovery code. Wrong codes count as
+    /// failed sign-ins.
+    pub async fn verify_mfa(
+        users: &dyn UserRepository,
+        refresh_tokens: &dyn RefreshTokenRepository,
+        attempts: &dyn LoginAttemptRepository,
+        config: &AppConfig,
+        request: MfaVerifyRequest,
+        client_ip: Option<IpAddr>,
+    ) -> Result<(AuthResponse, SecondFactor), AuthenticationError> {
+        let claims = TokenService::new(&config.jwt)
+            .verify_action_token(&request.challenge_token, TokenPurpose::MfaChallenge)?;
+        let (_, user) = Self::find_token_subject(users, &claims.sub).await?;
+
+        // The challenge is bound to the password it was issued for
+        let current = user.password.as_deref().map(TokenService::fingerprint);
+        if claims.pwd.is_none() || claims.pwd != current {
+            return Err(AuthenticationError::TokenError(
+                "Sign-in challenge is no longer valid".to_owned(),
+            ));
+        }
+        if !user.totp_enabled {
+            return Err(AuthenticationError::MfaConflict(
+                "Two-factor authentication is not enabled".to_owned(),
+            ));
+        }
+
+        let (user, factor) =
+            Self::check_second_factor(users, attempts, config, user, &request.code, client_ip)
+                .await?;
+        let family_id = ObjectId::new().to_hex();
+        let res = Self::issue_tokens(refresh_tokens, config, user, family_id).await?;
+        Ok((res, factor))
+    }
+
+    /// Replaces the recovery codes of a user with two-factor authentication
+    /// enabled, returning the new ones along with the second factor it was
+    /// authorized with.
+    pub async fn regenerate_recovery_codes(
+        users: &dyn UserRepository,
+        attempts: &dyn LoginAttemptRepository,
+        config: &AppConfig,
+        user: User,
+        code: &str,
+        client_ip: Option<IpAddr>,
+    ) -> Result<(User, Vec<String>, SecondFactor), AuthenticationError> {
+        if !user.totp_enabled {
+            return Err(AuthenticationError::MfaConflict(
+                "Two-factor authentication is not enabled".to_owned(),
+            ));
+        }
+        let (user, factor) =
+            Self::check_second_factor(users, attempts, config, user, code, client_ip).await?;
+
+        let (codes, hashes) = Self::generate_recovery_codes(config).await?;
+        let changes = UserChanges {
+            recovery_codes: Some(hashes),
+            updated_at: Some(Utc::now().naive_utc()),
+            ..Default::default()
+        };
+        let user = Self::update(users, user.user_id.as_ref().unwrap(), changes).await?;
+        Ok((user, codes, factor))
+    }
+
+    /// Checks `code` against the authenticator of `user`, or else against
+    /// their recovery codes, using it up either way. Wrong codes count as
+    /// failed sign-ins.
+    async fn check_second_factor(
+        users: &dyn UserRepository,
+        attempts: &dyn LoginAttemptRepository,
+        config: &AppConfig,
+        user: User,
+        code: &str,
+        client_ip: Option<IpAddr>,
+    ) -> Result<(User, SecondFactor), AuthenticationError> {
+        let account = LockoutSubject::Account(&user.email_id);
+        let mut subjects = vec![account.clone()];
+        subjects.extend(client_ip.map(LockoutSubject::Ip));
+        LockoutService::reserve(attempts, &config.lockout, &subjects).await?;
+
+        let step = user.totp_secret.as_deref().and_then(|secret| {
+            TotpService::new(&config.totp).verify(
+                secret,
+                code,
+                Utc::now().timestamp(),
+                user.totp_last_step,
+            )
+        });
+        let factor = match step {
+            Some(_) => Some(SecondFactor::Totp),
+            None => Self::use_recovery_code(users, &user, code).await?,
+        };
+        let factor = match factor {
+            Some(factor) => factor,
+            None => {
+                LockoutService::fail(attempts, &subjects).await?;
+                return Err(AuthenticationError::InvalidMfaCode(
+                    "Invalid authentication code".to_owned(),
+                ))
+            }
+        };
+        LockoutService::release(attempts, &subjects).await?;
+        LockoutService::reset(attempts, &account).await?;
+
+        let changes = UserChanges {
+            totp_last_step: step,
+            ..Default::default()
+        };
+        let user = Self::update(users, user.user_id.as_ref().unwrap(), changes).await?;
+        Ok((user, factor))
+    }
 
-        // Verify passwords
+    /// Spends `code` if it is one of the recovery codes of `user`, `None` if
+    /// it is not, or was already spent by a concurrent sign-in.
+    async fn use_recovery_code(
+        users: &dyn UserRepository,
+        user: &User,
+        code: &str,
+    ) -> Result<Option<SecondFactor>, AuthenticationError> {
+        let code = match TotpService::normalize_recovery_code(code) {
+            Some(code) => code,
+            None => return Ok(None),
+        };
         let verifier = CryptoService::new();
-        match verifier
-            .verify_password(user.password, found_user.clone()?.password.unwrap())
+        for hash in &user.recovery_codes {
+            let matches = verifier
+                .verify_password(code.clone(), hash.clone())
+                .await
+                .map_err(|err| AuthenticationError::LoginError(err.to_string()))?;
+            if matches {
+                let removed = users
+                    .remove_recovery_code(user.user_id.as_ref().unwrap(), hash)
+                    .await?;
+                return Ok(removed.then_some(SecondFactor::RecoveryCode));
+            }
+        }
+        Ok(None)
+    }
+
+    /// New recovery codes, along with the hashes that get persisted.
+    async fn generate_recovery_codes(
+        config: &AppConfig,
+    ) -> Result<(Vec<String>, Vec<String>), AuthenticationError> {
+        let hasher = CryptoService::new();
+        let codes: Vec<String> = (0..config.totp.recovery_codes)
+            .map(|_| TotpService::generate_recovery_code())
+            .collect();
+        let mut hashes = Vec::with_capacity(codes.len());
+        for code in &codes {
+            let hash = hasher
+                .hash_password(code.clone())
+                .await
+                .map_err(|err| AuthenticationError::LoginError(err.to_string()))?;
+            hashes.push(hash);
+        }
+        Ok((codes, hashes))
+    }
+
+    /// Starts two-factor enrolment with a new secret, returning it along with
+    /// its provisioning URI. Enrolment is only complete once a code from the
+    /// authenticator is confirmed.
+    pub async fn enroll_mfa(
+        users: &dyn UserRepository,
+        config: &AppConfig,
+        user: User,
+    ) -> Result<(String, String), AuthenticationError> {
+        if user.totp_enabled {
+            return Err(AuthenticationError::MfaConflict(
+                "Two-factor authentication is already enabled".to_owned(),
+            ));
+        }
+        let secret = TotpService::generate_secret();
+        let changes = UserChanges {
+            totp_secret: Some(secret.clone()),
+            updated_at: Some(Utc::now().naive_utc()),
+            ..Default::default()
+        };
+        Self::update(users, user.user_id.as_ref().unwrap(), changes).await?;
+
+        let uri = TotpService::new(&config.totp).provisioning_uri(&secret, &user.email_id);
+        Ok((secret, uri))
+    }
+
+    /// Enables two-factor authentication once `code` shows the authenticator
+    /// holds the secret from `enroll_mfa`, returning the user's recovery
+    /// codes. They are only ever shown here.
+    pub async fn confirm_mfa(
+        users: &dyn UserRepository,
+        config: &AppConfig,
+        user: User,
+        code: &str,
+    ) -> Result<(User, Vec<String>), AuthenticationError> {
+        let secret = match (&user.totp_secret, user.totp_enabled) {
+            (_, true) => {
+                return Err(AuthenticationError::MfaConflict(
+                    "Two-factor authentication is already enabled".to_owned(),
+                ))
+            }
+            (Some(secret), false) => secret,
+            (None, false) => {
+                return Err(AuthenticationError::MfaConflict(
+                    "Two-factor enrolment has not been started".to_owned(),
+                ))
+            }
+        };
+        let step = TotpService::new(&config.totp)
+            .verify(secret, code, Utc::now().timestamp(), None)
+            .ok_or_else(|| {
+                AuthenticationError::InvalidMfaCode("Invalid authentication code".to_owned())
+            })?;
+
+        let (codes, hashes) = Self::generate_recovery_codes(config).await?;
+        let changes = UserChanges {
+            totp_enabled: Some(true),
+            totp_last_step: Some(step),
+            recovery_codes: Some(hashes),
+            updated_at: Some(Utc::now().naive_utc()),
+            ..Default::default()
+        };
+        let user = Self::update(users, user.user_id.as_ref().unwrap(), changes).await?;
+        Ok((user, codes))
+    }
+
+    /// The user signing in, if the password is theirs.
+    async fn verify_credentials(
+        users: &dyn UserRepository,
+        config: &AppConfig,
+        user: &LoginUser,
+    ) -> Result<Option<User>, AuthenticationError> {
+        // Longer passwords are never accepted, so there is no need to hash them
+        if user.password.chars().count() > config.password.max_length {
+            return Ok(None);
+        }
+        let found_user = users.find_by_email(&user.username).await?;
+
+        // Without an account or password the same work is done, so that
+        // the time taken does not tell whether the account exists
+        let verifier = CryptoService::new();
+        let password_hash = match found_user.as_ref().and_then(|found| found.password.clone()) {
+            Some(password_hash) => password_hash,
+            None => verifier.dummy_hash().to_owned(),
+        };
+        let is_verified = verifier
+            .verify_password(user.password.clone(), password_hash)
+            .await
+            .map_err(|e| AuthenticationError::LoginError(e.to_string()))?;
+        Ok(found_user.filter(|found| is_verified && found.password.is_some()))
+    }
+
+    pub async fn send_verification_email(
+        config: &AppConfig,
+        user: &User,
+        mailer: &dyn Mailer,
+    ) -> Result<(), AuthenticationError> {
+        let tokens = TokenService::new(&config.jwt);
+        let token = tokens.issue_action_token(
+            user,
+            TokenPurpose::VerifyEmail,
+            tokens.config.verification_token_lifetime,
+        )?;
+
+        mailer
+            .send(Email {
+                to: user.email_id.clone(),
+                subject: "Verify your email address".to_owned(),
+                body: format!(
+                    "Hi {},\n\nConfirm your email address by opening the link below:\n\n{}?token={}\n",
+                    user.first_name, config.mail.verification_url, token
+                ),
+            })
+            .await
+    }
+
+    /// Sends a new verification link if `email_id` belongs to an unverified
+    /// account. Succeeds either way so callers cannot probe for accounts.
+    pub async fn resend_verification(
+        users: &dyn UserRepository,
+        config: &AppConfig,
+        email_id: &str,
+        mailer: &dyn Mailer,
+    ) -> Result<(), AuthenticationError> {
+        if let Some(user) = users.find_by_email(email_id).await? {
+            if !user.email_verified {
+                Self::send_verification_email(config, &user, mailer).await?;
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn verify_email(
+        users: &dyn UserRepository,
+        config: &AppConfig,
+        token: &str,
+    ) -> Result<User, AuthenticationError> {
+        let claims = TokenService::new(&config.jwt).verify_action_token(token, TokenPurpose::VerifyEmail)?;
+        let (user_id, user) = Self::find_token_subject(users, &claims.sub).await?;
+
+        // Verification links stop working once they have been used
+        if user.email_verified {
+            return Err(AuthenticationError::TokenError(
+                "Verification link has already been used".to_owned(),
+            ));
+        }
+
+        let changes = UserChanges {
+            email_verified: Some(true),
+            updated_at: Some(Utc::now().naive_utc()),
+            ..Default::default()
+        };
+        Self::update(users, &user_id, changes).await
+    }
+
+    /// Emails a password reset link if `email_id` belongs to an account.
+    /// Succeeds either way so callers cannot probe for accounts, and signs
+    /// and sends the link in the background so that known addresses take no
+    /// longer to answer than unknown ones.
+    pub async fn forgot_password(
+        users: &dyn UserRepository,
+        config: &AppConfig,
+        email_id: &str,
+        mailer: Arc<dyn Mailer>,
+    ) -> Result<(), AuthenticationError> {
+        let user = match users.find_by_email(email_id).await? {
+            Some(user) => user,
+            None => return Ok(()),
+        };
+
+        let config = config.clone();
+        rocket::tokio::spawn(async move {
+            if let Err(err) = Self::send_reset_email(&config, &user, mailer.as_ref()).await {
+                log::error!("Failed to send password reset email: {:?}", err);
+            }
+        });
+        Ok(())
+    }
+
+    async fn send_reset_email(
+        config: &AppConfig,
+        user: &User,
+        mailer: &dyn Mailer,
+    ) -> Result<(), AuthenticationError> {
+        let tokens = TokenService::new(&config.jwt);
+        let token = tokens.issue_action_token(
+            user,
+            TokenPurpose::ResetPassword,
+            tokens.config.reset_token_lifetime,
+        )?;
+
+        mailer
+            .send(Email {
+                to: user.email_id.clone(),
+                subject: "Reset your password".to_owned(),
+                body: format!(
+                    "Hi {},\n\nSomeone asked to reset the password for your account. If that was you, open the link below to choose a new password:\n\n{}?token={}\n\nThe link expires in {} minutes. If you did not ask for this, you can ignore this email.\n",
+                    user.first_name,
+                    config.mail.reset_url,
+                    token,
+                    tokens.config.reset_token_lifetime / 60
+                ),
+            })
+            .await
+    }
+
+    pub async fn reset_password(
+        users: &dyn UserRepository,
+        refresh_tokens: &dyn RefreshTokenRepository,
+        config: &AppConfig,
+        request: ResetPassword,
+    ) -> Result<User, AuthenticationError> {
+        let claims = TokenService::new(&config.jwt)
+            .verify_action_token(&request.token, TokenPurpose::ResetPassword)?;
+        let (user_id, user) = Self::find_token_subject(users, &claims.sub).await?;
+        Self::check_new_password(config, &user, &request.new_password).await?;
+
+        // The token is bound to the old password, so it is single-use
+        let current = user.password.as_deref().map(TokenService::fingerprint);
+        if claims.pwd.is_none() || claims.pwd != current {
+            return Err(AuthenticationError::TokenError(
+                "Reset link has already been used".to_owned(),
+            ));
+        }
+
+        let password_hash = CryptoService::new()
+            .hash_password(request.new_password)
             .await
-        {
-            Ok(is_verified) => {
-                return if is_verified {
-                    Ok(found_user?)
-                } else {
-                    Err(AuthenticationError::PasswordMismatch(
-                        "Password Does Not Match".to_owned(),
-                    ))
+            .map_err(|err| AuthenticationError::LoginError(err.to_string()))?;
+        let changes = UserChanges {
+            password: Some(password_hash),
+            token_version: Some(user.token_version + 1),
+            updated_at: Some(Utc::now().naive_utc()),
+            ..Default::default()
+        };
+        let updated = Self::update(users, &user_id, changes).await?;
+
+        // Sign the user out everywhere
+        refresh_tokens.revoke_for_user(&user_id, None).await?;
+        Ok(updated)
+    }
+
+    /// Changes the password of a signed-in user. When other sessions are
+    /// revoked a fresh access token for the current session is returned.
+    pub async fn change_password(
+        users: &dyn UserRepository,
+        refresh_tokens: &dyn RefreshTokenRepository,
+        config: &AppConfig,
+        user: User,
+        claims: Claims,
+        request: ChangePassword,
+    ) -> Result<(User, Option<String>), AuthenticationError> {
+        // The policy is only checked for whoever knows the current password
+        let mismatch = || {
+            AuthenticationError::PasswordMismatch("Current Password Does Not Match".to_owned())
+        };
+        let current_hash = user.password.clone().ok_or_else(mismatch)?;
+        let crypto = CryptoService::new();
+        let is_verified = crypto
+            .verify_password(request.current_password, current_hash)
+            .await
+            .map_err(|e| AuthenticationError::LoginError(e.to_string()))?;
+        if !is_verified {
+            return Err(mismatch());
+        }
+        Self::check_new_password(config, &user, &request.new_password).await?;
+
+        let password_hash = crypto
+            .hash_password(request.new_password)
+            .await
+            .map_err(|err| AuthenticationError::LoginError(err.to_string()))?;
+        let revoke_other_sessions = request.revoke_other_sessions.unwrap_or(false);
+        let user_id = user.user_id.clone().unwrap();
+        let changes = UserChanges {
+            password: Some(password_hash),
+            token_version: if revoke_other_sessions {
+                Some(user.token_version + 1)
+            } else {
+                None
+            },
+            updated_at: Some(Utc::now().naive_utc()),
+            ..Default::default()
+        };
+        let updated = Self::update(users, &user_id, changes).await?;
+
+        if !revoke_other_sessions {
+            return Ok((updated, None));
+        }
+        refresh_tokens
+            .revoke_for_user(&user_id, Some(&claims.sid))
+            .await?;
+        let access_token = TokenService::new(&config.jwt).issue_access_token(&updated, &claims.sid)?;
+        Ok((updated, Some(access_token)))
+    }
+
+    pub async fn update_profile(
+        users: &dyn UserRepository,
+        user: User,
+        profile: UpdateProfile,
+    ) -> Result<User, AuthenticationError> {
+        profile
+            .validate()
+            .map_err(AuthenticationError::ValidationError)?;
+
+        let changes = UserChanges {
+            updated_at: Some(Utc::now().naive_utc()),
+            ..UserChanges::from(profile)
+        };
+        Self::update(users, user.user_id.as_ref().unwrap(), changes).await
+    }
+
+    /// Start of the names avatars of the user are stored under.
+    pub fn avatar_prefix(user_id: &ObjectId) -> String {
+        format!("{}{}-", AVATAR_PREFIX, user_id.to_hex())
+    }
+
+    /// Points `User.image` at a newly stored avatar and removes the file of
+    /// the previous one, if it was an avatar of the same user.
+    pub async fn set_avatar(
+        users: &dyn UserRepository,
+        config: &AppConfig,
+        user: User,
+        image_url: String,
+    ) -> Result<User, AuthenticationError> {
+        let changes = UserChanges {
+            image: Some(image_url),
+            updated_at: Some(Utc::now().naive_utc()),
+            ..Default::default()
+        };
+        let user_id = user.user_id.as_ref().unwrap();
+        let updated = Self::update(users, user_id, changes).await?;
+
+        if let Some(previous) = user.image {
+            let prefix = Self::avatar_prefix(user_id);
+            if let Some(path) = FileUtil::stored_avatar_path(config, &previous, &prefix) {
+                if let Err(err) = async_std::fs::remove_file(&path).await {
+                    log::warn!("Failed to remove previous avatar {}: {:?}", path, err);
                 }
             }
-            Err(e) => Err(AuthenticationError::LoginError(e.to_string())),
         }
+        Ok(updated)
+    }
+
+    pub async fn list_users(
+        users: &dyn UserRepository,
+        query: UserListQuery,
+    ) -> Result<UserPage, AuthenticationError> {
+        let after = match &query.cursor {
+            Some(cursor) => Some(
+                PageCursor::decode(cursor)
+                    .ok_or_else(|| AuthenticationError::InvalidQuery("Invalid cursor".to_owned()))?,
+            ),
+            None => None,
+        };
+        let filter = UserFilter {
+            user_type: query.user_type,
+            user_tags: query.user_tags,
+            verified: query.verified,
+            text: query.q.filter(|text| !text.is_empty()),
+            after,
+            order: query.order.unwrap_or(SortOrder::Descending),
+            // Fetch one extra record to know whether there is a next page
+            limit: query.limit + 1,
+        };
+
+        let mut page = users.list(&filter).await?;
+        let next_cursor = if page.len() as i64 > query.limit {
+            page.truncate(query.limit as usize);
+            page.last().map(|last| PageCursor::from(last).encode())
+        } else {
+            None
+        };
+        Ok(UserPage {
+            users: page,
+            next_cursor,
+        })
+    }
+
+    /// Lifts the sign-in lock of a user's account, returning `false` if it
+    /// had no failed sign-ins counted.
+    pub async fn unlock(
+        users: &dyn UserRepository,
+        attempts: &dyn LoginAttemptRepository,
+        user_id: &str,
+    ) -> Result<bool, AuthenticationError> {
+        let user_id = ObjectId::with_string(user_id)
+            .map_err(|_| AuthenticationError::InvalidQuery("Invalid user id".to_owned()))?;
+        let user = users
+            .find_by_id(&user_id)
+            .await?
+            .ok_or_else(|| AuthenticationError::UserNotFound(user_id.to_hex()))?;
+        LockoutService::reset(attempts, &LockoutSubject::Account(&user.email_id)).await
+    }
+
+    pub async fn refresh(
+        users: &dyn UserRepository,
+        refresh_tokens: &dyn RefreshTokenRepository,
+        config: &AppConfig,
+        request: RefreshRequest,
+    ) -> Result<AuthResponse, AuthenticationError> {
+        let token_hash = TokenService::hash_refresh_token(&request.refresh_token);
+        let stored = refresh_tokens
+            .find_by_hash(&token_hash)
+            .await?
+            .ok_or_else(|| {
+                AuthenticationError::InvalidRefreshToken("Unknown refresh token".to_owned())
+            })?;
+
+        // A refresh token is only ever valid once. Seeing it again means it
+        // leaked, so the whole family is revoked.
+        if stored.used_at.is_some() || stored.revoked {
+            refresh_tokens.revoke_family(&stored.family_id).await?;
+            return Err(AuthenticationError::RefreshTokenReused(
+                "Refresh token has already been used".to_owned(),
+            ));
+        }
+        if stored.expires_at <= Utc::now().naive_utc() {
+            return Err(AuthenticationError::InvalidRefreshToken(
+                "Refresh token has expired".to_owned(),
+            ));
+        }
+
+        let token_id = stored.token_id.unwrap();
+        if !refresh_tokens.consume(&token_id).await? {
+            refresh_tokens.revoke_family(&stored.family_id).await?;
+            return Err(AuthenticationError::RefreshTokenReused(
+                "Refresh token has already been used".to_owned(),
+            ));
+        }
+
+        let user = users.find_by_id(&stored.user_id).await?.ok_or_else(|| {
+            AuthenticationError::InvalidRefreshToken("User no longer exists".to_owned())
+        })?;
+        Self::issue_tokens(refresh_tokens, config, user, stored.family_id).await
+    }
+
+    /// Issues an access token and a new refresh token belonging to `family_id`.
+    async fn issue_tokens(
+        refresh_tokens: &dyn RefreshTokenRepository,
+        config: &AppConfig,
+        user: User,
+        family_id: String,
+    ) -> Result<AuthResponse, AuthenticationError> {
+        let tokens = TokenService::new(&config.jwt);
+        let access_token = tokens.issue_access_token(&user, &family_id)?;
+
+        let (refresh_token, token_hash) = tokens.generate_refresh_token();
+        let now = Utc::now().naive_utc();
+        refresh_tokens
+            .insert(RefreshToken {
+                token_id: None,
+                user_id: user.user_id.clone().unwrap(),
+                family_id,
+                token_hash,
+                expires_at: now + Duration::seconds(tokens.config.refresh_token_lifetime),
+                used_at: None,
+                revoked: false,
+                created_at: now,
+            })
+            .await?;
+
+        Ok(AuthResponse {
+            user,
+            access_token,
+            refresh_token,
+            token_type: "Bearer".to_owned(),
+            expires_in: tokens.config.access_token_lifetime,
+        })
+    }
+
+    /// Checks the new password of an existing user against the policy.
+    async fn check_new_password(
+        config: &AppConfig,
+        user: &User,
+        password: &str,
+    ) -> Result<(), AuthenticationError> {
+        let personal_info = [user.first_name.as_str(), &user.last_name, &user.email_id];
+        Self::check_password(
+            config,
+            ValidationErrors::new(),
+            "new_password",
+            password,
+            &personal_info,
+        )
+        .await
+    }
+
+    /// Adds every password policy rule `password` breaks to `errors` under
+    /// `field`, failing if there are any errors. Passwords found in the
+    /// breached password list break the policy as well.
+    async fn check_password(
+        config: &AppConfig,
+        mut errors: ValidationErrors,
+        field: &'static str,
+        password: &str,
+        personal_info: &[&str],
+    ) -> Result<(), AuthenticationError> {
+        for error in config.password.check(password, personal_info) {
+            errors.add(field, error);
+        }
+        let breached = config
+            .password
+            .is_breached(password)
+            .await
+            .map_err(|err| AuthenticationError::DbError(format!("Breached password list: {}", err)))?;
+        if breached {
+            let mut error = ValidationError::new("password_breached");
+            error.message = Some("has appeared in a data breach, choose another password".into());
+            errors.add(field, error);
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(AuthenticationError::ValidationError(errors))
+        }
+    }
+
+    /// Loads the user a token was issued for.
+    async fn find_token_subject(
+        users: &dyn UserRepository,
+        subject: &str,
+    ) -> Result<(ObjectId, User), AuthenticationError> {
+        let user_id = ObjectId::with_string(subject)
+            .map_err(|err| AuthenticationError::TokenError(err.to_string()))?;
+        let user = users
+            .find_by_id(&user_id)
+            .await?
+            .ok_or_else(|| AuthenticationError::UserNotFound(subject.to_owned()))?;
+        Ok((user_id, user))
+    }
+
+    async fn update(
+        users: &dyn UserRepository,
+        user_id: &ObjectId,
+        changes: UserChanges,
+    ) -> Result<User, AuthenticationError> {
+        users
+            .update(user_id, changes)
+            .await?
+            .ok_or_else(|| AuthenticationError::UserNotFound(user_id.to_hex()))
     }
 }
@@ -1,14 +1,82 @@
-use rocket::config::Config;
+use crate::config::app::AppConfig;
+use mongodb::bson::oid::ObjectId;
+use serde::Deserialize;
 
-pub const STORAGE_DIRECTORY: &str = "./uploads";
+#[derive(Debug, Clone, Deserialize)]
+pub struct StorageConfig {
+    /// Directory uploaded files are stored in, each user's in a subdirectory
+    /// of their own
+    pub directory: String,
+    /// Largest accepted upload, in bytes
+    pub max_upload_size: u64,
+    /// Largest accepted avatar image, in bytes
+    pub max_avatar_size: u64,
+}
+
+/// Subdirectory of `StorageConfig.directory` avatars are kept in, apart from
+/// the files users name themselves.
+pub const AVATAR_DIRECTORY: &str = "avatars";
 
 pub struct FileUtil;
 
 impl FileUtil {
-    pub async fn get_basefile_path() -> String {
-        let config = Config::figment();
-        let address = config.extract_inner::<String>("address").unwrap();
-        let port = config.extract_inner::<i64>("port").unwrap();
-        format!("http://{}:{}/files", address, port)
+    pub fn get_basefile_path(config: &AppConfig) -> String {
+        format!("{}/files", config.public_base_url)
+    }
+
+    pub fn get_avatar_base_path(config: &AppConfig) -> String {
+        format!("{}/{}", Self::get_basefile_path(config), AVATAR_DIRECTORY)
+    }
+
+    /// Path of the stored file named `name`.
+    pub fn storage_path(config: &AppConfig, name: &str) -> String {
+        format!("{}/{}", config.storage.directory, name)
+    }
+
+    pub fn avatar_directory(config: &AppConfig) -> String {
+        Self::storage_path(config, AVATAR_DIRECTORY)
+    }
+
+    /// Directory the files uploaded by the user `owner` are stored in.
+    pub fn user_directory(config: &AppConfig, owner: &ObjectId) -> String {
+        Self::storage_path(config, &owner.to_hex())
+    }
+
+    /// Path of the file named `name` uploaded by the user `owner`, if that is
+    /// a plain file name.
+    pub fn user_file_path(config: &AppConfig, owner: &ObjectId, name: &str) -> Option<String> {
+        if !Self::is_valid_file_name(name) {
+            return None;
+        }
+        Some(format!("{}/{}", Self::user_directory(config, owner), name))
+    }
+
+    /// Whether `name` can be stored as given: a single path segment that is
+    /// neither hidden nor the avatar directory.
+    pub fn is_valid_file_name(name: &str) -> bool {
+        !name.is_empty()
+            && !name.starts_with('.')
+            && !name.contains("..")
+            && !name.contains(['/', '\\', '\0'])
+            && name != AVATAR_DIRECTORY
+    }
+
+    /// Path of the stored avatar named `name`, if that is a plain file name.
+    pub fn avatar_path(config: &AppConfig, name: &str) -> Option<String> {
+        if !Self::is_valid_file_name(name) {
+            return None;
+        }
+        Some(format!("{}/{}", Self::avatar_directory(config), name))
+    }
+
+    /// Maps an avatar url handed out by this service back to the stored
+    /// file, if it points to one whose name starts with `prefix`.
+    pub fn stored_avatar_path(config: &AppConfig, url: &str, prefix: &str) -> Option<String> {
+        let base_path = format!("{}/", Self::get_avatar_base_path(config));
+        let name = url.strip_prefix(&base_path)?;
+        if !name.starts_with(prefix) {
+            return None;
+        }
+        Self::avatar_path(config, name)
     }
 }
@@ -1,123 +1,87 @@
-use crate::models::user::*;
-use color_eyre::Result as EyreResult;
-use eyre::eyre;
-use futures::stream::TryStreamExt;
-use mongodb::{
-    bson,
-    bson::oid::ObjectId,
-    bson::{doc, Document},
-    error::Error,
-    options::ClientOptions,
-    results::DeleteResult,
-    Client, Collection,
-};
-use serde_json::{json, Value};
-use dotenv::dotenv;
-
-pub const DATABASE_NAME: &str = "users";
-pub const APP_NAME: &str = "authentication-service";
+use mongodb::{bson::doc, error::Error, options::ClientOptions, Client};
+use serde::Deserialize;
+use std::time::Duration;
+
+pub const USER_COLLECTION: &str = "users";
+pub const REFRESH_TOKEN_COLLECTION: &str = "refresh_tokens";
+pub const LOGIN_ATTEMPT_COLLECTION: &str = "login_attempts";
+pub const AUDIT_COLLECTION: &str = "audit_events";
+pub const OAUTH_CLIENT_COLLECTION: &str = "oauth_clients";
+pub const AUTHORIZATION_CODE_COLLECTION: &str = "oauth_codes";
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MongoConfig {
+    pub uri: String,
+    pub database: String,
+    /// Name the service reports to the server, shown in its logs
+    pub app_name: String,
+    pub max_pool_size: u32,
+    pub min_pool_size: u32,
+    /// Seconds to wait for a connection to be established
+    pub connect_timeout: u64,
+    /// Seconds to wait for a suitable server before an operation fails
+    pub server_selection_timeout: u64,
+    /// Seconds an idle pooled connection is kept open
+    pub max_idle_time: u64,
+}
 
 pub struct MongoUtil;
 
 impl MongoUtil {
-    pub async fn mongo_client() -> Result<Client, Error> {
+    /// Creates the client shared by the whole service and checks that the
+    /// server can be reached.
+    pub async fn mongo_client(config: &MongoConfig) -> Result<Client, Error> {
         // Parse a connection string into an options struct.
-        dotenv().ok();
-        let mongo_uri: String = std::env::var("MONGO_URI")
-            .expect("Mongo Uri not set")
-            .into();
-        let mut client_options = ClientOptions::parse(&mongo_uri).await?;
-
-        // Manually set an option
-        client_options.app_name = Some("tasking-app".to_string());
-
-        // Get a handle to the deployment.
+        let mut client_options = ClientOptions::parse(&config.uri).await?;
+        client_options.app_name = Some(config.app_name.clone());
+        client_options.max_pool_size = Some(config.max_pool_size);
+        client_options.min_pool_size = Some(config.min_pool_size);
+        client_options.connect_timeout = Some(Duration::from_secs(config.connect_timeout));
+        client_options.server_selection_timeout =
+            Some(Duration::from_secs(config.server_selection_timeout));
+        client_options.max_idle_time = Some(Duration::from_secs(config.max_idle_time));
+
+        // Get a handle to the deployment, the driver only connects lazily
         let client = Client::with_options(client_options)?;
-        println!("Connected to mongodb");
+        client
+            .database(&config.database)
+            .run_command(doc! { "ping": 1 }, None)
+            .await?;
+        log::info!("Connected to mongodb");
 
         Ok(client)
     }
+}
 
-    pub async fn mongo_collection(name: &str) -> Result<Collection, Error> {
-        let client = MongoUtil::mongo_client().await.unwrap();
-        let db = client.database(APP_NAME);
-        let collection = db.collection(name);
-        Ok(collection)
-    }
-
-    pub async fn insert_one(data: RegisterUser) -> Result<Option<User>, Error> {
-        let db = MongoUtil::mongo_collection(DATABASE_NAME).await?;
-        let insertable = bson::to_document(&data).unwrap();
-        let bson_res = db.insert_one(insertable, None).await.unwrap();
-        let res: ObjectId = bson::from_bson(bson_res.inserted_id).unwrap();
-
-        let created_obj = Self::find_one(json!({ "_id": res }))
-            .await
-            .expect("Could not find document in db");
-        Ok(created_obj)
-    }
-
-    pub async fn find_one(filter: Value) -> EyreResult<Option<User>> {
-        let db = MongoUtil::mongo_collection(DATABASE_NAME).await?;
-        let insertable = bson::to_document(&filter).unwrap();
-        let doc_res = db.find_one(insertable, None).await?;
-        match doc_res {
-            Some(document) => {
-                let res: User =
-                    bson::from_bson(bson::Bson::Document(document)).expect("could not decode");
-                println!("user: {:?}", res.clone());
-                // let res: User = json!(doc);
-                println!("Find: {:#?}", res.clone());
-                Ok(Some(res))
-            }
-            None => {
-                let message = "Could not find document";
-                return Err(eyre!(message));
-            }
-        }
-    }
-
-    pub async fn find_all() -> Result<Vec<Document>, Error> {
-        let db = MongoUtil::mongo_collection(DATABASE_NAME).await?;
-        let db_res = db.find(None, None).await.unwrap();
-        let results: Vec<Document> = db_res.try_collect().await.unwrap();
-        Ok(results)
+/// Stores a `NaiveDateTime` as a BSON date instead of the string serde makes
+/// of it, so that TTL indexes can expire documents by it. Strings stored
+/// before are still read.
+pub mod bson_datetime {
+    use chrono::{NaiveDateTime, TimeZone, Utc};
+    use mongodb::bson::serde_helpers::chrono_datetime_as_bson_datetime;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Stored {
+        #[serde(with = "chrono_datetime_as_bson_datetime")]
+        Date(chrono::DateTime<Utc>),
+        Text(NaiveDateTime),
     }
 
-    pub async fn update_one(id: ObjectId, new_data: RegisterUser) -> Result<Option<User>, Error> {
-        println!("new_data: {:#?}", &new_data);
-        let db = MongoUtil::mongo_collection(DATABASE_NAME).await?;
-        let filter_json = json!({ "_id": id.clone() });
-        let insertable_filter = bson::to_document(&filter_json).unwrap();
-        println!("Updatable Task: :{:#?}", &new_data);
-        let insertable_task = bson::to_document(&new_data).unwrap();
-        println!("insertable_filter: {:#?}", insertable_filter.clone());
-
-        let doc_res = db
-            .update_one(insertable_filter, doc! { "$set": insertable_task }, None)
-            .await?;
-
-        println!("Updated {} document", doc_res.modified_count);
-
-        let updated_obj = Self::find_one(json!({ "_id": id }))
-            .await
-            .expect("Could not find document in db");
-
-        Ok(updated_obj)
+    pub fn serialize<S: Serializer>(value: &NaiveDateTime, serializer: S) -> Result<S::Ok, S::Error> {
+        chrono_datetime_as_bson_datetime::serialize(&Utc.from_utc_datetime(value), serializer)
     }
 
-    pub async fn delete_one(id: ObjectId) -> Result<DeleteResult, Error> {
-        let db = MongoUtil::mongo_collection(DATABASE_NAME).await?;
-        let filter_json = json!({ "_id": id });
-        let insertable_filter = bson::to_document(&filter_json).unwrap();
-        println!("insertable_filter: {:#?}", insertable_filter.clone());
-        db.delete_one(insertable_filter, None).await
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<NaiveDateTime, D::Error> {
+        Ok(match Stored::deserialize(deserializer)? {
+            Stored::Date(date) => date.naive_utc(),
+            Stored::Text(text) => text,
+        })
     }
 
-    pub async fn delete_one_by_filter(filter: Value) -> Result<DeleteResult, Error> {
-        let db = MongoUtil::mongo_collection(DATABASE_NAME).await?;
-        let insertable_filter = bson::to_document(&filter).unwrap();
-        println!("insertable_filter: {:#?}", insertable_filter.clone());
-        db.delete_one(insertable_filter, None).await
+    /// `value` as stored, for use in queries and updates.
+    pub fn to_bson(value: &NaiveDateTime) -> mongodb::bson::Bson {
+        mongodb::bson::Bson::DateTime(Utc.from_utc_datetime(value))
     }
 }
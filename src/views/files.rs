@@ -0,0 +1,12 @@
+use super::Page;
+use rocket::http::Status;
+
+/// Form for trying out uploads to `/files` from a browser.
+pub fn upload_page() -> Page {
+    let body = r#"    <form method="post" enctype="multipart/form-data">
+      <label for="somefile">File</label>
+      <input type="file" id="somefile" name="somefile"/>
+      <button type="submit">Upload</button>
+    </form>"#;
+    Page::new(Status::Ok, "Upload a file", body)
+}
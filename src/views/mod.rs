@@ -0,0 +1,91 @@
+//! Server-rendered HTML pages.
+
+pub mod files;
+pub mod oauth;
+
+use rocket::http::{ContentType, Header, Status};
+use rocket::request::Request;
+use rocket::response::{self, Responder, Response};
+use std::io::Cursor;
+
+/// An HTML page. Pages may not be framed by other sites, which keeps forms
+/// on them from being clickjacked, and are never cached.
+pub struct Page {
+    pub status: Status,
+    pub html: String,
+}
+
+impl Page {
+    pub fn new(status: Status, title: &str, body: &str) -> Self {
+        Self {
+            status,
+            html: layout(title, body),
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for Page {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        Response::build()
+            .status(self.status)
+            .header(ContentType::HTML)
+            .header(Header::new("X-Frame-Options", "DENY"))
+            .header(Header::new("Content-Security-Policy", "frame-ancestors 'none'"))
+            .header(Header::new("Cache-Control", "no-store"))
+            .sized_body(self.html.len(), Cursor::new(self.html))
+            .ok()
+    }
+}
+
+/// `text` with the characters that are special in HTML escaped, safe to use
+/// in element content and quoted attribute values.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#x27;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// A hidden form input carrying `value`.
+pub fn hidden_input(name: &str, value: &str) -> String {
+    format!(
+        r#"<input type="hidden" name="{}" value="{}"/>"#,
+        escape(name),
+        escape(value)
+    )
+}
+
+fn layout(title: &str, body: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+  <head>
+    <meta charset="utf-8"/>
+    <meta name="viewport" content="width=device-width, initial-scale=1"/>
+    <title>{title}</title>
+    <style>
+      body {{ font-family: sans-serif; max-width: 28rem; margin: 3rem auto; padding: 0 1rem; }}
+      label, input, button {{ display: block; width: 100%; box-sizing: border-box; }}
+      input {{ margin: 0.25rem 0 1rem; padding: 0.5rem; }}
+      button {{ margin-top: 0.5rem; padding: 0.5rem; }}
+      .error {{ color: #b00020; }}
+    </style>
+  </head>
+  <body>
+    <h1>{title}</h1>
+{body}
+  </body>
+</html>
+"#,
+        title = escape(title),
+        body = body
+    )
+}
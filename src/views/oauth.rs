@@ -0,0 +1,78 @@
+use super::{escape, hidden_input, Page};
+use crate::models::oauth::{AuthorizeRequest, Scope};
+use rocket::http::Status;
+
+/// What the login and consent page shows.
+pub struct AuthorizeView<'a> {
+    pub client_name: &'a str,
+    pub scopes: &'a [Scope],
+    /// Email of the signed-in user, who only has to consent
+    pub signed_in_as: Option<&'a str>,
+    pub request: &'a AuthorizeRequest,
+    pub csrf_token: &'a str,
+    pub error: Option<&'a str>,
+}
+
+/// Asks the user to sign in, unless they already are, and to let the client
+/// act on their behalf. The form posts back to `/oauth/authorize`.
+pub fn authorize_page(status: Status, view: AuthorizeView<'_>) -> Page {
+    let mut body = String::new();
+    if let Some(error) = view.error {
+        body.push_str(&format!("    <p class=\"error\">{}</p>\n", escape(error)));
+    }
+    body.push_str(&format!(
+        "    <p><strong>{}</strong> would like to:</p>\n    <ul>\n",
+        escape(view.client_name)
+    ));
+    for scope in view.scopes {
+        body.push_str(&format!("      <li>{}</li>\n", escape(scope.description())));
+    }
+    body.push_str("    </ul>\n    <form method=\"post\" action=\"/oauth/authorize\">\n");
+
+    let request = view.request;
+    let parameters = [
+        ("response_type", &request.response_type),
+        ("client_id", &request.client_id),
+        ("redirect_uri", &request.redirect_uri),
+        ("scope", &request.scope),
+        ("state", &request.state),
+        ("code_challenge", &request.code_challenge),
+        ("code_challenge_method", &request.code_challenge_method),
+        ("nonce", &request.nonce),
+    ];
+    for (name, value) in parameters.iter() {
+        if let Some(value) = value {
+            body.push_str(&format!("      {}\n", hidden_input(&format!("request.{}", name), value)));
+        }
+    }
+    body.push_str(&format!("      {}\n", hidden_input("csrf_token", view.csrf_token)));
+
+    match view.signed_in_as {
+        Some(email_id) => body.push_str(&format!(
+            "      <p>Signed in as {}</p>\n",
+            escape(email_id)
+        )),
+        None => body.push_str(
+            r#"      <label for="username">Email</label>
+      <input type="email" id="username" name="username" autocomplete="username" required/>
+      <label for="password">Password</label>
+      <input type="password" id="password" name="password" autocomplete="current-password" required/>
+      <label for="otp">Authentication code, if enabled</label>
+      <input type="text" id="otp" name="otp" autocomplete="one-time-code"/>
+"#,
+        ),
+    }
+    body.push_str(
+        r#"      <button type="submit" name="decision" value="approve">Allow</button>
+      <button type="submit" name="decision" value="deny" formnovalidate>Deny</button>
+    </form>"#,
+    );
+    Page::new(status, "Authorize access", &body)
+}
+
+/// Shown instead of redirecting when the client or its redirect URI cannot
+/// be trusted.
+pub fn error_page(status: Status, message: &str) -> Page {
+    let body = format!("    <p class=\"error\">{}</p>", escape(message));
+    Page::new(status, "Authorization failed", &body)
+}